@@ -1,6 +1,10 @@
 // Entry point of the kernel. The kernel starts off at a low address (0x80200000),
 // setting up the entry page table and then jumps to a high address space (0xFFFFFFC080000000).
 // Then, after setting the boot stack, it enters the "main" function.
+//
+// Secondary harts are started by the boot hart at `_secondary_entry` (see `smp`), with
+// `a0` holding the hart id and `a1` the top of its stack. They share the entry page table.
+// Every hart keeps its id in `tp`.
 core::arch::global_asm! {r#"
     .section .text.entry
     .global _entry
@@ -24,12 +28,36 @@ core::arch::global_asm! {r#"
 
     relocated:
         la sp, bootstack_top
+        mv tp, a0
         j main
 
+    .globl _secondary_entry
+    _secondary_entry:
+        la t0, entry_pgtable
+        srli t0, t0, 12
+        li t1, 0x8 << 60
+        or t0, t0, t1
+
+        sfence.vma zero, zero
+        csrw satp, t0
+        sfence.vma zero, zero
+
+        ld t0, _secondary_relocated
+        jr t0
+
+    secondary_relocated:
+        mv sp, a1
+        mv tp, a0
+        j secondary_main
+
     .globl _relocated
     _relocated:
         .8byte relocated
 
+    .globl _secondary_relocated
+    _secondary_relocated:
+        .8byte secondary_relocated
+
     .section .data
     .align 12
     .globl bootstack
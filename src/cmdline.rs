@@ -0,0 +1,195 @@
+//! Kernel Command Line
+//!
+//! QEMU hands the string given by `-append` to the kernel through the `bootargs`
+//! property of the device tree. It is a whitespace separated list of tokens:
+//!
+//! - `key=value` sets a registered option (see [`PARAMS`]). Options with an
+//!   unknown key or a malformed value are ignored with a warning;
+//! - the first other token is the name of the init program, which stops option
+//!   parsing. It and all following tokens are passed through unchanged;
+//! - `--` stops option parsing as well, and is dropped.
+//!
+//! ## Examples
+//! ```
+//! // qemu ... -append "loglevel=debug tick=100 args-many a=b c"
+//! let cmdline = cmdline::get();
+//! assert_eq!(cmdline.loglevel, LogLevel::Debug);
+//! assert_eq!(cmdline.tick, 100);
+//! assert_eq!(cmdline.command(), "args-many a=b c");
+//! ```
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::str::FromStr;
+
+use crate::mem::replace;
+use crate::sbi::timer::{CLOCK_PRE_SEC, TICKS_PER_SEC};
+use crate::smp::MAX_HARTS;
+use crate::sync::OnceCell;
+
+/// Verbosity of kernel messages, from the least to the most verbose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl FromStr for LogLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Self::Error),
+            "warn" => Ok(Self::Warn),
+            "info" => Ok(Self::Info),
+            "debug" => Ok(Self::Debug),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Parsed kernel command line.
+pub struct Cmdline {
+    /// Verbosity of kernel messages printed by [`klog`](crate::klog). Set by
+    /// `loglevel=<error|warn|info|debug>`.
+    pub loglevel: LogLevel,
+    /// The scheduler requested by `sched=<fcfs|priority>`.
+    pub sched: Option<&'static str>,
+    /// The page replacement policy. Set by `replace=<clock|aging>`.
+    pub replace: &'static str,
+    /// Pages of user memory. Set by `userpool=<pages>`, see [`UserPool`](crate::mem::palloc::UserPool).
+    pub userpool: Option<usize>,
+    /// Name of the swap file on the disk. Set by `swap=<file>`.
+    pub swap: &'static str,
+    /// The program to run first. Set by `init=<file>`.
+    pub init: Option<&'static str>,
+    /// Timer interrupts per second. Set by `tick=<hz>`.
+    pub tick: usize,
+    /// The maximum number of harts to bring up. Set by `smp=<n>`.
+    pub smp: usize,
+    /// Tokens after the options, in the order they appear.
+    args: Vec<&'static str>,
+}
+
+/// Parses `value` and stores it into the corresponding field.
+/// Returns `None` if the value is malformed.
+type Setter = fn(&mut Cmdline, &'static str) -> Option<()>;
+
+/// All registered options.
+static PARAMS: [(&str, Setter); 8] = [
+    ("loglevel", |cmdline, value| {
+        cmdline.loglevel = value.parse().ok()?;
+        Some(())
+    }),
+    ("sched", |cmdline, value| {
+        matches!(value, "fcfs" | "priority").then(|| cmdline.sched = Some(value))
+    }),
+    ("replace", |cmdline, value| {
+        replace::NAMES
+            .contains(&value)
+            .then(|| cmdline.replace = value)
+    }),
+    ("userpool", |cmdline, value| {
+        let pages = value.parse().ok()?;
+        (pages > 0).then(|| cmdline.userpool = Some(pages))
+    }),
+    ("swap", |cmdline, value| {
+        (!value.is_empty()).then(|| cmdline.swap = value)
+    }),
+    ("init", |cmdline, value| {
+        (!value.is_empty()).then(|| cmdline.init = Some(value))
+    }),
+    ("tick", |cmdline, value| {
+        let tick = value.parse().ok()?;
+        (1..=CLOCK_PRE_SEC)
+            .contains(&tick)
+            .then(|| cmdline.tick = tick)
+    }),
+    ("smp", |cmdline, value| {
+        let smp = value.parse().ok()?;
+        (1..=MAX_HARTS).contains(&smp).then(|| cmdline.smp = smp)
+    }),
+];
+
+impl Default for Cmdline {
+    fn default() -> Self {
+        Self {
+            loglevel: if cfg!(feature = "debug") {
+                LogLevel::Debug
+            } else {
+                LogLevel::Info
+            },
+            sched: None,
+            replace: "clock",
+            userpool: None,
+            swap: ".glbswap",
+            init: None,
+            tick: TICKS_PER_SEC,
+            smp: MAX_HARTS,
+            args: Vec::new(),
+        }
+    }
+}
+
+impl Cmdline {
+    pub fn parse(bootargs: &'static str) -> Self {
+        let mut cmdline = Self::default();
+        let mut tokens = bootargs.split_whitespace();
+
+        while let Some(token) = tokens.next() {
+            if token == "--" {
+                cmdline.args.extend(tokens);
+                break;
+            }
+
+            let Some((key, value)) = token.split_once('=') else {
+                cmdline.args.push(token);
+                cmdline.args.extend(tokens);
+                break;
+            };
+
+            match PARAMS.iter().find(|(name, _)| *name == key) {
+                Some((_, set)) => {
+                    if set(&mut cmdline, value).is_none() {
+                        kprintln!("[CMDLINE] Bad value for option {:?}, ignored", token);
+                    }
+                }
+                None => kprintln!("[CMDLINE] Unknown option {:?}, ignored", token),
+            }
+        }
+
+        cmdline
+    }
+
+    /// Tokens after the options.
+    pub fn args(&self) -> &[&'static str] {
+        &self.args
+    }
+
+    /// The command line of the init program, i.e. `init` (if any) followed by
+    /// all tokens after the options, separated by a single space.
+    pub fn command(&self) -> String {
+        let command: Vec<_> = self.init.iter().chain(self.args.iter()).copied().collect();
+        command.join(" ")
+    }
+}
+
+static CMDLINE: OnceCell<Cmdline> = OnceCell::new();
+
+/// Parses the command line. Requires the kernel heap to be ready.
+pub fn init(bootargs: &'static str) {
+    CMDLINE.init(|| Cmdline::parse(bootargs));
+}
+
+/// Gets the parsed command line.
+pub fn get() -> &'static Cmdline {
+    CMDLINE.get()
+}
+
+/// Whether messages of `level` are printed by [`klog`](crate::klog). All of them are
+/// until the command line is parsed.
+pub fn enabled(level: LogLevel) -> bool {
+    !CMDLINE.is_initialized() || level <= CMDLINE.get().loglevel
+}
@@ -51,16 +51,17 @@ impl DeviceTree {
     /// Depends on the structure of device tree blob.
     ///
     /// # Return
-    /// `(usize, usize, &str)`: (pm_start, pm_len, bootargs).
+    /// `(usize, usize, *const c_char)`: (pm_start, pm_len, bootargs). `bootargs` is
+    /// a physical address, and is null if the property doesn't exist.
     ///
     /// TODO: rewrite this fn.
-    pub unsafe fn traverse(&self) -> (usize, usize, *const i8) {
+    pub unsafe fn traverse(&self) -> (usize, usize, *const ffi::c_char) {
         #[cfg(feature = "debug")]
         kprintln!("[DTB] Start device enum...");
         let dt_struct_base = self.base.add(self.header.off_dt_struct as usize) as *const u32;
 
         let mut off = 0;
-        let mut ret = (0, 0, 0 as *const i8);
+        let mut ret = (0, 0, core::ptr::null());
         let mut _debug_prefix_num = 0;
         loop {
             let ptr = dt_struct_base.add(off);
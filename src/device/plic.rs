@@ -4,31 +4,33 @@
 //! external interrupt or interrupt handler at any time when external interrupt is on.
 //! Therefore, it is recommended to only call these functions in kernel initialization or interrupt handler.
 //!
+//! Each hart has its own S-mode context. "This hart" below refers to the hart that
+//! executes the function.
+//!
 //! For more information, see <https://github.com/riscv/riscv-plic-spec/blob/master/riscv-plic.adoc>.
 //!
 
 use crate::mem::PLIC_BASE;
-use crate::sync::OnceCell;
+use crate::smp;
 
 /// Hard-coded Virtio device 0 interrupt identifier (ID).
 pub const VIRTIO0_ID: usize = 1;
 
-// Hart ID.
-static HART_ID: OnceCell<usize> = OnceCell::new();
-
-/// Initialization.
-pub fn init(hart_id: usize) {
-    HART_ID.init(|| hart_id);
+/// Initialization of this hart's context. Device interrupts are only routed to
+/// the boot hart.
+pub fn init() {
     unsafe {
         // Set this hart's S-mode priority threshold.
         write_threshold(0);
 
-        // Set interrupt priority of Virtio device 0.
-        // 0 means no interrupt. Any positive value is OK.
-        write_priority(VIRTIO0_ID, 1);
+        if hart_id() == smp::boot_hart() {
+            // Set interrupt priority of Virtio device 0.
+            // 0 means no interrupt. Any positive value is OK.
+            write_priority(VIRTIO0_ID, 1);
 
-        // Enable this hart to receive interrupts from Virtio device 0.
-        set_enable(VIRTIO0_ID);
+            // Enable this hart to receive interrupts from Virtio device 0.
+            set_enable(VIRTIO0_ID);
+        }
     }
 }
 
@@ -89,7 +91,7 @@ pub unsafe fn write_completion(val: u32) {
 
 // Get hart ID.
 fn hart_id() -> usize {
-    *HART_ID.get()
+    smp::hart_id()
 }
 
 // Address calculation helper functions.
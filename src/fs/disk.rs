@@ -21,7 +21,7 @@ use self::inode::Inode;
 use super::{File, FileSys, Vnode};
 use crate::device::virtio::{Virtio, SECTOR_SIZE};
 use crate::mem::PG_SIZE;
-use crate::sync::{Lazy, Mutex};
+use crate::sync::{Lazy, Mutex, RwLock};
 use crate::{OsError, Result};
 
 /// Inode number.
@@ -51,9 +51,9 @@ const ROOT_DIR_SECTOR_LEN: u32 = 8;
 /// let new_sector = freemap.alloc(1);
 /// ```
 ///
-/// - **get root dir:**
+/// - **get root dir:** lookups share it, while updates need exclusive access.
 /// ```ignore
-/// let rootdir = DISKFS.root_dir.lock();
+/// let rootdir = DISKFS.root_dir.read();
 /// // Do sth.
 /// let if_exist = rootdir.exists("/myfile".into());
 /// ```
@@ -81,8 +81,8 @@ pub struct DiskFs {
     #[allow(unused)]
     device: &'static Mutex<Virtio>,
     pub(self) free_map: Mutex<FreeMap>,
-    pub root_dir: Mutex<RootDir>,
-    inode_table: Mutex<BTreeMap<Inum, Weak<Inode>>>,
+    pub root_dir: RwLock<RootDir>,
+    inode_table: RwLock<BTreeMap<Inum, Weak<Inode>>>,
 }
 
 impl FileSys for DiskFs {
@@ -91,7 +91,7 @@ impl FileSys for DiskFs {
 
     fn mount(device: Self::Device) -> Result<Self> {
         let capacity = device.lock().capacity();
-        let inode_table = Mutex::new(BTreeMap::new());
+        let inode_table = RwLock::new(BTreeMap::new());
         let free_map = Mutex::new({
             let size = capacity as u32;
             if let Ok(loaded) = FreeMap::load(size) {
@@ -100,7 +100,7 @@ impl FileSys for DiskFs {
                 FreeMap::new_format(size)?
             }
         });
-        let root_dir = Mutex::new({
+        let root_dir = RwLock::new({
             let vnode = if let Ok(loaded) = Inode::open(ROOT_DIR_SECTOR) {
                 loaded
             } else {
@@ -121,7 +121,7 @@ impl FileSys for DiskFs {
             };
 
             let weak = Arc::downgrade(&vnode);
-            inode_table.lock().insert(ROOT_DIR_SECTOR, weak);
+            inode_table.write().insert(ROOT_DIR_SECTOR, weak);
             RootDir(File::new(vnode))
         });
         Ok(Self {
@@ -137,10 +137,10 @@ impl FileSys for DiskFs {
     }
 
     fn create(&self, id: Self::Path) -> Result<super::File> {
-        let vnode = if self.root_dir.lock().exists(&id) {
-            let inum = self.root_dir.lock().path2inum(&id).unwrap();
+        let vnode = if self.root_dir.read().exists(&id) {
+            let inum = self.root_dir.read().path2inum(&id).unwrap();
             let vnode =
-                if let Some(arc) = self.inode_table.lock().get(&inum).and_then(Weak::upgrade) {
+                if let Some(arc) = self.inode_table.read().get(&inum).and_then(Weak::upgrade) {
                     arc
                 } else {
                     Inode::open(inum)?
@@ -156,9 +156,9 @@ impl FileSys for DiskFs {
 
             let vnode = Inode::create(sector, start, 0)?;
             let weak = Arc::downgrade(&vnode);
-            self.inode_table.lock().insert(sector, weak);
+            self.inode_table.write().insert(sector, weak);
 
-            self.root_dir.lock().insert(&id, sector)?;
+            self.root_dir.write().insert(&id, sector)?;
             vnode
         };
 
@@ -166,18 +166,18 @@ impl FileSys for DiskFs {
     }
 
     fn open(&self, id: Self::Path) -> Result<super::File> {
-        if !self.root_dir.lock().exists(&id) {
+        if !self.root_dir.read().exists(&id) {
             return Err(OsError::NoSuchFile);
         }
         // Expect existing.
-        let inum = self.root_dir.lock().path2inum(&id).unwrap();
-        if let Some(arc) = self.inode_table.lock().get(&inum).and_then(Weak::upgrade) {
+        let inum = self.root_dir.read().path2inum(&id).unwrap();
+        if let Some(arc) = self.inode_table.read().get(&inum).and_then(Weak::upgrade) {
             return Ok(File::new(arc));
         }
 
         let vnode = Inode::open(inum)?;
         let weak = Arc::downgrade(&vnode);
-        self.inode_table.lock().insert(inum, weak);
+        self.inode_table.write().insert(inum, weak);
 
         Ok(File::new(vnode))
     }
@@ -185,8 +185,8 @@ impl FileSys for DiskFs {
     fn close(&self, _file: super::File) {}
 
     fn remove(&self, id: Self::Path) -> Result<()> {
-        let inum = self.root_dir.lock().path2inum(&id)?;
-        if let Some(arc) = self.inode_table.lock().get(&inum).and_then(Weak::upgrade) {
+        let inum = self.root_dir.read().path2inum(&id)?;
+        if let Some(arc) = self.inode_table.read().get(&inum).and_then(Weak::upgrade) {
             arc.remove();
             return Ok(());
         }
@@ -210,7 +210,7 @@ impl DiskFs {
         let vnode = Inode::create(sector, start, cnt as usize)?;
         let weak = Arc::downgrade(&vnode);
 
-        self.inode_table.lock().insert(sector, weak);
+        self.inode_table.write().insert(sector, weak);
         Ok(File::new(vnode))
     }
 
@@ -222,7 +222,7 @@ impl DiskFs {
     pub fn free_location(&self, location: usize) {
         if let Some(arc) = self
             .inode_table
-            .lock()
+            .read()
             .get(&(location as u32))
             .and_then(Weak::upgrade)
         {
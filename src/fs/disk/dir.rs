@@ -21,11 +21,14 @@ pub struct RootDir(pub(super) File);
 impl RootDir {
     /// Convert a path to inumber. This will iteratively search through the
     /// root dir entries, return the first entry that with the same name of given one.
-    pub fn path2inum(&mut self, path: &Path) -> Result<Inum> {
-        self.0.rewind()?;
-        while let Ok(entry) = self.0.read_into::<DirEntry>() {
+    ///
+    /// Reads through a file of its own, so that lookups can share the root dir.
+    pub fn path2inum(&self, path: &Path) -> Result<Inum> {
+        let mut dir = self.0.clone();
+        dir.rewind()?;
+        while let Ok(entry) = dir.read_into::<DirEntry>() {
             let name = unsafe {
-                core::ffi::CStr::from_ptr(&entry.name as *const u8 as *const i8)
+                core::ffi::CStr::from_ptr(&entry.name as *const u8 as *const core::ffi::c_char)
                     .to_str()
                     .or(Err(OsError::CstrFormatErr))?
             };
@@ -43,7 +46,7 @@ impl RootDir {
     ///
     /// # See
     /// [`path2inum()`].
-    pub fn exists(&mut self, path: &Path) -> bool {
+    pub fn exists(&self, path: &Path) -> bool {
         self.path2inum(path).is_ok()
     }
 
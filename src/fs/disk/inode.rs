@@ -328,7 +328,7 @@ impl Vnode for Inode {
         }
         if desc.removed {
             // Remove the inode from the disk.
-            let mut rootdir = DISKFS.root_dir.lock();
+            let mut rootdir = DISKFS.root_dir.write();
             rootdir
                 .remove(desc.sector)
                 .expect("Failed to remove from root dir");
@@ -1,8 +1,10 @@
 //! Swap file.
 //!
-// Swap may not be used.
-#![allow(dead_code)]
+//! Written frames of the user pool are evicted there, one page per slot, see
+//! [`FrameTable`](crate::mem::FrameTable).
+
 use super::DISKFS;
+use crate::cmdline;
 use crate::fs::{File, FileSys};
 use crate::io::Seek;
 use crate::mem::PG_SIZE;
@@ -11,10 +13,11 @@ use crate::sync::{Lazy, Mutex, MutexGuard, Primitive};
 pub struct Swap;
 
 static SWAPFILE: Lazy<Mutex<File>> = Lazy::new(|| {
+    let name = cmdline::get().swap;
     Mutex::new(
         DISKFS
-            .open(".glbswap".into())
-            .expect("swap file \".glbswap\" should exist"),
+            .open(name.into())
+            .unwrap_or_else(|_| panic!("swap file {:?} should exist", name)),
     )
 });
 
@@ -28,7 +31,6 @@ impl Swap {
         Self::len() / PG_SIZE
     }
 
-    /// TODO: Design high-level interfaces, or do in lab3?
     pub fn lock() -> MutexGuard<'static, File, Primitive> {
         SWAPFILE.lock()
     }
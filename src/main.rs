@@ -9,12 +9,14 @@ extern crate riscv;
 #[macro_use]
 pub mod sbi;
 pub mod boot;
+pub mod cmdline;
 pub mod device;
 pub mod error;
 pub mod fs;
 pub mod io;
 pub mod mem;
 pub mod pq;
+pub mod smp;
 pub mod sync;
 pub mod thread;
 pub mod trap;
@@ -58,7 +60,7 @@ fn read_line() -> Option<String> {
 /// Note: `extern "C"` ensures this function adhere to the C calling convention.
 /// (ref: https://doc.rust-lang.org/nomicon/ffi.html?highlight=calling%20convention#rust-side)
 #[no_mangle]
-pub extern "C" fn main(hart_id: usize, dtb: usize) -> ! {
+pub extern "C" fn main(_hart_id: usize, dtb: usize) -> ! {
     kprintln!("Hello, World!");
 
     // Flush BSS since they are not loaded and the corresponding memory may be random
@@ -71,26 +73,40 @@ pub extern "C" fn main(hart_id: usize, dtb: usize) -> ! {
     assert_eq!(pm_base, mem::PM_BASE, "Error constant mem::PM_BASE.");
 
     // Get the boot arguments.
-    let _bootargs: &'static str = unsafe {
-        ffi::CStr::from_ptr(bootargs.add(mem::VM_OFFSET))
-            .to_str()
-            .expect("Bad bootarg.")
+    let bootargs: &'static str = match bootargs.is_null() {
+        true => "",
+        false => unsafe {
+            ffi::CStr::from_ptr(bootargs.add(mem::VM_OFFSET))
+                .to_str()
+                .expect("Bad bootarg.")
+        },
     };
 
     let ram_base = ekernel as usize;
     let ram_tail = dtb + mem::VM_OFFSET; // Current we do not reuse dtb area.
 
     #[cfg(feature = "debug")]
-    {
-        kprintln!("RAM: 0x{:x} - 0x{:x}", ram_base, ram_tail);
-        kprintln!("BOOTARGS: {:?}", _bootargs);
-    }
+    kprintln!("RAM: 0x{:x} - 0x{:x}", ram_base, ram_tail);
 
     unsafe {
         mem::Palloc::init(ram_base, ram_tail);
         mem::KernelPgTable::init(pm_len);
     }
 
+    // Options are parsed after the heap is ready.
+    cmdline::init(bootargs);
+    klog!(Debug, "BOOTARGS: {:?}", bootargs);
+    if let Some(sched) = cmdline::get().sched {
+        if sched != thread::scheduler::NAME {
+            klog!(
+                Warn,
+                "[CMDLINE] Scheduler {:?} is not built in, using {:?}",
+                sched,
+                thread::scheduler::NAME
+            );
+        }
+    }
+
     trap::set_strap_entry();
 
     unsafe {
@@ -98,20 +114,26 @@ pub extern "C" fn main(hart_id: usize, dtb: usize) -> ! {
         register::sstatus::set_sum();
     };
 
-    device::plic::init(hart_id);
+    smp::set_online();
+    device::plic::init();
     #[cfg(feature = "debug")]
     kprintln!("Virtio inited.");
 
     // Init timer & external interrupt
     sbi::interrupt::init();
 
+    smp::boot_secondary();
+
     #[cfg(feature = "test")]
     {
-        use alloc::sync::Arc;
-        let sema = Arc::new(sync::Semaphore::new(0));
-        let sema2 = sema.clone();
-        thread::spawn("test", move || crate::test::main(sema2, _bootargs));
-        sema.down();
+        let command = cmdline::get().command();
+        let test = thread::spawn("test", move || crate::test::main(&command));
+        if test.join().is_err() {
+            sbi::reset(
+                sbi::system_reset::Type::Shutdown,
+                sbi::system_reset::Reason::SystemFailure,
+            );
+        }
     }
 
     #[cfg(feature = "shell")]
@@ -131,6 +153,16 @@ pub extern "C" fn main(hart_id: usize, dtb: usize) -> ! {
 
     DISKFS.unmount();
 
+    let (faults, evictions, scans) = mem::replace::STATS.get();
+    klog!(
+        Info,
+        "[PAGE] Policy {}: {} faults, {} evictions, {} frames scanned",
+        cmdline::get().replace,
+        faults,
+        evictions,
+        scans
+    );
+
     kprintln!("Goodbye, World!");
 
     sbi::reset(
@@ -139,15 +171,39 @@ pub extern "C" fn main(hart_id: usize, dtb: usize) -> ! {
     )
 }
 
+/// Initializes a secondary hart, and then runs as its idle thread.
+///
+/// See [`smp`] for how a secondary hart gets here.
+#[no_mangle]
+pub extern "C" fn secondary_main(_hart_id: usize) -> ! {
+    mem::KernelPgTable::get().activate();
+    trap::set_strap_entry();
+
+    unsafe {
+        register::sstatus::set_sie();
+        register::sstatus::set_sum();
+    };
+
+    device::plic::init();
+    sbi::interrupt::init();
+
+    smp::set_online();
+
+    thread::manager::idle()
+}
+
 /* ---------------------------------- PANIC --------------------------------- */
 #[panic_handler]
 unsafe fn panic(info: &core::panic::PanicInfo) -> ! {
     // Disable interrupts until shutting down the whole system
-    sbi::interrupt::set(false);
+    let intr = sbi::interrupt::set(false);
 
     // Report the reason for invoking `panic`
     kprintln!("{}", info);
 
+    // Only the panicking thread exits, if possible.
+    thread::exit_on_panic(intr);
+
     sbi::reset(
         sbi::system_reset::Type::Shutdown,
         sbi::system_reset::Reason::SystemFailure,
@@ -10,8 +10,10 @@
 pub mod frametable;
 pub mod layout;
 pub mod malloc;
+pub mod oom;
 pub mod pagetable;
 pub mod palloc;
+pub mod replace;
 pub mod userbuf;
 mod utils;
 
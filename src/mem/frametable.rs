@@ -1,216 +1,751 @@
-use core::{
-    ptr,
-    slice::{from_raw_parts, from_raw_parts_mut},
-};
+//! User Frame Table
+//!
+//! Every frame of the user pool has an entry recording the pages mapping it, so
+//! that it can be taken away from them by [`FrameTable::evict`].
+//!
+//! A user page table entry mapping a frame is only changed with the table locked,
+//! which makes the entry and the mappers of the frame agree. Callers lock their
+//! page table first, and eviction, which runs with the table locked, reaches the
+//! entries through the mappers without locking any page table.
+//!
+//! A written frame is evicted to a slot of the swap file, see [`SwapTable`]. Its
+//! page is then recorded in the supplemental page table, and its entry is left in
+//! place without the valid bit, keeping the flags it's mapped with. The page is
+//! read back by [`FrameTable::swap_in`] on the next access. Disk I/O sleeps, so
+//! it's done with no lock held, while the page is marked as moving.
+
+use core::slice::{from_raw_parts, from_raw_parts_mut};
 
-use alloc::{collections::BTreeMap, vec::Vec};
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
 
 use crate::{
-    fs::disk::DISKFS,
-    io::{Read, Write},
+    cmdline,
+    fs::disk::Swap,
+    io::{Read, Seek, SeekFrom, Write},
     mem::{
-        in_kernel_space,
-        palloc::{self, UserPool},
+        in_kernel_space, oom,
+        palloc::UserPool,
+        replace::{self, ReplacementPolicy, STATS},
         PG_SIZE,
     },
+    smp::tlb::Shootdown,
     sync::{Intr, Lazy, Mutex},
-    thread::Manager,
+    thread, OsError, Result,
 };
 
-use super::{PTEFlags, PhysAddr};
+use super::{Entry, PTEFlags, PageTable, PhysAddr};
 
-#[derive(Default, Clone, Copy)]
-pub struct FrameInfo {
-    /// thread that refers to this frame
-    thread: isize,
-    /// virtual address referred to by the user thread
+/// A page mapping a frame.
+#[derive(Clone)]
+struct Mapper {
+    pid: isize,
+    /// User address of the page
     v_addr: usize,
-    /// if dirty and evicted, then resides in a swap file?
-    swap: bool,
+    /// The page table entry of the page. A page table is freed by
+    /// [`PageTable::destroy`] only after its mappers are dropped, so the entry
+    /// stays in place while the mapper is recorded.
+    entry: *const Entry,
+}
+
+impl Mapper {
+    fn entry(&self) -> &Entry {
+        unsafe { &*self.entry }
+    }
+}
+
+/// A frame of the user pool.
+#[derive(Default, Clone)]
+pub struct FrameInfo {
+    /// Pages mapping this frame
+    rmap: Vec<Mapper>,
+    /// The number of references to this frame. Besides mappings, a frame may be
+    /// referred to while not mapped, e.g. when it's parked by `mprotect`.
+    refs: usize,
+    /// The executable page this frame caches, if it's shared, see [`FrameTable::share`]
+    shared: Option<(usize, usize)>,
+    /// Set from allocation until the frame is mapped, see [`FrameTable::map`]
+    pinned: bool,
     /// whether this frame is in use
     active: bool,
 }
 
-pub struct SupplementInfo {
-    swap: bool,
-    location: usize,
+impl FrameInfo {
+    /// Whether the content can be written to swap for the page mapping it, i.e. it's
+    /// mapped by a single page and not shared.
+    fn swappable(&self) -> bool {
+        self.shared.is_none() && self.rmap.len() == 1
+    }
+
+    /// Removes page `v_addr` of process `pid` from the mappers.
+    fn unmapped_by(&mut self, pid: isize, v_addr: usize) {
+        if let Some(pos) = self
+            .rmap
+            .iter()
+            .position(|m| m.pid == pid && m.v_addr == v_addr)
+        {
+            self.rmap.swap_remove(pos);
+        }
+    }
+}
+
+/// Where a page is, while it's not in a frame. Pages clean when evicted are not
+/// recorded, since they are zero-filled again.
+enum Supplement {
+    /// Being written to swap or read back
+    Moving,
+    /// Unmapped while moving. It's dropped by whoever moves it.
+    Dropped,
+    /// In a slot of swap
+    Swap(usize),
+}
+
+/// A frame taken away by [`FrameTable::evict`].
+enum Victim {
+    /// Its content is dropped, so it can be used right away.
+    Clean(usize),
+    /// Its content is to be written to swap `slot` for page `page` first.
+    Dirty {
+        frame: usize,
+        page: (isize, usize),
+        slot: usize,
+    },
 }
 
 pub struct TableInner {
     frames: Vec<FrameInfo>,
-    clock_hand: usize,
+    /// Chunks of the user pool, see [`UserPool::chunks`]
+    chunks: Vec<(usize, usize)>,
+    /// Chooses which frame to evict
+    policy: Box<dyn ReplacementPolicy>,
+    /// Frames holding read-only pages of executables, by (inode, user address)
+    shared: BTreeMap<(usize, usize), usize>,
+    /// The supplemental page table, by (pid, user address)
+    supplement: BTreeMap<(isize, usize), Supplement>,
 }
 
 pub struct FrameTable(Lazy<Mutex<TableInner, Intr>>);
 
-/// Supplemental table. Indexed by the thread id and the virtual address.
-pub struct SupplementTable(Lazy<Mutex<BTreeMap<(usize, usize), SupplementInfo>, Intr>>);
+/// Slots of the swap file, each holding a page. Whether a slot is taken is only
+/// recorded here, since nothing in swap outlives a boot.
+struct SwapTable(Lazy<Mutex<Vec<bool>, Intr>>);
 
-struct SwapInner {
-    location: usize,
+unsafe impl Sync for FrameTable {}
+unsafe impl Sync for SwapTable {}
+
+/// The frames of the user pool, as seen by a [`ReplacementPolicy`]. Accessed bits
+/// it clears are shot down by the caller.
+pub struct Frames<'a> {
+    frames: &'a [FrameInfo],
+    shootdown: &'a mut Shootdown,
+    /// Whether a slot of swap is free
+    swap: bool,
 }
 
-impl From<usize> for SwapInner {
-    fn from(value: usize) -> Self {
-        Self { location: value }
+impl Frames<'_> {
+    /// The number of frames, which are indexed from 0.
+    pub fn count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Whether frame `index` can be evicted, i.e. it's in use, mapped, and only
+    /// referred to by the pages mapping it. The content of a dirty frame is written
+    /// to swap, so it must be mapped by a single page that is not shared, and swap
+    /// must not be full.
+    pub fn evictable(&self, index: usize) -> bool {
+        let info = &self.frames[index];
+        info.active
+            && !info.pinned
+            && info.rmap.len() == info.refs
+            && (!self.dirty(index) || self.swap && info.swappable())
+    }
+
+    /// Whether any page mapping frame `index` has been accessed. The accessed bits
+    /// are cleared, so that the next call tells whether it's accessed since.
+    pub fn referenced(&mut self, index: usize) -> bool {
+        let mut accessed = false;
+        for mapper in self.frames[index].rmap.iter() {
+            accessed |= mapper
+                .entry()
+                .fetch_clear(PTEFlags::A)
+                .contains(PTEFlags::A);
+            self.shootdown.add(mapper.v_addr, PG_SIZE);
+        }
+        accessed
+    }
+
+    /// Whether any page mapping frame `index` has been written.
+    pub fn dirty(&self, index: usize) -> bool {
+        self.frames[index]
+            .rmap
+            .iter()
+            .any(|mapper| mapper.entry().is_dirty())
     }
 }
 
-pub struct SwapTable(Lazy<Mutex<BTreeMap<(usize, usize), SwapInner>, Intr>>);
+impl TableInner {
+    /// Index of frame `ptr` in the table. Frames are numbered through the chunks of
+    /// the user pool in turn.
+    fn index(&self, ptr: usize) -> usize {
+        assert!(ptr % PG_SIZE == 0);
+        let mut base = 0;
+        for &(start, pages) in self.chunks.iter() {
+            if (start..start + pages * PG_SIZE).contains(&ptr) {
+                return base + (ptr - start) / PG_SIZE;
+            }
+            base += pages;
+        }
+        panic!("{:#x} is not in the user pool", ptr);
+    }
 
-unsafe impl Sync for FrameTable {}
-unsafe impl Sync for SupplementTable {}
-unsafe impl Sync for SwapTable {}
+    /// Kernel virtual address of frame `index`.
+    fn frame(&self, mut index: usize) -> usize {
+        for &(start, pages) in self.chunks.iter() {
+            if index < pages {
+                return start + index * PG_SIZE;
+            }
+            index -= pages;
+        }
+        panic!("frame {} is not in the user pool", index);
+    }
+
+    /// Drops a reference to frame `index`, and frees it with the last one.
+    fn put(&mut self, index: usize) {
+        let info = &mut self.frames[index];
+        assert!(info.active && info.refs > 0);
+
+        info.refs -= 1;
+        if info.refs > 0 {
+            return;
+        }
+
+        if let Some(key) = info.shared.take() {
+            self.shared.remove(&key);
+        }
+        info.rmap.clear();
+        info.pinned = false;
+        info.active = false;
+        unsafe { UserPool::dealloc_pages(self.frame(index) as *mut u8, 1) };
+    }
+
+    /// Unmaps `entry`, which maps page `v_addr` of process `pid`, and drops the
+    /// reference of the page. `false` if it's not mapped, e.g. it has been evicted,
+    /// in which case it's dropped from swap.
+    fn unmap(&mut self, entry: &Entry, pid: isize, v_addr: usize) -> bool {
+        if !entry.fetch_clear(PTEFlags::V).contains(PTEFlags::V) {
+            self.unswap(pid, v_addr);
+            return false;
+        }
+        let index = self.index(entry.pa().into_va());
+        self.frames[index].unmapped_by(pid, v_addr);
+        self.put(index);
+        true
+    }
+
+    /// Drops page `v_addr` of process `pid` from swap, if it's there. A moving page
+    /// is dropped once it's moved.
+    fn unswap(&mut self, pid: isize, v_addr: usize) {
+        let key = (pid, v_addr);
+        match self.supplement.get_mut(&key) {
+            Some(Supplement::Swap(slot)) => {
+                SwapTable::free(*slot);
+                self.supplement.remove(&key);
+            }
+            Some(state @ Supplement::Moving) => *state = Supplement::Dropped,
+            _ => {}
+        }
+    }
+
+    /// Records that `page` has been written to swap `slot`, unless it has been
+    /// dropped in the meantime.
+    fn swapped(&mut self, page: (isize, usize), slot: usize) {
+        match self.supplement.get_mut(&page) {
+            Some(state @ Supplement::Moving) => *state = Supplement::Swap(slot),
+            _ => {
+                self.supplement.remove(&page);
+                SwapTable::free(slot);
+            }
+        }
+    }
+}
 
 impl FrameTable {
-    /// Allocates a userpage. This function will not map v_addr to result address. v_addr is only provided for frame info.
-    pub unsafe fn alloc_page(thread: isize, v_addr: usize, swap: bool, flags: PTEFlags) -> usize {
-        assert!(v_addr % PG_SIZE == 0);
+    /// Allocates a userpage, which is not zeroed. It's pinned, i.e. it can't be
+    /// evicted, until it's mapped by [`FrameTable::map`].
+    ///
+    /// A dirty frame is written to swap before it's taken, which sleeps, so the
+    /// caller must not hold any lock.
+    ///
+    /// ## Return
+    /// - `Err(OutOfMemory)`: If the user pool is exhausted, and no frame can be
+    ///   evicted, e.g. all frames are dirty and swap is full. A process is killed by
+    ///   [`oom::kill`] to make room, so the caller may try again later.
+    pub fn alloc_page() -> Result<usize> {
+        // The swap file is opened on the first call, which sleeps as well.
+        SwapTable::instance();
+
         let mut table = Self::instance().lock();
-        let result = if let Some(addr) = UserPool::alloc_pages(1) {
-            addr
-        } else {
-            // memory exhausted. try to evict an existing page
-            let ptr = Self::evict() as *mut u8;
-            // zero out
-            ptr::write_bytes(ptr, 0, PG_SIZE);
-            ptr
-        } as usize;
+        let result = match unsafe { UserPool::alloc_pages(1) } {
+            Some(addr) => addr as usize,
+            None => match Self::evict(&mut table) {
+                Some(Victim::Clean(frame)) => frame,
+                Some(Victim::Dirty { frame, page, slot }) => {
+                    drop(table);
+                    SwapTable::store_page(frame, slot);
+                    table = Self::instance().lock();
+                    table.swapped(page, slot);
+                    frame
+                }
+                None => {
+                    let resident = Self::resident(&table);
+                    drop(table);
+                    oom::kill(resident);
+                    return Err(OsError::OutOfMemory);
+                }
+            },
+        };
 
         // setup frame table
-        let lowest = PhysAddr::from(UserPool::lowest()).ppn();
-        kprintln!(
-            "lowest: {:#x}, result: {:#x}",
-            lowest,
-            PhysAddr::from(result).ppn()
-        );
-        let index = PhysAddr::from(result).ppn() - lowest;
-        assert!(table.frames[index].active == false);
+        let index = table.index(result);
+        assert!(!table.frames[index].active);
         table.frames[index] = FrameInfo {
-            thread,
-            v_addr,
-            swap,
+            rmap: Vec::new(),
+            refs: 1,
+            shared: None,
+            pinned: true,
             active: true,
         };
+        table.policy.on_alloc(index);
 
         assert!(in_kernel_space(result));
-        result
+        Ok(result)
     }
 
-    pub unsafe fn dealloc_page(ptr: usize) {
-        assert!(ptr % PG_SIZE == 0);
-        let lowest = PhysAddr::from_pa(UserPool::lowest()).ppn();
-        let index = PhysAddr::from(ptr).ppn() - lowest;
+    /// Maps frame `ptr` at page `v_addr` of process `pid` in `pagetable`, with the
+    /// reference taken by [`FrameTable::alloc_page`] or [`FrameTable::get_shared`].
+    /// From then on, the frame may be evicted.
+    ///
+    /// ## Return
+    /// - `false`: If the page has been mapped in the meantime, e.g. by another
+    ///   thread, and maybe evicted to swap since. The reference is dropped instead.
+    pub fn map(
+        pagetable: &mut PageTable,
+        ptr: usize,
+        pid: isize,
+        v_addr: usize,
+        flags: PTEFlags,
+    ) -> bool {
+        let mut table = Self::instance().lock();
+        let index = table.index(ptr);
+
+        if table.supplement.contains_key(&(pid, v_addr))
+            || pagetable
+                .get_pte(v_addr)
+                .is_some_and(|entry| entry.is_valid())
+        {
+            table.put(index);
+            return false;
+        }
+        pagetable.map(PhysAddr::from(ptr), v_addr, PG_SIZE, flags);
+
+        let info = &mut table.frames[index];
+        info.rmap.push(Mapper {
+            pid,
+            v_addr,
+            entry: pagetable.get_pte(v_addr).unwrap(),
+        });
+        info.pinned = false;
+        true
+    }
+
+    /// Unmaps the pages of process `pid` in `[start, end)` from `pagetable`, and drops
+    /// their references. Pages not mapped are skipped.
+    pub fn unmap(pagetable: &mut PageTable, pid: isize, start: usize, end: usize) {
+        let mut table = Self::instance().lock();
+        // Flushed before a freed frame can be allocated again.
+        let mut shootdown = Shootdown::new();
+
+        for v_addr in (start..end).step_by(PG_SIZE) {
+            if let Some(entry) = pagetable.get_pte(v_addr) {
+                if table.unmap(entry, pid, v_addr) {
+                    shootdown.add(v_addr, PG_SIZE);
+                }
+            }
+        }
+    }
+
+    /// Like [`FrameTable::unmap`], but for a single `entry` of a page table being
+    /// destroyed, which is not used by any hart.
+    pub fn release(entry: &Entry, pid: isize, v_addr: usize) {
+        Self::instance().lock().unmap(entry, pid, v_addr);
+    }
+
+    /// Drops all pages of process `pid` from swap. Called before its page table is
+    /// destroyed, whose entries are left invalid for those pages.
+    pub fn release_swap(pid: isize) {
+        let mut table = Self::instance().lock();
+        let pages: Vec<usize> = table
+            .supplement
+            .range((pid, 0)..=(pid, usize::MAX))
+            .map(|(&(_, v_addr), _)| v_addr)
+            .collect();
+        for v_addr in pages {
+            table.unswap(pid, v_addr);
+        }
+    }
+
+    /// Reads page `v_addr` of process `pid` back from swap into `frame`, which is
+    /// taken by [`FrameTable::alloc_page`], and maps it with the flags it's evicted
+    /// with, if they allow `access`. If the page is being moved by another thread,
+    /// the frame is dropped, and the current thread yields before the access is
+    /// retried.
+    ///
+    /// `pagetable` is not locked while reading, which sleeps.
+    ///
+    /// ## Return
+    /// - `false`: If the page is not in swap, or `access` is not allowed. The frame
+    ///   is left to the caller.
+    pub fn swap_in(
+        pagetable: &Mutex<PageTable, Intr>,
+        frame: usize,
+        pid: isize,
+        v_addr: usize,
+        access: PTEFlags,
+    ) -> bool {
+        let key = (pid, v_addr);
+        let slot = {
+            let pagetable = pagetable.lock();
+            let mut table = Self::instance().lock();
+            let Some(state) = table.supplement.get_mut(&key) else {
+                return false;
+            };
+            let flags = pagetable
+                .get_pte(v_addr)
+                .map_or(PTEFlags::empty(), |entry| entry.flag());
+            if !flags.contains(access) {
+                return false;
+            }
+
+            match *state {
+                Supplement::Swap(slot) => {
+                    *state = Supplement::Moving;
+                    slot
+                }
+                _ => {
+                    let index = table.index(frame);
+                    table.put(index);
+                    drop(table);
+                    drop(pagetable);
+                    thread::schedule();
+                    return true;
+                }
+            }
+        };
+
+        SwapTable::load_page(frame, slot);
+
+        let mut pagetable = pagetable.lock();
+        let mut table = Self::instance().lock();
+        let index = table.index(frame);
+        if let Some(Supplement::Dropped) = table.supplement.remove(&key) {
+            // Unmapped in the meantime.
+            table.put(index);
+            return true;
+        }
+
+        // The page has been written, and the content in the frame only is kept.
+        let flags = pagetable.get_pte(v_addr).unwrap().flag();
+        let flags = flags | PTEFlags::V | PTEFlags::A | PTEFlags::D;
+        pagetable.map(PhysAddr::from(frame), v_addr, PG_SIZE, flags);
+
+        let info = &mut table.frames[index];
+        info.rmap.push(Mapper {
+            pid,
+            v_addr,
+            entry: pagetable.get_pte(v_addr).unwrap(),
+        });
+        info.pinned = false;
+        true
+    }
+
+    /// Pages of user memory held by each process, in thousandths of a page. A shared
+    /// frame is split evenly among the processes mapping it. Pages in swap count as
+    /// well, since they are freed along with the frames once the process is killed.
+    fn resident(table: &TableInner) -> BTreeMap<isize, usize> {
+        let mut resident = BTreeMap::new();
+        for info in table.frames.iter().filter(|info| info.active) {
+            for mapper in info.rmap.iter() {
+                *resident.entry(mapper.pid).or_default() += 1000 / info.rmap.len();
+            }
+        }
+        for &(pid, _) in table.supplement.keys() {
+            *resident.entry(pid).or_default() += 1000;
+        }
+        resident
+    }
+
+    /// Drops a reference to frame `ptr` not held by a mapping, e.g. one of a parked
+    /// frame. The frame is freed with the last reference.
+    pub fn dealloc_page(ptr: usize) {
+        let mut table = Self::instance().lock();
+        let index = table.index(ptr);
+        table.put(index);
+    }
+
+    /// Unmaps page `v_addr` of process `pid` from `pagetable`, keeping the reference
+    /// of the page. A parked frame can't be evicted. The caller shoots down the page.
+    ///
+    /// ## Return
+    /// - The frame, if the page was mapped.
+    pub fn park(pagetable: &mut PageTable, pid: isize, v_addr: usize) -> Option<usize> {
+        let mut table = Self::instance().lock();
+        let entry = pagetable.get_pte(v_addr)?;
+        if !entry.fetch_clear(PTEFlags::V).contains(PTEFlags::V) {
+            // A page in swap is left there, but can't be read back until it's
+            // given some permission by `protect`.
+            if table.supplement.contains_key(&(pid, v_addr)) {
+                entry.fetch_clear(PTEFlags::R | PTEFlags::W | PTEFlags::X);
+            }
+            return None;
+        }
+
+        let ptr = entry.pa().into_va();
+        let index = table.index(ptr);
+        table.frames[index].unmapped_by(pid, v_addr);
+        Some(ptr)
+    }
+
+    /// Maps frame `ptr`, which is parked by page `v_addr` of process `pid`, back
+    /// with `flags`. Whether it's accessed or dirty is kept from before.
+    pub fn unpark(
+        pagetable: &mut PageTable,
+        ptr: usize,
+        pid: isize,
+        v_addr: usize,
+        flags: PTEFlags,
+    ) {
         let mut table = Self::instance().lock();
-        table.frames[index].active = false;
-        UserPool::dealloc_pages(ptr as *mut u8, 1);
+        let kept = pagetable
+            .get_pte(v_addr)
+            .filter(|entry| entry.pa() == PhysAddr::from(ptr))
+            .map_or(PTEFlags::empty(), |entry| {
+                entry.flag() & (PTEFlags::A | PTEFlags::D)
+            });
+        pagetable.map(PhysAddr::from(ptr), v_addr, PG_SIZE, flags | kept);
+
+        let index = table.index(ptr);
+        table.frames[index].rmap.push(Mapper {
+            pid,
+            v_addr,
+            entry: pagetable.get_pte(v_addr).unwrap(),
+        });
     }
 
-    pub unsafe fn dealloc_pages(ptr: usize, n: usize) {
-        for i in 0..n {
-            Self::dealloc_page(ptr + i * PG_SIZE);
+    /// Changes the flags of page `v_addr` of process `pid` to `flags`, if it's mapped.
+    /// Whether it's accessed or dirty is kept. The caller shoots down the page. A page
+    /// in swap is mapped with the flags when it's read back.
+    ///
+    /// ## Return
+    /// - Whether the page is mapped.
+    pub fn protect(pagetable: &PageTable, pid: isize, v_addr: usize, flags: PTEFlags) -> bool {
+        let table = Self::instance().lock();
+        match pagetable.get_pte(v_addr) {
+            Some(entry) if entry.is_valid() => {
+                entry.set_flags(flags);
+                true
+            }
+            Some(entry) if table.supplement.contains_key(&(pid, v_addr)) => {
+                entry.set_flags(flags - PTEFlags::V);
+                false
+            }
+            _ => false,
         }
     }
 
-    /// This function tries to find an appropriate page for replacement. It returns
-    /// the kernel virtual address of the in-memory frame
-    /// Clock algorithm is adopted, so this function will only be invoked during
-    /// pagefault or alloc_pages
-    /// - We use 'use' bit to record whether this page is referenced recently
-    /// - When this function is called, advance the clock hand, then check the use bit
-    /// -   1. if use bit = 1 then clear use bit and left it alone
-    /// -   2. if use bit = 0 then select the page as replacement candidate
-    /// this function will not modify the candidate page
-    unsafe fn evict() -> usize {
+    /// Finds the frame holding the read-only page at `v_addr` of executable `ino`,
+    /// and takes a reference to it, which is to be mapped by [`FrameTable::map`].
+    pub fn get_shared(ino: usize, v_addr: usize) -> Option<usize> {
+        let mut table = Self::instance().lock();
+        let frame = *table.shared.get(&(ino, v_addr))?;
+        let index = table.index(frame);
+        table.frames[index].refs += 1;
+        Some(frame)
+    }
+
+    /// Lets other processes share frame `ptr`, which holds the read-only page at
+    /// `v_addr` of executable `ino`. The frame is no longer found once it's freed.
+    ///
+    /// The executable can't be written while it's running. Call [`FrameTable::unshare`]
+    /// before allowing that.
+    pub fn share(ptr: usize, ino: usize, v_addr: usize) {
         let mut table = Self::instance().lock();
+        if table.shared.contains_key(&(ino, v_addr)) {
+            // Loaded by another process in the meantime.
+            return;
+        }
+        table.shared.insert((ino, v_addr), ptr);
+        let index = table.index(ptr);
+        table.frames[index].shared = Some((ino, v_addr));
+    }
+
+    /// Unmaps all shared frames from `pagetable` of process `pid`, and drops the
+    /// references. Once the last process running an executable does so, none of its
+    /// pages are cached, and it may be written.
+    pub fn unshare(pid: isize, pagetable: &mut PageTable) {
+        let mut table = Self::instance().lock();
+        let mut shootdown = Shootdown::new();
+
+        let mapped: Vec<(usize, usize)> = table
+            .shared
+            .iter()
+            .map(|(&(_, v_addr), &frame)| (v_addr, frame))
+            .collect();
+        for (v_addr, frame) in mapped {
+            let mine = pagetable
+                .get_pte(v_addr)
+                .filter(|entry| entry.is_valid() && entry.pa() == PhysAddr::from(frame));
+            if let Some(entry) = mine {
+                table.unmap(entry, pid, v_addr);
+                shootdown.add(v_addr, PG_SIZE);
+            }
+        }
+    }
+
+    /// Takes a frame away from the pages mapping it. The frame is chosen by the
+    /// [`ReplacementPolicy`] of the table. `None` if no frame can be evicted.
+    ///
+    /// A dirty frame is given a slot of swap, and its page is moving until the caller
+    /// writes it there. The frame is left inactive but not freed meanwhile, so that
+    /// nobody else takes it.
+    ///
+    /// Cleared bits are shot down from all TLBs in a batch before it returns.
+    fn evict(table: &mut TableInner) -> Option<Victim> {
+        let mut shootdown = Shootdown::new();
+        // Slots are only taken here, so one stays free once it's found.
+        let swap = SwapTable::has_room();
+
         loop {
-            table.clock_hand = (table.clock_hand + 1) % palloc::USER_POOL_LIMIT;
-            let mut info = table.frames[table.clock_hand];
-            assert!(info.active);
-
-            let thread = Manager::get().get_by_id(info.thread).unwrap();
-            let pt = match &thread.pagetable {
-                Some(pt) => pt.lock(),
-                None => unreachable!(),
-            };
-            let entry = pt.get_pte_mut(info.v_addr).unwrap();
+            let TableInner {
+                frames,
+                policy,
+                shared,
+                supplement,
+                ..
+            } = &mut *table;
+
+            let index = policy.victim(&mut Frames {
+                frames,
+                shootdown: &mut shootdown,
+                swap,
+            })?;
+            let info = &mut frames[index];
+            assert!(info.active && !info.pinned && info.rmap.len() == info.refs);
 
-            if entry.is_accessed() {
-                entry.clean_access_bit();
-            } else {
-                if entry.is_dirty() {
-                    todo!()
+            // Once invalid, the pages can't be written without a fault, but one may
+            // have been written since the policy looked.
+            let mut dirty = false;
+            for mapper in info.rmap.iter() {
+                dirty |= mapper
+                    .entry()
+                    .fetch_clear(PTEFlags::V)
+                    .contains(PTEFlags::D);
+                shootdown.add(mapper.v_addr, PG_SIZE);
+            }
+            let slot = match dirty && info.swappable() {
+                true => SwapTable::alloc(),
+                false => None,
+            };
+            if dirty && slot.is_none() {
+                // Not evictable anymore, so the policy chooses another one.
+                for mapper in info.rmap.iter() {
+                    mapper.entry().fetch_set(PTEFlags::V);
                 }
+                continue;
+            }
 
-                entry.clean_valid_bit();
-                info.active = false;
-                return entry.pa().into_va();
+            let page = info.rmap.first().map(|mapper| (mapper.pid, mapper.v_addr));
+            info.rmap.clear();
+            info.refs = 0;
+            info.active = false;
+            if let Some(key) = info.shared.take() {
+                shared.remove(&key);
+            }
+            if slot.is_some() {
+                supplement.insert(page.unwrap(), Supplement::Moving);
             }
+            STATS.evict();
+
+            let frame = table.frame(index);
+            return Some(match slot {
+                Some(slot) => Victim::Dirty {
+                    frame,
+                    page: page.unwrap(),
+                    slot,
+                },
+                None => Victim::Clean(frame),
+            });
         }
     }
 
     pub fn instance() -> &'static Mutex<TableInner, Intr> {
         static TABLE: FrameTable = FrameTable(Lazy::new(|| {
+            let chunks = UserPool::chunks();
+            let pages = chunks.iter().map(|&(_, pages)| pages).sum();
             Mutex::new(TableInner {
-                frames: alloc::vec![FrameInfo::default(); palloc::USER_POOL_LIMIT],
-                clock_hand: 0,
+                frames: alloc::vec![FrameInfo::default(); pages],
+                chunks,
+                policy: replace::by_name(cmdline::get().replace).unwrap(),
+                shared: BTreeMap::new(),
+                supplement: BTreeMap::new(),
             })
         }));
         &TABLE.0
     }
 }
 
-impl SupplementTable {
-    fn put(thread: usize, ptr: usize, info: SupplementInfo) {
-        assert!(ptr & PG_SIZE == 0);
-        Self::instance().lock().insert((thread, ptr), info);
+impl SwapTable {
+    /// Whether a slot is free.
+    fn has_room() -> bool {
+        Self::instance().lock().contains(&false)
     }
 
-    fn instance() -> &'static Mutex<BTreeMap<(usize, usize), SupplementInfo>, Intr> {
-        static TABLE: SupplementTable = SupplementTable(Lazy::new(|| Mutex::new(BTreeMap::new())));
-        &TABLE.0
+    /// Takes a free slot. `None` if swap is full.
+    fn alloc() -> Option<usize> {
+        let mut slots = Self::instance().lock();
+        let slot = slots.iter().position(|&taken| !taken)?;
+        slots[slot] = true;
+        Some(slot)
     }
-}
 
-impl SwapTable {
-    /// writes the page at ptr in kernel virtual address to disk, returns its disk location
-    pub unsafe fn store_page(thread: usize, ptr: usize) -> usize {
-        assert!(ptr % PG_SIZE == 0);
-        let mut file = DISKFS.get().alloc_page().unwrap();
-        file.write(from_raw_parts(ptr as *const u8, PG_SIZE))
-            .unwrap();
-        Self::instance()
-            .lock()
-            .insert((thread, ptr), file.ino().into());
-        file.ino()
+    fn free(slot: usize) {
+        let mut slots = Self::instance().lock();
+        assert!(slots[slot], "swap slot {} is not taken", slot);
+        slots[slot] = false;
     }
 
-    /// load the page which resided in swap section to memory referred by ptr in kernel virtual address
-    unsafe fn load_to_frame(ptr: usize, location: usize) {
-        assert!(ptr % PG_SIZE == 0);
-        let mut file = DISKFS.get().from_location(location).unwrap();
-        let buf = from_raw_parts_mut(ptr as *mut u8, PG_SIZE);
-        let result = file.read(buf).unwrap();
-        DISKFS.get().free_location(location);
-        assert!(result == PG_SIZE)
+    /// Writes frame `ptr` to `slot`.
+    fn store_page(ptr: usize, slot: usize) {
+        let page = unsafe { from_raw_parts(ptr as *const u8, PG_SIZE) };
+        let mut file = Swap::lock();
+        file.seek(SeekFrom::Start(slot * PG_SIZE))
+            .and_then(|_| file.write_all(page))
+            .expect("swap file should be writable");
     }
 
-    /// load one page from swap file to the memory
-    pub unsafe fn load_page(thread: usize, ptr: usize) {
-        assert!(ptr % PG_SIZE == 0);
-        let inner = Self::instance().lock().remove(&(thread, ptr)).unwrap();
-        Self::load_to_frame(ptr, inner.location);
+    /// Reads `slot` into frame `ptr`, and frees the slot.
+    fn load_page(ptr: usize, slot: usize) {
+        let page = unsafe { from_raw_parts_mut(ptr as *mut u8, PG_SIZE) };
+        let mut file = Swap::lock();
+        file.seek(SeekFrom::Start(slot * PG_SIZE))
+            .and_then(|_| file.read_exact(page))
+            .expect("swap file should be readable");
+        drop(file);
+        Self::free(slot);
     }
 
-    fn instance() -> &'static Mutex<BTreeMap<(usize, usize), SwapInner>, Intr> {
-        static TABLE: SwapTable = SwapTable(Lazy::new(|| Mutex::new(BTreeMap::new())));
+    fn instance() -> &'static Mutex<Vec<bool>, Intr> {
+        static TABLE: SwapTable = SwapTable(Lazy::new(|| {
+            Mutex::new(alloc::vec![false; Swap::page_num()])
+        }));
         &TABLE.0
     }
 }
-
-/// moves page referred by ptr back to the memory
-pub fn demand_page(ptr: usize) {
-    assert!(ptr % PG_SIZE == 0);
-    assert!(in_kernel_space(ptr));
-    todo!()
-}
@@ -12,6 +12,9 @@ use crate::sync::{Intr, Lazy, Mutex};
 
 const ARENA_MAGIC: u32 = 0x9a548eed;
 const MAX_BLKSIZE: usize = PG_SIZE / 4;
+/// Empty arenas a descriptor keeps instead of returning them to [`Palloc`], so that
+/// a block allocated and freed over and over doesn't take a page each time.
+pub const EMPTY_ARENAS: usize = 1;
 
 /// Metadata for a page of memory
 ///
@@ -64,6 +67,8 @@ struct Desc {
     allocated: usize,
     free: usize,
     total: usize,
+    /// The number of arenas with all blocks free
+    empty: usize,
 }
 
 impl Desc {
@@ -76,6 +81,7 @@ impl Desc {
             allocated: 0,
             free: 0,
             total: 0,
+            empty: 0,
         }
     }
 
@@ -94,6 +100,7 @@ impl Desc {
 
             self.total += PG_SIZE;
             self.free += self.block_size * self.blocks_per_arena;
+            self.empty += 1;
         }
 
         self.allocated += self.block_size;
@@ -101,6 +108,9 @@ impl Desc {
 
         let block = self.free_list.pop().unwrap() as *mut u8;
         let arena = Arena::from_block(block);
+        if arena.free_cnt as usize == self.blocks_per_arena {
+            self.empty -= 1;
+        }
         arena.free_cnt -= 1;
 
         block
@@ -108,7 +118,8 @@ impl Desc {
 
     /// Returns a memory block back to this descriptor
     ///
-    /// Pages in the arena will not be returned to the page allocator.
+    /// Once its arena is empty, the page is returned to the page allocator, unless
+    /// the descriptor is keeping no more than [`EMPTY_ARENAS`] empty arenas.
     unsafe fn dealloc(&mut self, ptr: *mut u8) {
         self.free_list.push(ptr.cast());
         self.allocated -= self.block_size;
@@ -116,6 +127,25 @@ impl Desc {
 
         let arena = Arena::from_block(ptr);
         arena.free_cnt += 1;
+        if arena.free_cnt as usize == self.blocks_per_arena {
+            self.empty += 1;
+            if self.empty > EMPTY_ARENAS {
+                self.release(arena);
+            }
+        }
+    }
+
+    /// Unlinks the blocks of an empty arena, and frees its page.
+    unsafe fn release(&mut self, arena: &mut Arena) {
+        let page = arena as *mut Arena as usize;
+        self.free_list
+            .retain(|block| !(page..page + PG_SIZE).contains(&(block as usize)));
+        arena.magic = 0;
+        Palloc::dealloc(page as *mut u8, 1);
+
+        self.total -= PG_SIZE;
+        self.free -= self.block_size * self.blocks_per_arena;
+        self.empty -= 1;
     }
 }
 
@@ -0,0 +1,47 @@
+//! Out of Memory Killer
+//!
+//! When the user pool is exhausted and no frame can be evicted, e.g. swap is full,
+//! an allocation of [`FrameTable`](crate::mem::FrameTable) fails, and [`kill`] picks
+//! a process to make room. The badness of a process is the user memory it holds,
+//! in frames or in swap, so the one freeing the most is killed. Its frames are freed once all of its threads have
+//! left, which is when the failed allocation may succeed.
+//!
+//! Nothing else is killed while a process holding frames is exiting, so that a
+//! burst of failures doesn't take down every process.
+
+use alloc::collections::BTreeMap;
+
+use crate::thread::Manager;
+use crate::userproc::signal::{killed, SIGKILL};
+
+/// Kills the process with the highest badness in `resident`, which maps pids to the
+/// pages they hold, in thousandths of a page.
+pub fn kill(resident: BTreeMap<isize, usize>) {
+    let manager = Manager::get();
+    let dying = resident
+        .keys()
+        .filter_map(|&pid| manager.get_process(pid))
+        .any(|process| process.exiting());
+    if dying {
+        return;
+    }
+
+    // The youngest process loses a tie.
+    let Some((&pid, &badness)) = resident
+        .iter()
+        .max_by_key(|(&pid, &badness)| (badness, pid))
+    else {
+        return;
+    };
+    let Some(process) = manager.get_process(pid) else {
+        return;
+    };
+
+    klog!(
+        Warn,
+        "[OOM] Out of user memory, killed process {} holding {} pages",
+        pid,
+        (badness + 999) / 1000
+    );
+    process.kill(killed(SIGKILL));
+}
@@ -18,6 +18,9 @@
 //! This method replicates the kernel page table as a template for all user page tables.
 //! Having kernel pages existing in all user memory spaces, there will be no need to
 //! switch page table when doing a system call.
+//!
+//! Other harts may cache the old translation of a remapped or unmapped page, so
+//! [`PageTable::map`] and [`PageTable::unmap`] shoot it down on all harts.
 
 mod entry;
 
@@ -28,9 +31,10 @@ use crate::mem::{
     layout::{MMIO_BASE, PLIC_BASE, VM_BASE},
     malloc::{kalloc, kfree},
     palloc::UserPool,
-    utils::{PageAlign, PhysAddr, PG_SIZE},
+    utils::{PageAlign, PhysAddr, PG_SHIFT, PG_SIZE},
 };
 use crate::mem::{FrameTable, KERN_BASE, VM_OFFSET};
+use crate::smp::tlb::Shootdown;
 use crate::sync::OnceCell;
 
 pub use self::entry::*;
@@ -46,7 +50,7 @@ impl PageTable {
     const PX_MASK: usize = Self::NENTRY - 1;
     const SV39_MODE: usize = 0x8 << 60;
 
-    /// Activates `self` as the effective page table.
+    /// Activates `self` as the effective page table of this hart.
     pub fn activate(&self) {
         // SATP layout: MODE(WARL) 4 bit | ASID(WARL) 16 bits | PPN(WARL) 44 bits
         let satp: usize = PhysAddr::from(self.entries.as_ptr()).ppn() | Self::SV39_MODE;
@@ -66,16 +70,39 @@ impl PageTable {
 
         let pa_end = pa.value() + size;
         let (mut pa, mut va) = (pa.value(), va);
+        let mut shootdown = Shootdown::new();
 
         while pa < pa_end {
             let mut l1_table = self.walk_or_create(Self::px(2, va), flag.contains(PTEFlags::G));
             let l0_table = l1_table.walk_or_create(Self::px(1, va), flag.contains(PTEFlags::G));
-            l0_table.entries[Self::px(0, va)] = Entry::new(PhysAddr::from_pa(pa), flag);
+            let entry = &mut l0_table.entries[Self::px(0, va)];
+
+            // Only a valid entry may be cached.
+            if entry.is_valid() {
+                shootdown.add(va, PG_SIZE);
+            }
+
+            *entry = Entry::new(PhysAddr::from_pa(pa), flag);
             pa += PG_SIZE;
             va += PG_SIZE;
         }
     }
 
+    /// Unmaps `size` bytes starting from `va`. Pages that are not mapped are skipped.
+    /// The frames are not freed.
+    pub fn unmap(&mut self, va: usize, size: usize) {
+        assert!(va.is_aligned(), "address misaligns");
+
+        let mut shootdown = Shootdown::new();
+
+        for va in (va..va + size).step_by(PG_SIZE) {
+            if let Some(entry) = self.get_pte_mut(va).filter(|entry| entry.is_valid()) {
+                entry.clean_valid_bit();
+                shootdown.add(va, PG_SIZE);
+            }
+        }
+    }
+
     /// Finds the corresponding entry by the given virtual address
     pub fn get_pte(&self, va: usize) -> Option<&Entry> {
         self.walk(Self::px(2, va)).and_then(|l1_table| {
@@ -94,25 +121,37 @@ impl PageTable {
     }
 
     /// Free all memory used by this pagetable back to where they were allocated.
-    pub unsafe fn destroy(&mut self) {
-        unsafe fn destroy_imp(pgt: &mut PageTable, level: usize) {
+    /// User frames are released as pages of process `pid`, and its pages in swap
+    /// are dropped.
+    ///
+    /// # Safety
+    ///
+    /// The page table must not be active on any hart, and must not be used again
+    /// afterwards, since its pages are freed.
+    pub unsafe fn destroy(&mut self, pid: isize) {
+        unsafe fn destroy_imp(pgt: &mut PageTable, level: usize, base: usize, pid: isize) {
             assert!((0..=2).contains(&level));
 
             pgt.entries
                 .iter()
-                .filter(|entry| entry.is_valid() && !entry.is_global())
-                .for_each(|entry| {
+                .enumerate()
+                .filter(|(_, entry)| entry.is_valid() && !entry.is_global())
+                .for_each(|(i, entry)| {
                     let va = entry.pa().into_va();
+                    let uaddr = base + (i << (PG_SHIFT + 9 * level));
                     if entry.is_leaf() {
-                        FrameTable::dealloc_pages(va, 1 << (9 * level));
-                        // UserPool::dealloc_pages(va as *mut _, 1 << (9 * level));
+                        // User pages are never larger than a page.
+                        assert_eq!(level, 0);
+                        FrameTable::release(entry, pid, uaddr);
                     } else {
-                        destroy_imp(&mut PageTable::from_raw(va as *mut _), level - 1);
+                        let mut table = PageTable::from_raw(va as *mut _);
+                        destroy_imp(&mut table, level - 1, uaddr, pid);
                     }
                 });
             kfree(pgt.entries.as_mut_ptr().cast(), PG_SIZE, PG_SIZE);
         }
-        destroy_imp(self, 2);
+        FrameTable::release_swap(pid);
+        destroy_imp(self, 2, 0, pid);
     }
 
     /// Allocates a page to build a new page table
@@ -1,5 +1,7 @@
 //! Page Table Entry
 
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
 use crate::mem::utils::{PhysAddr, PG_SHIFT};
 
 /// The format of Sv39 page table entry:
@@ -38,10 +40,35 @@ impl Entry {
         Entry((((pa.value() >> PG_SHIFT) & Self::PPN_MASK) << Self::FLAG_SHIFT) | flags.bits())
     }
 
-    fn flag(&self) -> PTEFlags {
+    pub fn flag(&self) -> PTEFlags {
         PTEFlags::from_bits_truncate(self.0)
     }
 
+    /// The hardware sets the A and D bits of a valid entry on its own, so changes
+    /// to such an entry racing with it must be atomic.
+    fn atomic(&self) -> &AtomicUsize {
+        unsafe { &*(self as *const Entry as *const AtomicUsize) }
+    }
+
+    /// Atomically clears `flags`, and returns the flags before.
+    pub fn fetch_clear(&self, flags: PTEFlags) -> PTEFlags {
+        PTEFlags::from_bits_truncate(self.atomic().fetch_and(!flags.bits, SeqCst))
+    }
+
+    /// Atomically sets `flags`.
+    pub fn fetch_set(&self, flags: PTEFlags) {
+        self.atomic().fetch_or(flags.bits, SeqCst);
+    }
+
+    /// Atomically replaces the flags with `flags`, but keeps the A and D bits.
+    pub fn set_flags(&self, flags: PTEFlags) {
+        let kept = PTEFlags::A | PTEFlags::D;
+        let mask = (1 << Self::FLAG_SHIFT) - 1;
+        let _ = self.atomic().fetch_update(SeqCst, SeqCst, |old| {
+            Some(old & !mask | old & kept.bits | (flags - kept).bits)
+        });
+    }
+
     fn ppn(&self) -> usize {
         self.0 >> Self::FLAG_SHIFT & Self::PPN_MASK
     }
@@ -59,6 +86,10 @@ impl Entry {
         self.0 &= !PTEFlags::V.bits;
     }
 
+    pub fn set_valid_bit(&mut self) {
+        self.0 |= PTEFlags::V.bits;
+    }
+
     pub fn is_global(&self) -> bool {
         self.flag().contains(PTEFlags::G)
     }
@@ -1,14 +1,23 @@
 //! Global Page Allocator
+//!
+//! All memory after the kernel image is managed by [`Palloc`]. Part of it is
+//! handed to [`UserPool`] when it's first used, which is where user pages come
+//! from. Its size is given by `userpool=<pages>`, and is a share of the memory
+//! left by default. Either way, some memory is always left to the kernel.
 
+use alloc::vec::Vec;
 use core::cmp::min;
 
+use crate::cmdline;
 use crate::mem::utils::*;
 use crate::sync::{Intr, Lazy, Mutex};
 
 // BuddyAllocator allocates at most `1<<MAX_ORDER` pages at a time
 const MAX_ORDER: usize = 10;
-// How many pages are there in the user memory pool
-pub const USER_POOL_LIMIT: usize = 1024;
+/// Share of the free memory given to the user pool by default, in percent
+const USER_POOL_PERCENT: usize = 50;
+/// Pages always left to the kernel
+const KERNEL_RESERVE: usize = 1024;
 
 /// Buddy Allocator. It allocates and deallocates memory page-wise.
 #[derive(Debug)]
@@ -19,8 +28,6 @@ struct BuddyAllocator {
     total: usize,
     /// The number of pages allocated
     allocated: usize,
-    /// Lowest physical address
-    lowest: usize,
 }
 
 impl BuddyAllocator {
@@ -31,7 +38,6 @@ impl BuddyAllocator {
             free_lists: [InMemList::new(); MAX_ORDER + 1],
             total: 0,
             allocated: 0,
-            lowest: usize::MAX,
         }
     }
 
@@ -40,8 +46,6 @@ impl BuddyAllocator {
         let start = round_up(start, PG_SIZE);
         let end = round_down(end, PG_SIZE);
 
-        self.lowest = min(self.lowest, start);
-
         self.total += end - start;
 
         let mut current_start: usize = start;
@@ -59,10 +63,6 @@ impl BuddyAllocator {
         }
     }
 
-    pub fn lowest(&self) -> usize {
-        self.lowest
-    }
-
     /// Allocate n pages and returns the virtual address.
     unsafe fn alloc(&mut self, n: usize) -> Option<*mut u8> {
         assert!(n <= 1 << MAX_ORDER, "request is too large");
@@ -98,9 +98,9 @@ impl BuddyAllocator {
         let mut curr_ptr = ptr as usize;
         let mut curr_order = order;
 
-        while curr_order < self.free_lists.len() {
+        while curr_order < MAX_ORDER {
             // Find the buddy block of the current block
-            let buddy = curr_ptr ^ (1 << curr_order);
+            let buddy = curr_ptr ^ ((1 << curr_order) * PG_SIZE);
             // Try to find and merge blocks
             if let Some(blk) = self.free_lists[curr_order]
                 .iter_mut()
@@ -133,9 +133,21 @@ impl Palloc {
         Self::instance().lock().insert_range(start, end);
     }
 
-    /// Allocate n pages of a consecutive memory segment
+    /// Allocate n pages of a consecutive memory segment. Kernel memory can't be
+    /// reclaimed, so it panics when there isn't enough.
     pub unsafe fn alloc(n: usize) -> *mut u8 {
-        Self::instance().lock().alloc(n).unwrap()
+        let mut palloc = Self::instance().lock();
+        if let Some(ptr) = palloc.alloc(n) {
+            return ptr;
+        }
+        let (allocated, total) = (palloc.allocated, palloc.total / PG_SIZE);
+        // The panic handler may need the allocator.
+        drop(palloc);
+        panic!(
+            "kernel page allocator exhausted: {} pages requested, {} of {} pages in use. \
+             Give QEMU more memory with `-m`, or shrink the user pool with `userpool=<pages>`.",
+            n, allocated, total
+        )
     }
 
     /// Free n pages of memory starting at `ptr`
@@ -143,6 +155,12 @@ impl Palloc {
         Self::instance().lock().dealloc(ptr, n)
     }
 
+    /// The number of pages not allocated.
+    pub fn free_pages() -> usize {
+        let palloc = Self::instance().lock();
+        palloc.total / PG_SIZE - palloc.allocated
+    }
+
     fn instance() -> &'static Mutex<BuddyAllocator, Intr> {
         static PALLOC: Palloc = Palloc(Lazy::new(|| Mutex::new(BuddyAllocator::empty())));
 
@@ -150,7 +168,14 @@ impl Palloc {
     }
 }
 
-pub struct UserPool(Lazy<Mutex<BuddyAllocator, Intr>>);
+/// Memory given to the user pool.
+struct Pool {
+    alloc: BuddyAllocator,
+    /// Consecutive ranges of pages, as (kernel virtual address, the number of pages)
+    chunks: Vec<(usize, usize)>,
+}
+
+pub struct UserPool(Lazy<Mutex<Pool, Intr>>);
 
 // UserPool has only one instance, so it's safe to claim it as `Sync`
 unsafe impl Sync for UserPool {}
@@ -158,27 +183,59 @@ unsafe impl Sync for UserPool {}
 impl UserPool {
     /// Allocate n pages of consecutive space
     pub unsafe fn alloc_pages(n: usize) -> Option<*mut u8> {
-        Self::instance().lock().alloc(n)
+        Self::instance().lock().alloc.alloc(n)
     }
 
     /// Free n pages of memory starting at `ptr`
     pub unsafe fn dealloc_pages(ptr: *mut u8, n: usize) {
-        Self::instance().lock().dealloc(ptr, n)
+        Self::instance().lock().alloc.dealloc(ptr, n)
     }
 
-    pub fn lowest() -> usize {
-        Self::instance().lock().lowest()
+    /// The ranges of pages in the pool, as (kernel virtual address, the number of
+    /// pages). They are not necessarily next to each other.
+    pub fn chunks() -> Vec<(usize, usize)> {
+        Self::instance().lock().chunks.clone()
     }
 
-    fn instance() -> &'static Mutex<BuddyAllocator, Intr> {
+    /// The number of pages in the pool.
+    pub fn pages() -> usize {
+        Self::instance().lock().alloc.total / PG_SIZE
+    }
+
+    /// How many pages to take from [`Palloc`].
+    fn size() -> usize {
+        let free = Palloc::free_pages();
+        let max = free.saturating_sub(KERNEL_RESERVE);
+        match cmdline::get().userpool {
+            Some(pages) if pages > max => {
+                klog!(
+                    Warn,
+                    "[PALLOC] User pool of {} pages leaves too little to the kernel, using {}",
+                    pages,
+                    max
+                );
+                max
+            }
+            Some(pages) => pages,
+            None => min(free * USER_POOL_PERCENT / 100, max),
+        }
+    }
+
+    fn instance() -> &'static Mutex<Pool, Intr> {
         static USERPOOL: UserPool = UserPool(Lazy::new(|| unsafe {
-            let mut alloc = BuddyAllocator::empty();
-            let chunk_size = 1 << MAX_ORDER;
-            for _ in (0..USER_POOL_LIMIT).step_by(chunk_size) {
-                let start = Palloc::alloc(chunk_size) as usize;
-                alloc.insert_range(start, start + chunk_size * PG_SIZE);
+            let mut pool = Pool {
+                alloc: BuddyAllocator::empty(),
+                chunks: Vec::new(),
+            };
+            let mut left = UserPool::size();
+            while left > 0 {
+                let n = min(1 << MAX_ORDER, prev_power_of_two(left));
+                let start = Palloc::alloc(n) as usize;
+                pool.alloc.insert_range(start, start + n * PG_SIZE);
+                pool.chunks.push((start, n));
+                left -= n;
             }
-            Mutex::new(alloc)
+            Mutex::new(pool)
         }));
 
         &USERPOOL.0
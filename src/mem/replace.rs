@@ -0,0 +1,91 @@
+//! Page Replacement
+//!
+//! When the user pool runs out, [`FrameTable`](crate::mem::FrameTable) takes a
+//! frame away from the pages mapping it. Which one is up to a [`ReplacementPolicy`],
+//! chosen at boot by `replace=<name>` (see [`NAMES`]):
+//!
+//! - `clock`: The clock algorithm. A frame accessed since the hand last passed is
+//!   given a second chance.
+//! - `aging`: Approximates LRU with a counter per frame, which is shifted right
+//!   and gets the accessed bit on the left at every eviction.
+//!
+//! A written frame is written to swap when it's evicted, while the content of a
+//! clean one is dropped, since it's zero-filled again on the next access.
+//!
+//! [`Stats`] counts what happens under the policy, so that they can be compared
+//! on the same workload.
+
+pub mod aging;
+pub mod clock;
+
+use alloc::boxed::Box;
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+use crate::mem::Frames;
+
+/// Names of the built-in policies, as accepted by the `replace=` boot option.
+pub const NAMES: [&str; 2] = ["clock", "aging"];
+
+/// Chooses which frame to take away when the user pool is exhausted.
+pub trait ReplacementPolicy: Send {
+    /// Name of the policy, as in [`NAMES`].
+    fn name(&self) -> &'static str;
+
+    /// Notifies the policy that frame `index` has just been allocated.
+    fn on_alloc(&mut self, _index: usize) {}
+
+    /// Chooses a frame to evict among `frames`. The frame must be
+    /// [evictable](Frames::evictable).
+    ///
+    /// ## Return
+    /// - `None`: If no frame can be evicted.
+    fn victim(&mut self, frames: &mut Frames) -> Option<usize>;
+}
+
+/// Creates the policy called `name`.
+pub fn by_name(name: &str) -> Option<Box<dyn ReplacementPolicy>> {
+    match name {
+        "clock" => Some(Box::new(clock::Clock::default())),
+        "aging" => Some(Box::new(aging::Aging::default())),
+        _ => None,
+    }
+}
+
+/// Counters of paging events since boot.
+pub struct Stats {
+    /// Page faults of user processes
+    faults: AtomicUsize,
+    /// Frames taken away from their pages
+    evictions: AtomicUsize,
+    /// Frames looked at while choosing victims
+    scans: AtomicUsize,
+}
+
+impl Stats {
+    pub fn fault(&self) {
+        self.faults.fetch_add(1, SeqCst);
+    }
+
+    pub fn evict(&self) {
+        self.evictions.fetch_add(1, SeqCst);
+    }
+
+    pub fn scan(&self) {
+        self.scans.fetch_add(1, SeqCst);
+    }
+
+    /// (faults, evictions, scans)
+    pub fn get(&self) -> (usize, usize, usize) {
+        (
+            self.faults.load(SeqCst),
+            self.evictions.load(SeqCst),
+            self.scans.load(SeqCst),
+        )
+    }
+}
+
+pub static STATS: Stats = Stats {
+    faults: AtomicUsize::new(0),
+    evictions: AtomicUsize::new(0),
+    scans: AtomicUsize::new(0),
+};
@@ -0,0 +1,43 @@
+use alloc::vec::Vec;
+
+use crate::mem::replace::{ReplacementPolicy, STATS};
+use crate::mem::Frames;
+
+/// Aging. Each frame has an 8-bit history of its accessed bit, most recent on the
+/// left, and the frame with the least history is the least recently used.
+#[derive(Default)]
+pub struct Aging {
+    ages: Vec<u8>,
+}
+
+impl ReplacementPolicy for Aging {
+    fn name(&self) -> &'static str {
+        "aging"
+    }
+
+    fn on_alloc(&mut self, index: usize) {
+        // A new frame is about to be used, don't take it away right away.
+        if self.ages.len() <= index {
+            self.ages.resize(index + 1, 0);
+        }
+        self.ages[index] = 0x80;
+    }
+
+    fn victim(&mut self, frames: &mut Frames) -> Option<usize> {
+        self.ages.resize(frames.count(), 0);
+
+        let mut victim: Option<usize> = None;
+        for index in 0..frames.count() {
+            if !frames.evictable(index) {
+                continue;
+            }
+            STATS.scan();
+            let bit = if frames.referenced(index) { 0x80 } else { 0 };
+            self.ages[index] = self.ages[index] >> 1 | bit;
+            if victim.map_or(true, |v| self.ages[index] < self.ages[v]) {
+                victim = Some(index);
+            }
+        }
+        victim
+    }
+}
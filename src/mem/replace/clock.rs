@@ -0,0 +1,28 @@
+use crate::mem::replace::{ReplacementPolicy, STATS};
+use crate::mem::Frames;
+
+#[derive(Default)]
+pub struct Clock {
+    hand: usize,
+}
+
+impl ReplacementPolicy for Clock {
+    fn name(&self) -> &'static str {
+        "clock"
+    }
+
+    fn victim(&mut self, frames: &mut Frames) -> Option<usize> {
+        // The second round finds a frame, unless none can be evicted.
+        for _ in 0..2 * frames.count() {
+            self.hand = (self.hand + 1) % frames.count();
+            if !frames.evictable(self.hand) {
+                continue;
+            }
+            STATS.scan();
+            if !frames.referenced(self.hand) {
+                return Some(self.hand);
+            }
+        }
+        None
+    }
+}
@@ -1,90 +1,167 @@
-#![allow(dead_code)]
+//! Access to User Memory
+//!
+//! The kernel reads and writes user memory directly, through the page table of the
+//! current process. A user address may be unmapped, or mapped without the needed
+//! permission, so every access goes through a small assembly routine here. A page
+//! fault inside one of them first gets a chance to allocate a page not allocated
+//! yet, like a fault of the user. If it can't, the fault handler looks up [`fixup`]
+//! and resumes at the end of the routine, which reports the failure, instead of
+//! panicking.
+//!
+//! These routines may fault in pages, which takes the locks of the process, so
+//! they must not be called with any of them held.
 
-use core::arch::{asm, global_asm};
+use alloc::string::String;
+use alloc::vec;
+use core::arch::global_asm;
+use core::mem::{size_of, MaybeUninit};
+use core::slice;
 
 use crate::error::OsError;
 use crate::mem::in_kernel_space;
 use crate::Result;
 
-/// Read a single byte from user space.
-///
-/// ## Return
-/// - `Ok(byte)`
-/// - `Err`: A page fault happened.
-fn read_user_byte(user_src: *const u8) -> Result<u8> {
-    if in_kernel_space(user_src as usize) {
-        return Err(OsError::BadPtr);
+extern "C" {
+    fn __knrl_copy_usr(dst: *mut u8, src: *const u8, len: usize) -> usize;
+    fn __knrl_copy_usr_exit();
+    fn __knrl_strncpy_usr(dst: *mut u8, src: *const u8, len: usize) -> isize;
+    fn __knrl_strncpy_usr_fault();
+}
+
+global_asm! {r#"
+        .section .text
+        .globl __knrl_copy_usr
+        .globl __knrl_copy_usr_exit
+
+    # Copies a2 bytes from a1 to a0, either of which is in user space. Returns the
+    # number of bytes not copied, which is not zero only if a page fault stops it.
+    __knrl_copy_usr:
+        beqz a2, __knrl_copy_usr_exit
+    .Lcopy_usr_loop:
+        lb   t0, (a1)
+        sb   t0, (a0)
+        addi a0, a0, 1
+        addi a1, a1, 1
+        addi a2, a2, -1
+        bnez a2, .Lcopy_usr_loop
+    __knrl_copy_usr_exit:
+        mv   a0, a2
+        ret
+
+        .globl __knrl_strncpy_usr
+        .globl __knrl_strncpy_usr_fault
+
+    # Copies a null-terminated string of at most a2 bytes from user address a1 to
+    # a0. Returns the length of the string, or a2 if there's no null in a2 bytes.
+    __knrl_strncpy_usr:
+        mv   t1, a0
+        beqz a2, .Lstrncpy_usr_done
+    .Lstrncpy_usr_loop:
+        lb   t0, (a1)
+        sb   t0, (a0)
+        beqz t0, .Lstrncpy_usr_done
+        addi a0, a0, 1
+        addi a1, a1, 1
+        addi a2, a2, -1
+        bnez a2, .Lstrncpy_usr_loop
+    .Lstrncpy_usr_done:
+        sub  a0, a0, t1
+        ret
+    __knrl_strncpy_usr_fault:
+        li   a0, -1
+        ret
+"#}
+
+/// Where a page fault at `pc` resumes, if `pc` is in one of the routines above.
+/// Each of them resumes at the label right after its accesses.
+pub fn fixup(pc: usize) -> Option<usize> {
+    let table = [
+        (
+            __knrl_copy_usr as *const (),
+            __knrl_copy_usr_exit as *const (),
+        ),
+        (
+            __knrl_strncpy_usr as *const (),
+            __knrl_strncpy_usr_fault as *const (),
+        ),
+    ];
+
+    table
+        .iter()
+        .map(|&(start, fixup)| (start as usize, fixup as usize))
+        .find(|(start, fixup)| (*start..*fixup).contains(&pc))
+        .map(|(_, fixup)| fixup)
+}
+
+/// Checks that `[addr, addr + len)` lies in user space. Kernel space is at the top
+/// of the address space, so it's enough to check the last byte.
+fn check_range(addr: usize, len: usize) -> Result<()> {
+    match addr.checked_add(len) {
+        _ if len == 0 => Ok(()),
+        Some(end) if !in_kernel_space(end - 1) => Ok(()),
+        _ => Err(OsError::BadPtr),
     }
+}
 
-    let mut ret_status: isize = 0;
-    let mut byte: u8;
-    unsafe {
-        asm! {
-            "call __knrl_read_usr_byte",
-            in("a0") user_src,  lateout("a0") byte,
-            inout("a1") ret_status
-        }
-    };
-
-    if ret_status == 0 {
-        Ok(byte)
-    } else {
-        Err(OsError::BadPtr)
+/// Copies `dst.len()` bytes from user address `src`.
+pub fn copy_from_user(dst: &mut [u8], src: usize) -> Result<()> {
+    check_range(src, dst.len())?;
+    match unsafe { __knrl_copy_usr(dst.as_mut_ptr(), src as *const u8, dst.len()) } {
+        0 => Ok(()),
+        _ => Err(OsError::BadPtr),
     }
 }
 
-/// Write a single byte to user space.
+/// Copies `src` to user address `dst`.
+pub fn copy_to_user(dst: usize, src: &[u8]) -> Result<()> {
+    check_range(dst, src.len())?;
+    match unsafe { __knrl_copy_usr(dst as *mut u8, src.as_ptr(), src.len()) } {
+        0 => Ok(()),
+        _ => Err(OsError::BadPtr),
+    }
+}
+
+/// Copies a null-terminated string from user address `src` into `dst`, including
+/// the null if it fits.
 ///
 /// ## Return
-/// - `Ok(())`
-/// - `Err`: A page fault happened.
-fn write_user_byte(user_src: *const u8, value: u8) -> Result<()> {
-    if in_kernel_space(user_src as usize) {
+/// - `Ok(len)`: The length of the string, or `dst.len()` if the string doesn't end
+///   in `dst.len()` bytes.
+/// - `Err`: A page fault happened before the string ends.
+pub fn strncpy_from_user(dst: &mut [u8], src: usize) -> Result<usize> {
+    // Addresses right above user space are not canonical, so a string running out
+    // of user space faults before reaching kernel space.
+    if in_kernel_space(src) {
         return Err(OsError::BadPtr);
     }
-
-    let mut ret_status: isize = 0;
-    unsafe {
-        asm! {
-            "call __knrl_write_usr_byte",
-            in("a0") user_src,
-            inout("a1") ret_status,
-            in("a2") value
-        }
-    };
-
-    if ret_status == 0 {
-        Ok(())
-    } else {
-        Err(OsError::BadPtr)
+    match unsafe { __knrl_strncpy_usr(dst.as_mut_ptr(), src as *const u8, dst.len()) } {
+        -1 => Err(OsError::BadPtr),
+        len => Ok(len as usize),
     }
 }
 
-extern "C" {
-    pub fn __knrl_read_usr_byte(user_src: *const u8);
-    pub fn __knrl_read_usr_exit();
-    pub fn __knrl_write_usr_byte(user_src: *const u8);
-    pub fn __knrl_write_usr_exit();
+/// Reads a null-terminated string of less than `max` bytes from user address `src`.
+pub fn read_user_str(src: usize, max: usize) -> Result<String> {
+    let mut buf = vec![0; max];
+    let len = strncpy_from_user(&mut buf, src)?;
+    if len == max {
+        return Err(OsError::CstrFormatErr);
+    }
+    buf.truncate(len);
+    Ok(buf.into_iter().map(char::from).collect())
 }
 
-global_asm! {r#"
-        .section .text
-        .globl __knrl_read_usr_byte
-        .globl __knrl_read_usr_exit
-
-    __knrl_read_usr_byte:
-        lb t0, (a0)
-    __knrl_read_usr_exit:
-        # pagefault handler will set a1 if any error occurs
-        mv a0, t0
-        ret
-
-        .globl __knrl_write_usr_byte
-        .globl __knrl_write_usr_exit
+/// Reads a `T` from user address `src`. `T` must be valid for any bit pattern.
+pub fn read_user<T: Copy>(src: usize) -> Result<T> {
+    let mut value = MaybeUninit::<T>::uninit();
+    let bytes =
+        unsafe { slice::from_raw_parts_mut(value.as_mut_ptr().cast::<u8>(), size_of::<T>()) };
+    copy_from_user(bytes, src)?;
+    Ok(unsafe { value.assume_init() })
+}
 
-    __knrl_write_usr_byte:
-        sb a2, (a0)
-    __knrl_write_usr_exit:
-        # pagefault handler will set a1 if any error occurs
-        ret
-"#}
+/// Writes `value` to user address `dst`.
+pub fn write_user<T: Copy>(dst: usize, value: &T) -> Result<()> {
+    let bytes = unsafe { slice::from_raw_parts((value as *const T).cast::<u8>(), size_of::<T>()) };
+    copy_to_user(dst, bytes)
+}
@@ -38,6 +38,20 @@ impl InMemList {
         }
     }
 
+    /// Removes all items for which `f` returns false
+    pub unsafe fn retain(&mut self, mut f: impl FnMut(*mut usize) -> bool) {
+        let mut prev = &mut self.head as *mut *mut usize;
+        while !(*prev).is_null() {
+            let curr = *prev;
+            if f(curr) {
+                prev = curr as *mut *mut usize;
+            } else {
+                // Skip the current one
+                *prev = *curr as *mut usize;
+            }
+        }
+    }
+
     /// Return an mutable iterator over the items in the list
     pub fn iter_mut(&mut self) -> IterMut {
         IterMut {
@@ -25,6 +25,12 @@ impl<T: Ord> FIFOPrioriyQueue<T> {
         None
     }
 
+    /// Removes the first item matching `f`, regardless of its priority.
+    pub fn remove_by(&mut self, f: impl FnMut(&T) -> bool) -> Option<T> {
+        let index = self.items.iter().position(f)?;
+        self.items.remove(index)
+    }
+
     pub fn peek(&self) -> Option<&T> {
         self.items
             .iter()
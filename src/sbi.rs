@@ -66,6 +66,108 @@ pub mod legacy {
     }
 }
 
+pub mod hsm {
+    //! Hart State Management Extension
+
+    const HSM: usize = 0x48534D;
+    const HART_START: usize = 0;
+    const HART_STOP: usize = 1;
+    const HART_GET_STATUS: usize = 2;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Status {
+        Started = 0,
+        Stopped = 1,
+        StartPending = 2,
+        StopPending = 3,
+        Suspended = 4,
+        SuspendPending = 5,
+        ResumePending = 6,
+    }
+
+    /// Starts `hart_id` in supervisor mode at the physical address `start_addr`,
+    /// with `a0 = hart_id` and `a1 = opaque`. Paging is disabled on entry.
+    ///
+    /// Returns the SBI error code on failure.
+    pub fn hart_start(hart_id: usize, start_addr: usize, opaque: usize) -> Result<(), isize> {
+        match call!(HSM, HART_START; hart_id, start_addr, opaque) {
+            (0, _) => Ok(()),
+            (err, _) => Err(err as isize),
+        }
+    }
+
+    /// Stops the calling hart. It can be started again by [`hart_start`].
+    pub fn hart_stop() -> ! {
+        call!(HSM, HART_STOP;);
+
+        unreachable!("hart should have been stopped")
+    }
+
+    /// Gets the status of `hart_id`, or the SBI error code if it doesn't exist.
+    pub fn hart_status(hart_id: usize) -> Result<Status, isize> {
+        match call!(HSM, HART_GET_STATUS; hart_id) {
+            (0, 0) => Ok(Status::Started),
+            (0, 1) => Ok(Status::Stopped),
+            (0, 2) => Ok(Status::StartPending),
+            (0, 3) => Ok(Status::StopPending),
+            (0, 4) => Ok(Status::Suspended),
+            (0, 5) => Ok(Status::SuspendPending),
+            (0, _) => Ok(Status::ResumePending),
+            (err, _) => Err(err as isize),
+        }
+    }
+}
+
+pub mod ipi {
+    //! IPI Extension
+
+    const IPI: usize = 0x735049;
+    const SEND_IPI: usize = 0;
+
+    /// Raises a supervisor software interrupt on harts `hart_mask_base + i` for
+    /// every bit `i` set in `hart_mask`.
+    pub fn send_ipi(hart_mask: usize, hart_mask_base: usize) -> Result<(), isize> {
+        match call!(IPI, SEND_IPI; hart_mask, hart_mask_base) {
+            (0, _) => Ok(()),
+            (err, _) => Err(err as isize),
+        }
+    }
+}
+
+pub mod rfence {
+    //! RFENCE Extension
+    //!
+    //! Harts are selected by `hart_mask` and `hart_mask_base` as in [`send_ipi`](super::ipi::send_ipi).
+    //! The fences are done by the firmware, so the target harts don't need to be
+    //! interruptible.
+
+    const RFENCE: usize = 0x52464E43;
+    const REMOTE_FENCE_I: usize = 0;
+    const REMOTE_SFENCE_VMA: usize = 1;
+
+    /// Executes `fence.i` on the selected harts.
+    pub fn remote_fence_i(hart_mask: usize, hart_mask_base: usize) -> Result<(), isize> {
+        match call!(RFENCE, REMOTE_FENCE_I; hart_mask, hart_mask_base) {
+            (0, _) => Ok(()),
+            (err, _) => Err(err as isize),
+        }
+    }
+
+    /// Executes `sfence.vma` covering `[start, start + size)` on the selected harts.
+    /// A `size` of `usize::MAX` flushes the whole address space.
+    pub fn remote_sfence_vma(
+        hart_mask: usize,
+        hart_mask_base: usize,
+        start: usize,
+        size: usize,
+    ) -> Result<(), isize> {
+        match call!(RFENCE, REMOTE_SFENCE_VMA; hart_mask, hart_mask_base, start, size) {
+            (0, _) => Ok(()),
+            (err, _) => Err(err as isize),
+        }
+    }
+}
+
 pub mod system_reset {
     const SYSTEM_RESET: usize = 0;
 
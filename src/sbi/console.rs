@@ -1,13 +1,17 @@
 use core::fmt::{Result, Write};
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicUsize, Ordering::*};
 
 use crate::sbi::{console_putchar, interrupt};
+use crate::smp;
 
 pub struct Stdout;
 
 /// A locked standard output
 ///
 /// `StdoutLock` simply shuts down interrupt when acquired, and restores
-/// the previous interrupt setting when dropped.
+/// the previous interrupt setting when dropped. It also keeps other harts
+/// from printing in the meantime.
 ///
 /// ## Examples
 /// ```
@@ -22,8 +26,13 @@ pub struct StdoutLock<'a> {
     inner: &'a mut Stdout,
     /// interrupt status before stdout being locked
     intr: bool,
+    /// whether this hart already held the lock
+    nested: bool,
 }
 
+/// The hart holding the standard output, or `usize::MAX`
+static HOLDER: AtomicUsize = AtomicUsize::new(usize::MAX);
+
 /// The one and only Stdout instance
 pub fn stdout() -> &'static mut Stdout {
     static mut INSTANCE: Stdout = Stdout;
@@ -35,9 +44,23 @@ impl Stdout {
     ///
     /// This is a re-entrant lock, allowing called in a nested manner.
     pub fn lock(&self) -> StdoutLock {
+        let intr = interrupt::set(false);
+        let hart = smp::hart_id();
+
+        let nested = HOLDER.load(Relaxed) == hart;
+        if !nested {
+            while HOLDER
+                .compare_exchange_weak(usize::MAX, hart, Acquire, Relaxed)
+                .is_err()
+            {
+                spin_loop();
+            }
+        }
+
         StdoutLock {
             inner: stdout(),
-            intr: interrupt::set(false),
+            intr,
+            nested,
         }
     }
 }
@@ -59,6 +82,9 @@ impl Write for StdoutLock<'_> {
 
 impl Drop for StdoutLock<'_> {
     fn drop(&mut self) {
+        if !self.nested {
+            HOLDER.store(usize::MAX, Release);
+        }
         interrupt::set(self.intr);
     }
 }
@@ -86,3 +112,14 @@ macro_rules! kprintln {
         kprint!("\n");
     }};
 }
+
+/// Prints like [`kprintln`] if messages of `level` are enabled by `loglevel=`,
+/// e.g. `klog!(Warn, "{} is not found", name)`.
+#[macro_export]
+macro_rules! klog {
+    ($level:ident, $($arg:tt)*) => {{
+        if $crate::cmdline::enabled($crate::cmdline::LogLevel::$level) {
+            kprintln!($($arg)*);
+        }
+    }};
+}
@@ -1,4 +1,6 @@
-//! RISC-V timer & external interrupt
+//! RISC-V timer, external & software interrupt
+//!
+//! Software interrupts are sent between harts (see [`crate::smp::ipi`]).
 
 use riscv::register;
 
@@ -7,24 +9,26 @@ fn on() {
     unsafe {
         register::sie::set_stimer();
         register::sie::set_sext();
+        register::sie::set_ssoft();
     };
 }
 
 #[inline]
 fn off() {
     unsafe {
+        register::sie::clear_ssoft();
         register::sie::clear_sext();
         register::sie::clear_stimer();
     };
 }
 
-/// Get timer & external interrupt level. `true` means interruptible.
+/// Get timer, external & software interrupt level. `true` means interruptible.
 #[inline]
 pub fn get() -> bool {
     register::sie::read().stimer()
 }
 
-/// Set timer & external interrupt level.
+/// Set timer, external & software interrupt level.
 pub fn set(level: bool) -> bool {
     let old = get();
 
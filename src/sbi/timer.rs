@@ -1,9 +1,8 @@
 //! RISC-V Timer Interface
 
-use core::sync::atomic::{AtomicI64, Ordering::SeqCst};
-
-use crate::{sbi::set_timer, thread::Manager};
+use crate::{cmdline, sbi::set_timer, smp, thread::Manager};
 
+/// Default tick rate, which can be overridden by the `tick=` boot option.
 pub const TICKS_PER_SEC: usize = 10;
 pub const CLOCK_PRE_SEC: usize = 12500000;
 
@@ -24,23 +23,57 @@ pub fn time_us() -> usize {
     clock() * 1_000_000 / CLOCK_PRE_SEC
 }
 
-/// Set the next moment when timer interrupt should happen
+/// Get the number of timer interrupts per second
+#[inline]
+pub fn ticks_per_sec() -> usize {
+    cmdline::get().tick
+}
+
+/// Get the clock cycles between two ticks
+#[inline]
+fn interval() -> usize {
+    CLOCK_PRE_SEC / ticks_per_sec()
+}
+
+/// Set the next moment when timer interrupt should happen, which is when the
+/// next tick begins.
 #[inline]
 pub fn next() {
-    set_timer(clock() + CLOCK_PRE_SEC / TICKS_PER_SEC);
+    set_timer((timer_ticks() as usize + 1) * interval());
+}
+
+/// Stops the periodic tick of the boot hart until tick `deadline` is due, or
+/// forever if it's `None`. Used by the idle thread when nothing is ready to run.
+pub fn stop_tick(deadline: Option<i64>) {
+    set_timer(match deadline {
+        // A deadline that has passed is due at once.
+        Some(deadline) => deadline.max(0) as usize * interval(),
+        None => usize::MAX,
+    });
 }
 
-static TICKS: AtomicI64 = AtomicI64::new(0);
+/// Restarts the periodic tick after [`stop_tick`]. The caller should wake up
+/// threads whose deadline has passed.
+pub fn restart_tick() {
+    next();
+}
 
 /// Returns the number of timer ticks since booted.
+///
+/// Ticks are counted by the clock rather than by interrupts, so all harts agree
+/// on them, even while the boot hart is idle without a tick.
 pub fn timer_ticks() -> i64 {
-    TICKS.load(SeqCst)
+    (clock() / interval()) as i64
 }
 
-/// Increments timer ticks by 1 and sets the next timer interrupt.
+/// Sets the next timer interrupt.
+///
+/// Every hart has its own timer. Only the boot hart wakes up sleeping threads,
+/// others merely preempt their running threads.
 pub fn tick() {
-    TICKS.fetch_add(1, SeqCst);
-    Manager::get().check_sleep_threads();
+    if smp::hart_id() == smp::boot_hart() {
+        Manager::get().check_sleep_threads();
+    }
     next();
 }
 
@@ -0,0 +1,98 @@
+//! Symmetric Multiprocessing
+//!
+//! The boot hart enters [`main`](crate::main) and brings up the others through the
+//! SBI HSM extension in [`boot_secondary`]. A secondary hart enters at `_secondary_entry`
+//! with paging disabled, turns on the entry page table, and then jumps to
+//! [`secondary_main`](crate::secondary_main) on the stack of its idle thread.
+//!
+//! Every hart keeps its own id in `tp`, which is set at entry and never changed in
+//! kernel mode. `trap_entry_u` and `trap_exit_u` preserve the user's `tp` separately.
+//!
+//! Harts talk to each other through [`ipi`], and keep their TLBs coherent through [`tlb`].
+
+pub mod ipi;
+pub mod tlb;
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+use crate::cmdline;
+use crate::mem::VM_OFFSET;
+use crate::sbi::hsm;
+use crate::thread::Manager;
+
+/// Harts with an id no less than this are ignored.
+pub const MAX_HARTS: usize = 8;
+
+/// Bit `i` is set once hart `i` is up and running.
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+static BOOT_HART: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" {
+    fn _secondary_entry();
+}
+
+/// Id of the hart which executes the caller.
+///
+/// Note that a thread may be moved to another hart whenever it is preempted. Turn off
+/// interrupts first if the id is used to index per-hart data.
+#[inline]
+pub fn hart_id() -> usize {
+    let id: usize;
+    unsafe { asm!("mv {}, tp", out(reg) id) };
+    id
+}
+
+/// Id of the hart which booted the kernel.
+pub fn boot_hart() -> usize {
+    BOOT_HART.load(SeqCst)
+}
+
+/// Number of harts that are up and running.
+pub fn online() -> usize {
+    ONLINE.load(SeqCst).count_ones() as usize
+}
+
+/// Bit `i` is set if hart `i` is up and running.
+pub fn online_mask() -> usize {
+    ONLINE.load(SeqCst)
+}
+
+/// Marks the calling hart as online. The boot hart should call it before any other.
+pub fn set_online() {
+    let hart = hart_id();
+    assert!(hart < MAX_HARTS, "hart {} is not supported", hart);
+
+    if ONLINE.fetch_or(1 << hart, SeqCst) == 0 {
+        BOOT_HART.store(hart, SeqCst);
+    }
+}
+
+/// Starts all other harts, until `smp=` harts are online. Returns when all started
+/// harts are online.
+pub fn boot_secondary() {
+    let entry = _secondary_entry as usize - VM_OFFSET;
+
+    for hart in 0..MAX_HARTS {
+        if online() >= cmdline::get().smp {
+            break;
+        }
+        if ONLINE.load(SeqCst) & (1 << hart) != 0 || hsm::hart_status(hart).is_err() {
+            continue;
+        }
+
+        let stack_top = Manager::get().create_idle(hart);
+        if let Err(err) = hsm::hart_start(hart, entry, stack_top) {
+            klog!(Warn, "[SMP] Failed to start hart {}, error {}", hart, err);
+            Manager::get().remove_idle(hart);
+            continue;
+        }
+
+        while ONLINE.load(SeqCst) & (1 << hart) == 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    klog!(Info, "[SMP] {} hart(s) online", online());
+}
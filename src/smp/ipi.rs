@@ -0,0 +1,25 @@
+//! Inter-Processor Interrupts
+//!
+//! [`kick`] interrupts another hart through a supervisor software interrupt
+//! raised by the SBI, e.g. to wake it up from `wfi`. The interrupt carries no
+//! data: [`handle`] only acknowledges it, and the hart goes on to schedule.
+//!
+//! ## Examples
+//! ```
+//! smp::ipi::kick(smp::boot_hart());
+//! ```
+
+use core::arch::asm;
+
+use crate::sbi;
+
+/// Interrupts `hart`, e.g. to wake it up from `wfi`.
+pub fn kick(hart: usize) {
+    sbi::ipi::send_ipi(1 << hart, 0).expect("failed to send IPI");
+}
+
+/// Acknowledges the interrupt. Invoked on supervisor software interrupts.
+pub fn handle() {
+    // Clear the pending bit, so that later kicks raise another one.
+    unsafe { asm!("csrci sip, 2") };
+}
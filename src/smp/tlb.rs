@@ -0,0 +1,95 @@
+//! TLB Shootdown
+//!
+//! Changing or removing a valid mapping leaves stale translations in the TLB of
+//! every hart that has used it. [`Shootdown`] collects such pages, and flushes them
+//! on all online harts at once: locally by `sfence.vma`, and remotely through the
+//! SBI RFENCE extension.
+//!
+//! Without ASIDs, harts can't tell which address space a translation belongs to,
+//! so all online harts are flushed.
+//!
+//! ## Examples
+//! ```
+//! let mut shootdown = Shootdown::new();
+//! for va in pages {
+//!     pagetable.get_pte_mut(va).unwrap().clean_valid_bit();
+//!     shootdown.add(va, PG_SIZE);
+//! }
+//! shootdown.flush();
+//! ```
+
+use core::arch::asm;
+
+use crate::mem::PG_SIZE;
+use crate::sbi::{interrupt, rfence};
+use crate::smp;
+
+/// A range of virtual addresses to be flushed from all TLBs.
+///
+/// It is flushed when dropped.
+pub struct Shootdown {
+    start: usize,
+    end: usize,
+}
+
+impl Shootdown {
+    /// Flushing a larger range page by page is slower than flushing everything.
+    const MAX_PAGES: usize = 64;
+
+    pub const fn new() -> Self {
+        Self {
+            start: usize::MAX,
+            end: 0,
+        }
+    }
+
+    /// Adds `[va, va + size)` to the range.
+    pub fn add(&mut self, va: usize, size: usize) {
+        self.start = self.start.min(va);
+        self.end = self.end.max(va + size);
+    }
+
+    /// Flushes the range on all online harts, and empties it.
+    pub fn flush(&mut self) {
+        if self.start >= self.end {
+            return;
+        }
+
+        let (start, size) = if (self.end - self.start) / PG_SIZE > Self::MAX_PAGES {
+            (0, usize::MAX)
+        } else {
+            (self.start, self.end - self.start)
+        };
+        *self = Self::new();
+
+        // Stay on this hart, so that it's excluded from the remote harts correctly.
+        let old = interrupt::set(false);
+
+        if size == usize::MAX {
+            unsafe { asm!("sfence.vma zero, zero") };
+        } else {
+            for va in (start..start + size).step_by(PG_SIZE) {
+                unsafe { asm!("sfence.vma {}, zero", in(reg) va) };
+            }
+        }
+
+        let others = smp::online_mask() & !(1 << smp::hart_id());
+        if others != 0 {
+            rfence::remote_sfence_vma(others, 0, start, size).expect("remote sfence.vma failed");
+        }
+
+        interrupt::set(old);
+    }
+}
+
+impl Default for Shootdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Shootdown {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
@@ -1,20 +1,26 @@
 //! Synchronization and Interior Mutability
 //!
 
+pub mod barrier;
 pub mod condvar;
 pub mod intr;
 pub mod lazy;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
 pub mod mutex;
 pub mod once;
+pub mod rwlock;
 pub mod sema;
 pub mod sleep;
 pub mod spin;
 
+pub use self::barrier::Barrier;
 pub use self::condvar::Condvar;
 pub use self::intr::Intr;
 pub use self::lazy::Lazy;
 pub use self::mutex::{Mutex, MutexGuard};
 pub use self::once::{Once, OnceCell};
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 pub use self::sema::Semaphore;
 pub use self::sleep::Sleep;
 pub use self::spin::Spin;
@@ -0,0 +1,56 @@
+use crate::sync::{self, Condvar, Lock, Mutex};
+
+/// A barrier enables a group of threads to wait until all of them have reached
+/// the same point, and then continue together.
+///
+/// A barrier can be reused: once all threads have passed it, it's ready for the
+/// next round.
+///
+/// # Examples
+/// ```
+/// let barrier = Arc::new(Barrier::new(3));
+/// for _ in 0..3 {
+///     let b = barrier.clone();
+///     thread::spawn("worker", move || {
+///         // Do sth.
+///         b.wait();
+///         // All 3 workers have done sth.
+///     });
+/// }
+/// ```
+pub struct Barrier<L: Lock = sync::Primitive> {
+    n: usize,
+    /// Number of threads arrived in this round, and the round itself
+    state: Mutex<(usize, usize), L>,
+    cvar: Condvar,
+}
+
+impl<L: Lock> Barrier<L> {
+    /// Creates a barrier for `n` threads.
+    pub fn new(n: usize) -> Self {
+        Self {
+            n,
+            state: Mutex::new((0, 0)),
+            cvar: Condvar::new(),
+        }
+    }
+
+    /// Blocks until all `n` threads have called it. Returns `true` in exactly one
+    /// of them, i.e. the last to arrive.
+    pub fn wait(&self) -> bool {
+        let mut state = self.state.lock();
+        let round = state.1;
+
+        state.0 += 1;
+        if state.0 < self.n {
+            while state.1 == round {
+                self.cvar.wait(&mut state);
+            }
+            false
+        } else {
+            *state = (0, round.wrapping_add(1));
+            self.cvar.notify_all();
+            true
+        }
+    }
+}
@@ -80,6 +80,32 @@ impl Condvar {
         guard.acquire();
     }
 
+    /// Like [`Condvar::wait`], but gives up after `ticks` timer ticks. Returns whether
+    /// it timed out, i.e. it hasn't been notified.
+    pub fn wait_timeout<T, L: Lock>(&self, guard: &mut MutexGuard<'_, T, L>, ticks: i64) -> bool {
+        let sema = Arc::new(Semaphore::new(0));
+
+        #[cfg(feature = "thread-scheduler-priority")]
+        self.0.borrow_mut().push((current().into(), sema.clone()));
+        #[cfg(not(feature = "thread-scheduler-priority"))]
+        self.0.borrow_mut().push((current(), sema.clone()));
+
+        guard.release();
+        sema.down_timeout(ticks);
+        guard.acquire();
+
+        // A notification may come between the timeout and re-acquiring the lock, so
+        // check the list instead of the result of `down_timeout`.
+        let mut waiters = self.0.borrow_mut();
+        match waiters.iter().position(|(_, s)| Arc::ptr_eq(s, &sema)) {
+            Some(index) => {
+                waiters.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Wake up one thread from the waiting list
     #[cfg(feature = "thread-scheduler-priority")]
     pub fn notify_one(&self) {
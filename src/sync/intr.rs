@@ -1,6 +1,8 @@
 use core::cell::Cell;
+use core::hint::spin_loop;
+use core::sync::atomic::{AtomicUsize, Ordering::*};
 
-use crate::{sbi, sync::Lock};
+use crate::{sbi, smp, sync::Lock};
 
 /// A lock based on disabling timer interrupt.
 ///
@@ -10,12 +12,31 @@ use crate::{sbi, sync::Lock};
 ///
 /// On acquisition, it turns off the timer and record the old timer status. On release,
 /// it simply restores the old status.
-#[derive(Debug, Default)]
-pub struct Intr(Cell<Option<bool>>);
+///
+/// Turning off interrupts only excludes threads on the same hart, so the lock also
+/// spins until no other hart holds it.
+#[derive(Debug)]
+pub struct Intr {
+    /// Interrupt status before the lock was acquired
+    intr: Cell<Option<bool>>,
+    /// The hart holding this lock, or [`Intr::FREE`]
+    holder: AtomicUsize,
+}
 
 impl Intr {
+    const FREE: usize = usize::MAX;
+
     pub const fn new() -> Self {
-        Self(Cell::new(None))
+        Self {
+            intr: Cell::new(None),
+            holder: AtomicUsize::new(Self::FREE),
+        }
+    }
+}
+
+impl Default for Intr {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -24,14 +45,25 @@ unsafe impl Send for Intr {}
 
 impl Lock for Intr {
     fn acquire(&self) {
-        assert!(self.0.get().is_none());
+        let old = sbi::interrupt::set(false);
+        let hart = smp::hart_id();
+
+        while let Err(holder) =
+            self.holder
+                .compare_exchange_weak(Self::FREE, hart, Acquire, Relaxed)
+        {
+            assert_ne!(holder, hart, "acquire a held lock");
+            spin_loop();
+        }
 
         // Record the old timer status. Here setting the immutable `self` is safe
-        // because the interrupt is already turned off.
-        self.0.set(Some(sbi::interrupt::set(false)));
+        // because the lock is already held.
+        self.intr.set(Some(old));
     }
 
     fn release(&self) {
-        sbi::interrupt::set(self.0.take().expect("release before acquire"));
+        let old = self.intr.take().expect("release before acquire");
+        self.holder.store(Self::FREE, Release);
+        sbi::interrupt::set(old);
     }
 }
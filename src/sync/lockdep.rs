@@ -0,0 +1,349 @@
+//! Lock Dependency Validator
+//!
+//! Enabled by the `lockdep` feature. Every [`Mutex`](crate::sync::Mutex) and
+//! [`RwLock`](crate::sync::RwLock) belongs to a lock class, which is the place
+//! where it's created. [`acquire`] records the order in which classes are taken:
+//! taking `B` while holding `A` adds the edge `A -> B` to a global graph. A new
+//! edge closing a cycle means that two threads may take the same locks in
+//! opposite orders, which may deadlock even if it didn't this time. Taking a
+//! lock already held by the current thread deadlocks for sure. Both are
+//! reported by a panic, which prints the locks held by the current thread, and
+//! the ones held when the conflicting order was first seen.
+//!
+//! Locks of the same class but different instances, e.g. the page tables of two
+//! processes, are not ordered against each other.
+//!
+//! The validator can't use the heap or any [`Mutex`], since both of them take
+//! locks. So all states live in fixed-size tables guarded by a bare [`Intr`] lock.
+//! When a table runs out, the validator turns itself off with a warning.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::panic::Location;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst};
+
+use crate::sbi;
+use crate::sync::{Intr, Lock};
+use crate::thread::Manager;
+
+const MAX_CLASSES: usize = 128;
+const MAX_EDGES: usize = 256;
+/// Threads holding locks at the same time
+const MAX_THREADS: usize = 64;
+/// Locks held by a thread at the same time
+const MAX_HELD: usize = 8;
+
+const NO_CLASS: usize = usize::MAX;
+
+/// Class of a lock, i.e. where it's created.
+#[derive(Debug)]
+pub struct Class {
+    site: &'static Location<'static>,
+    /// Index into the class table, assigned on first acquisition.
+    id: AtomicUsize,
+}
+
+impl Class {
+    #[track_caller]
+    pub fn new() -> Self {
+        Self {
+            site: Location::caller(),
+            id: AtomicUsize::new(NO_CLASS),
+        }
+    }
+}
+
+impl Default for Class {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A lock held by a thread.
+#[derive(Clone, Copy)]
+struct Held {
+    class: usize,
+    /// Address of the lock instance
+    lock: usize,
+    /// Where it was acquired
+    site: &'static Location<'static>,
+}
+
+/// Locks held by a thread, from the outermost.
+#[derive(Clone, Copy)]
+struct Stack {
+    held: [Option<Held>; MAX_HELD],
+    len: usize,
+}
+
+impl Stack {
+    const EMPTY: Self = Self {
+        held: [None; MAX_HELD],
+        len: 0,
+    };
+
+    fn iter(&self) -> impl Iterator<Item = &Held> {
+        self.held[..self.len].iter().flatten()
+    }
+}
+
+/// Edge `from -> to`, with the stack of the thread which took `to` at that time.
+#[derive(Clone, Copy)]
+struct Edge {
+    from: usize,
+    to: usize,
+    stack: Stack,
+}
+
+struct State {
+    classes: [Option<&'static Location<'static>>; MAX_CLASSES],
+    /// `graph[a] & (1 << b)` is set if there is an edge `a -> b`.
+    graph: [u128; MAX_CLASSES],
+    edges: [Option<Edge>; MAX_EDGES],
+    /// Stacks of threads holding locks, by thread id
+    threads: [(isize, Stack); MAX_THREADS],
+}
+
+struct Validator {
+    lock: Intr,
+    state: UnsafeCell<State>,
+}
+
+unsafe impl Sync for Validator {}
+
+static VALIDATOR: Validator = Validator {
+    lock: Intr::new(),
+    state: UnsafeCell::new(State {
+        classes: [None; MAX_CLASSES],
+        graph: [0; MAX_CLASSES],
+        edges: [None; MAX_EDGES],
+        threads: [(-1, Stack::EMPTY); MAX_THREADS],
+    }),
+};
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// What went wrong, reported after the validator has been released. It's large,
+/// but boxing it would take the heap.
+#[allow(clippy::large_enum_variant)]
+enum Violation {
+    Recursive {
+        site: &'static Location<'static>,
+        stack: Stack,
+    },
+    Cycle {
+        site: &'static Location<'static>,
+        stack: Stack,
+        /// The recorded edge leaving the class being acquired
+        edge: Option<Edge>,
+    },
+    Full(&'static str),
+}
+
+#[allow(clippy::result_large_err)]
+impl State {
+    fn class_id(&mut self, class: &Class) -> Option<usize> {
+        let id = class.id.load(SeqCst);
+        if id != NO_CLASS {
+            return Some(id);
+        }
+
+        let id = self
+            .classes
+            .iter()
+            .position(|c| c.map_or(true, |site| core::ptr::eq(site, class.site)))?;
+        self.classes[id] = Some(class.site);
+        class.id.store(id, SeqCst);
+        Some(id)
+    }
+
+    fn stack_of(&mut self, tid: isize) -> Option<&mut Stack> {
+        let index = match self.threads.iter().position(|(t, _)| *t == tid) {
+            Some(index) => index,
+            None => {
+                let index = self.threads.iter().position(|(t, _)| *t == -1)?;
+                self.threads[index] = (tid, Stack::EMPTY);
+                index
+            }
+        };
+        Some(&mut self.threads[index].1)
+    }
+
+    /// Whether `to` is reachable from `from`.
+    fn reaches(&self, from: usize, to: usize) -> bool {
+        let mut visited: u128 = 1 << from;
+        let mut pending: u128 = self.graph[from];
+
+        while pending != 0 {
+            let next = pending.trailing_zeros() as usize;
+            if next == to {
+                return true;
+            }
+            pending &= !(1 << next);
+            visited |= 1 << next;
+            pending |= self.graph[next] & !visited;
+        }
+        false
+    }
+
+    fn add_edge(&mut self, from: usize, to: usize, stack: Stack) -> Result<(), Violation> {
+        if self.graph[from] & (1 << to) != 0 {
+            return Ok(());
+        }
+
+        if self.reaches(to, from) {
+            let edge = self
+                .edges
+                .iter()
+                .flatten()
+                .find(|e| e.from == to && (e.to == from || self.reaches(e.to, from)))
+                .copied();
+            return Err(Violation::Cycle {
+                site: stack.iter().last().unwrap().site,
+                stack,
+                edge,
+            });
+        }
+
+        self.graph[from] |= 1 << to;
+        let slot = self.edges.iter_mut().find(|e| e.is_none());
+        let slot = slot.ok_or(Violation::Full("edges"))?;
+        *slot = Some(Edge { from, to, stack });
+        Ok(())
+    }
+
+    fn acquire(
+        &mut self,
+        tid: isize,
+        class: &Class,
+        lock: usize,
+        site: &'static Location<'static>,
+    ) -> Result<(), Violation> {
+        let class = self.class_id(class).ok_or(Violation::Full("classes"))?;
+        let stack = *self.stack_of(tid).ok_or(Violation::Full("threads"))?;
+
+        if stack.iter().any(|h| h.lock == lock) {
+            return Err(Violation::Recursive { site, stack });
+        }
+
+        let mut new = stack;
+        if new.len == MAX_HELD {
+            return Err(Violation::Full("held locks"));
+        }
+        new.held[new.len] = Some(Held { class, lock, site });
+        new.len += 1;
+
+        for held in stack.iter().filter(|h| h.class != class) {
+            self.add_edge(held.class, class, new)?;
+        }
+
+        *self.stack_of(tid).unwrap() = new;
+        Ok(())
+    }
+
+    fn release(&mut self, tid: isize, lock: usize) {
+        let Some(index) = self.threads.iter().position(|(t, _)| *t == tid) else {
+            return;
+        };
+        let (owner, stack) = &mut self.threads[index];
+
+        let pos = stack.iter().position(|h| h.lock == lock);
+        if let Some(pos) = pos {
+            stack.held.copy_within(pos + 1..stack.len, pos);
+            stack.len -= 1;
+            stack.held[stack.len] = None;
+        }
+        if stack.len == 0 {
+            *owner = -1;
+        }
+    }
+}
+
+fn with_state<R>(f: impl FnOnce(&mut State) -> R) -> R {
+    let old = sbi::interrupt::set(false);
+    VALIDATOR.lock.acquire();
+    let result = f(unsafe { &mut *VALIDATOR.state.get() });
+    VALIDATOR.lock.release();
+    sbi::interrupt::set(old);
+    result
+}
+
+/// Records that the current thread is taking `lock` of `class` at `site`.
+/// Called before actually taking it, so that a deadlock is reported instead of
+/// hanging.
+pub fn acquire(class: &Class, lock: usize, site: &'static Location<'static>) {
+    if DISABLED.load(SeqCst) {
+        return;
+    }
+
+    #[allow(clippy::result_large_err)]
+    let result = with_state(|state| state.acquire(Manager::current_id(), class, lock, site));
+    if let Err(violation) = result {
+        report(violation);
+    }
+}
+
+/// Records that the current thread has released `lock`.
+pub fn release(lock: usize) {
+    if DISABLED.load(SeqCst) {
+        return;
+    }
+
+    with_state(|state| state.release(Manager::current_id(), lock));
+}
+
+fn report(violation: Violation) {
+    // Turn off first, since nothing can be trusted from now on.
+    if DISABLED.swap(true, SeqCst) {
+        return;
+    }
+
+    match violation {
+        Violation::Recursive { site, stack } => panic!(
+            "[LOCKDEP] Recursive locking at {}, while holding:\n{}",
+            site,
+            Display(stack)
+        ),
+        Violation::Cycle { site, stack, edge } => {
+            let classes = unsafe { &(*VALIDATOR.state.get()).classes };
+            match edge {
+                Some(edge) => panic!(
+                    "[LOCKDEP] Possible deadlock at {}, while holding:\n{}\
+                     The reversed order was taken before, from lock class {}, while holding:\n{}",
+                    site,
+                    Display(stack),
+                    classes[edge.from].unwrap(),
+                    Display(edge.stack),
+                ),
+                None => panic!(
+                    "[LOCKDEP] Possible deadlock at {}, while holding:\n{}",
+                    site,
+                    Display(stack)
+                ),
+            }
+        }
+        Violation::Full(table) => {
+            klog!(Warn, "[LOCKDEP] Too many {}, turning off the validator", table)
+        }
+    }
+}
+
+/// Prints a lock stack, one lock per line.
+struct Display(Stack);
+
+impl fmt::Display for Display {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let classes = unsafe { &(*VALIDATOR.state.get()).classes };
+        for (i, held) in self.0.iter().enumerate() {
+            writeln!(
+                f,
+                "  #{} acquired at {} (class {})",
+                i,
+                held.site,
+                classes[held.class].unwrap()
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,11 @@
 use core::cell::UnsafeCell;
 use core::ops::{Deref, DerefMut};
 
+#[cfg(feature = "lockdep")]
+use core::panic::Location;
+
+#[cfg(feature = "lockdep")]
+use crate::sync::lockdep;
 use crate::sync::{self, Lock};
 
 /// A mutual exclusion primitive useful for protecting shared data
@@ -14,10 +19,13 @@ use crate::sync::{self, Lock};
 /// }
 /// assert_eq!(foo.lock(), 10);
 /// ```
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct Mutex<T, L: Lock = sync::Primitive> {
     value: UnsafeCell<T>,
     lock: L,
+    /// Where it's created, see [`lockdep`].
+    #[cfg(feature = "lockdep")]
+    class: lockdep::Class,
 }
 
 // The only access to a Mutex's value is MutexGuard, so safety is guaranteed here.
@@ -27,18 +35,42 @@ unsafe impl<T: Send, L: Lock> Send for Mutex<T, L> {}
 
 impl<T, L: Lock> Mutex<T, L> {
     /// Creates a mutex in an unlocked state ready for use.
+    #[track_caller]
     pub fn new(value: T) -> Self {
         Self {
             value: UnsafeCell::new(value),
             lock: L::default(),
+            #[cfg(feature = "lockdep")]
+            class: lockdep::Class::new(),
         }
     }
 
     /// Acquires a mutex, blocking the current thread until it is able to do so.
+    #[track_caller]
     pub fn lock(&self) -> MutexGuard<'_, T, L> {
-        self.lock.acquire();
+        self.acquire();
         MutexGuard(self)
     }
+
+    #[track_caller]
+    fn acquire(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(&self.class, self as *const _ as usize, Location::caller());
+        self.lock.acquire();
+    }
+
+    fn release(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self as *const _ as usize);
+        self.lock.release();
+    }
+}
+
+impl<T: Default, L: Lock> Default for Mutex<T, L> {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
 }
 
 /// An RAII implementation of a “scoped lock” of a mutex.
@@ -66,17 +98,18 @@ impl<T, L: Lock> DerefMut for MutexGuard<'_, T, L> {
 
 impl<T, L: Lock> Drop for MutexGuard<'_, T, L> {
     fn drop(&mut self) {
-        self.0.lock.release();
+        self.0.release();
     }
 }
 
 // Useful in Condvar
 impl<T, L: Lock> MutexGuard<'_, T, L> {
     pub(super) fn release(&self) {
-        self.0.lock.release();
+        self.0.release();
     }
 
+    #[track_caller]
     pub(super) fn acquire(&self) {
-        self.0.lock.acquire();
+        self.0.acquire();
     }
 }
@@ -70,6 +70,11 @@ impl<T> OnceCell<T> {
         self.once.call_once(|| self.inner.set(Some(f())));
     }
 
+    /// Whether the cell has been initialized.
+    pub fn is_initialized(&self) -> bool {
+        self.once.is_completed()
+    }
+
     /// Initialize or get the value from a cell. A cell will only
     /// be initialized **once**.
     pub fn get_or_init<F>(&self, f: F) -> &T
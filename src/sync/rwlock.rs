@@ -0,0 +1,278 @@
+//! # Reader-Writer Lock
+//!
+//! [`RwLock`] allows any number of readers or at most one writer at a time. It
+//! prefers writers: once a writer is waiting, new readers wait until it's done, so
+//! that a steady stream of readers can't starve writers.
+//!
+//! With `thread-scheduler-priority`, threads waiting for a lock held by a writer
+//! donate their priorities to the writer once, and depend on it, as for
+//! [`Sleep`](crate::sync::Sleep). The writer gives them back on unlocking, and the
+//! next writer takes the donations of the threads still waiting. A lock held by
+//! readers has no single holder to donate to, so readers aren't boosted.
+//!
+//! ## Examples
+//! ```
+//! let lock = RwLock::new(5);
+//! {
+//!     let r1 = lock.read();
+//!     let r2 = lock.read();
+//!     assert_eq!(*r1 + *r2, 10);
+//! }
+//! *lock.write() += 1;
+//! assert_eq!(*lock.read(), 6);
+//! ```
+//!
+//! Note that reading again while holding a read guard may deadlock, if a writer
+//! comes in between. For the same reason, [`lockdep`] treats reading and writing
+//! alike: both take the class of the lock.
+
+use alloc::sync::Arc;
+#[cfg(feature = "thread-scheduler-priority")]
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+#[cfg(feature = "lockdep")]
+use core::panic::Location;
+
+#[cfg(feature = "thread-scheduler-priority")]
+use crate::sbi;
+#[cfg(feature = "lockdep")]
+use crate::sync::lockdep;
+use crate::sync::{self, Condvar, Lock, Mutex, MutexGuard};
+use crate::thread::{self, Thread};
+
+#[derive(Default)]
+struct State {
+    /// Number of readers holding the lock
+    readers: usize,
+    /// The writer holding the lock
+    writer: Option<Arc<Thread>>,
+    /// Number of writers waiting for the lock
+    waiting_writers: usize,
+    /// Threads waiting for the lock, which donate to `writer`
+    #[cfg(feature = "thread-scheduler-priority")]
+    waiters: Vec<Arc<Thread>>,
+}
+
+/// A reader-writer lock, whose state is protected by a lock of type `L`.
+pub struct RwLock<T, L: Lock = sync::Primitive> {
+    value: UnsafeCell<T>,
+    state: Mutex<State, L>,
+    /// Readers wait here
+    readable: Condvar,
+    /// Writers wait here
+    writable: Condvar,
+    /// Where it's created, see [`lockdep`].
+    #[cfg(feature = "lockdep")]
+    class: lockdep::Class,
+}
+
+// Same as `Mutex`, except that readers on different threads share `&T`.
+unsafe impl<T: Send + Sync, L: Lock> Sync for RwLock<T, L> {}
+unsafe impl<T: Send, L: Lock> Send for RwLock<T, L> {}
+
+impl<T, L: Lock> RwLock<T, L> {
+    /// Creates a reader-writer lock in an unlocked state ready for use.
+    #[track_caller]
+    pub fn new(value: T) -> Self {
+        Self {
+            value: UnsafeCell::new(value),
+            state: Mutex::new(State::default()),
+            readable: Condvar::new(),
+            writable: Condvar::new(),
+            #[cfg(feature = "lockdep")]
+            class: lockdep::Class::new(),
+        }
+    }
+
+    /// Acquires shared access, blocking the current thread while a writer holds or
+    /// waits for the lock.
+    #[track_caller]
+    pub fn read(&self) -> RwLockReadGuard<'_, T, L> {
+        self.acquire();
+        let mut state = self.state.lock();
+        if state.writer.is_some() || state.waiting_writers > 0 {
+            Self::donate(&mut state);
+            while state.writer.is_some() || state.waiting_writers > 0 {
+                self.readable.wait(&mut state);
+            }
+            Self::stop_waiting(&mut state);
+        }
+        state.readers += 1;
+
+        RwLockReadGuard(self)
+    }
+
+    /// Acquires exclusive access, blocking the current thread until no one else
+    /// holds the lock.
+    #[track_caller]
+    pub fn write(&self) -> RwLockWriteGuard<'_, T, L> {
+        self.acquire();
+        let mut state = self.state.lock();
+        state.waiting_writers += 1;
+        if state.writer.is_some() || state.readers > 0 {
+            Self::donate(&mut state);
+            while state.writer.is_some() || state.readers > 0 {
+                self.writable.wait(&mut state);
+            }
+            Self::stop_waiting(&mut state);
+        }
+        state.waiting_writers -= 1;
+        state.writer = Some(thread::current());
+        Self::inherit(&mut state);
+
+        RwLockWriteGuard(self)
+    }
+
+    /// Records the acquisition for [`lockdep`], before waiting for it.
+    #[track_caller]
+    fn acquire(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::acquire(&self.class, self.id(), Location::caller());
+    }
+
+    fn release(&self) {
+        #[cfg(feature = "lockdep")]
+        lockdep::release(self.id());
+    }
+
+    /// Identifies the lock to [`lockdep`]. Its own address may be the one of
+    /// `state`, which is taken while holding it, but not the one of `class`.
+    #[cfg(feature = "lockdep")]
+    fn id(&self) -> usize {
+        &self.class as *const _ as usize
+    }
+
+    fn read_unlock(&self) {
+        let mut state = self.state.lock();
+        state.readers -= 1;
+        if state.readers == 0 {
+            self.writable.notify_one();
+        }
+        drop(state);
+        self.release();
+    }
+
+    fn write_unlock(&self) {
+        let mut state = self.state.lock();
+        let writer = state.writer.take().expect("unlock a free RwLock");
+        assert!(Arc::ptr_eq(&writer, &thread::current()));
+
+        #[cfg(feature = "thread-scheduler-priority")]
+        {
+            let old = sbi::interrupt::set(false);
+            state.waiters.iter().for_each(|x| {
+                writer.remove_donator(x.priority());
+                x.dependency.lock().take().unwrap();
+            });
+            sbi::interrupt::set(old);
+        }
+
+        if state.waiting_writers > 0 {
+            self.writable.notify_one();
+        } else {
+            self.readable.notify_all();
+        }
+        drop(state);
+        self.release();
+    }
+
+    /// Makes the current thread a waiter, which donates its priority to the writer
+    /// holding the lock, if any, and depends on it. Called once per wait.
+    #[cfg(feature = "thread-scheduler-priority")]
+    fn donate(state: &mut MutexGuard<'_, State, L>) {
+        let old = sbi::interrupt::set(false);
+        let current = thread::current();
+        assert!(current.dependency.lock().is_none());
+        if let Some(writer) = state.writer.as_ref() {
+            writer.add_donator(current.priority());
+            current.dependency.lock().replace(writer.clone());
+        }
+        state.waiters.push(current);
+        sbi::interrupt::set(old);
+    }
+
+    /// Removes the current thread from the waiters, once it has the lock. The last
+    /// writer has taken back its donation already.
+    #[cfg(feature = "thread-scheduler-priority")]
+    fn stop_waiting(state: &mut MutexGuard<'_, State, L>) {
+        let old = sbi::interrupt::set(false);
+        let current = thread::current();
+        assert!(current.dependency.lock().is_none());
+        state.waiters.retain(|x| !Arc::ptr_eq(x, &current));
+        sbi::interrupt::set(old);
+    }
+
+    /// Makes the threads still waiting donate to the current thread, which has just
+    /// taken the lock as a writer.
+    #[cfg(feature = "thread-scheduler-priority")]
+    fn inherit(state: &mut MutexGuard<'_, State, L>) {
+        let old = sbi::interrupt::set(false);
+        let current = thread::current();
+        state.waiters.iter().for_each(|x| {
+            current.add_donator(x.priority());
+            x.dependency.lock().replace(current.clone());
+        });
+        sbi::interrupt::set(old);
+    }
+
+    #[cfg(not(feature = "thread-scheduler-priority"))]
+    fn donate(_state: &mut MutexGuard<'_, State, L>) {}
+
+    #[cfg(not(feature = "thread-scheduler-priority"))]
+    fn stop_waiting(_state: &mut MutexGuard<'_, State, L>) {}
+
+    #[cfg(not(feature = "thread-scheduler-priority"))]
+    fn inherit(_state: &mut MutexGuard<'_, State, L>) {}
+}
+
+impl<T: Default, L: Lock> Default for RwLock<T, L> {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// An RAII guard of shared access to a [`RwLock`], released when dropped.
+pub struct RwLockReadGuard<'a, T, L: Lock>(&'a RwLock<T, L>);
+
+unsafe impl<T: Sync, L: Lock> Sync for RwLockReadGuard<'_, T, L> {}
+
+impl<T, L: Lock> Deref for RwLockReadGuard<'_, T, L> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.0.value.get() }
+    }
+}
+
+impl<T, L: Lock> Drop for RwLockReadGuard<'_, T, L> {
+    fn drop(&mut self) {
+        self.0.read_unlock();
+    }
+}
+
+/// An RAII guard of exclusive access to a [`RwLock`], released when dropped.
+pub struct RwLockWriteGuard<'a, T, L: Lock>(&'a RwLock<T, L>);
+
+unsafe impl<T: Sync, L: Lock> Sync for RwLockWriteGuard<'_, T, L> {}
+
+impl<T, L: Lock> Deref for RwLockWriteGuard<'_, T, L> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.0.value.get() }
+    }
+}
+
+impl<T, L: Lock> DerefMut for RwLockWriteGuard<'_, T, L> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.0.value.get() }
+    }
+}
+
+impl<T, L: Lock> Drop for RwLockWriteGuard<'_, T, L> {
+    fn drop(&mut self) {
+        self.0.write_unlock();
+    }
+}
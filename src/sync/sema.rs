@@ -2,33 +2,44 @@ use core::cell::{Cell, RefCell};
 
 #[cfg(not(feature = "thread-scheduler-priority"))]
 use alloc::collections::VecDeque;
-#[cfg(not(feature = "thread-scheduler-priority"))]
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 
 #[cfg(feature = "thread-scheduler-priority")]
 use crate::pq::FIFOPrioriyQueue;
-use crate::sbi;
+use crate::sbi::{self, timer::timer_ticks};
+use crate::sync::{Intr, Lock};
 #[cfg(feature = "thread-scheduler-priority")]
-use crate::thread::scheduler::fcfs::Thread;
+use crate::thread::scheduler::pirority::Thread;
 #[cfg(not(feature = "thread-scheduler-priority"))]
 use crate::thread::Thread;
-use crate::thread::{self};
+use crate::thread::{self, Manager, Status};
 
 /// Atomic counting semaphore
 ///
+/// A waiter of [`Semaphore::down_timeout`] is also registered as a sleeping thread.
+/// Exactly one of [`Semaphore::up`] and the timer wakes it up: `up` does only if
+/// it manages to take the waiter out of the sleeping threads first.
+///
 /// # Examples
 /// ```
 /// let sema = Semaphore::new(0);
 /// sema.down();
 /// sema.up();
+/// assert!(!sema.down_timeout(10)); // Acquired
+/// assert!(sema.down_timeout(10)); // Timed out after 10 ticks
+/// assert!(!sema.try_down());
 /// ```
-#[derive(Clone)]
 pub struct Semaphore {
+    /// Protects `value` and `waiters` from other harts
+    lock: Intr,
     value: Cell<usize>,
     #[cfg(feature = "thread-scheduler-priority")]
     waiters: RefCell<FIFOPrioriyQueue<Thread>>,
     #[cfg(not(feature = "thread-scheduler-priority"))]
     waiters: RefCell<VecDeque<Arc<Thread>>>,
+    /// Waiters with a deadline
+    timed: RefCell<Vec<Arc<thread::Thread>>>,
 }
 
 unsafe impl Sync for Semaphore {}
@@ -40,16 +51,20 @@ impl Semaphore {
     #[cfg(feature = "thread-scheduler-priority")]
     pub fn new(n: usize) -> Self {
         Semaphore {
+            lock: Intr::new(),
             value: Cell::new(n),
             waiters: RefCell::new(FIFOPrioriyQueue::new()),
+            timed: RefCell::new(Vec::new()),
         }
     }
 
     #[cfg(not(feature = "thread-scheduler-priority"))]
     pub const fn new(n: usize) -> Self {
         Semaphore {
+            lock: Intr::new(),
             value: Cell::new(n),
             waiters: RefCell::new(VecDeque::new()),
+            timed: RefCell::new(Vec::new()),
         }
     }
 
@@ -57,6 +72,7 @@ impl Semaphore {
     #[cfg(feature = "thread-scheduler-priority")]
     pub fn down(&self) {
         let old = sbi::interrupt::set(false);
+        self.lock.acquire();
 
         // Is semaphore available?
         while self.value() == 0 {
@@ -64,16 +80,18 @@ impl Semaphore {
             self.waiters.borrow_mut().push(thread::current().into());
 
             // Block the current thread until it's awakened by an `up` operation
-            thread::block();
+            thread::block_with(&self.lock);
         }
         self.value.set(self.value() - 1);
 
+        self.lock.release();
         sbi::interrupt::set(old);
     }
 
     #[cfg(not(feature = "thread-scheduler-priority"))]
     pub fn down(&self) {
         let old = sbi::interrupt::set(false);
+        self.lock.acquire();
 
         // Is semaphore available?
         while self.value() == 0 {
@@ -81,10 +99,11 @@ impl Semaphore {
             self.waiters.borrow_mut().push_front(thread::current());
 
             // Block the current thread until it's awakened by an `up` operation
-            thread::block();
+            thread::block_with(&self.lock);
         }
         self.value.set(self.value() - 1);
 
+        self.lock.release();
         sbi::interrupt::set(old);
     }
 
@@ -92,8 +111,14 @@ impl Semaphore {
     #[cfg(feature = "thread-scheduler-priority")]
     pub fn up(&self) {
         let old = sbi::interrupt::set(false);
+        self.lock.acquire();
         self.value.replace(self.value() + 1);
-        let result = self.waiters.borrow_mut().pop();
+        let result = self
+            .waiters
+            .borrow_mut()
+            .pop()
+            .filter(|thread| self.take_from_timer(&thread.0));
+        self.lock.release();
 
         // Check if we need to wake up a sleeping waiter
         if let Some(thread) = result {
@@ -107,10 +132,17 @@ impl Semaphore {
     #[cfg(not(feature = "thread-scheduler-priority"))]
     pub fn up(&self) {
         let old = sbi::interrupt::set(false);
+        self.lock.acquire();
         let count = self.value.replace(self.value() + 1);
+        let result = self
+            .waiters
+            .borrow_mut()
+            .pop_back()
+            .filter(|thread| self.take_from_timer(thread));
+        self.lock.release();
 
         // Check if we need to wake up a sleeping waiter
-        if let Some(thread) = self.waiters.borrow_mut().pop_back() {
+        if let Some(thread) = result {
             assert_eq!(count, 0);
 
             thread::wake_up(thread.clone());
@@ -119,6 +151,106 @@ impl Semaphore {
         sbi::interrupt::set(old);
     }
 
+    /// P operation that gives up after `ticks` timer ticks. Returns whether it timed out.
+    pub fn down_timeout(&self, ticks: i64) -> bool {
+        let old = sbi::interrupt::set(false);
+        self.lock.acquire();
+
+        let deadline = timer_ticks() + ticks;
+        let mut timed_out = false;
+
+        while self.value() == 0 {
+            if timer_ticks() >= deadline {
+                timed_out = true;
+                break;
+            }
+
+            let current = thread::current();
+            self.push_waiter(current.clone());
+            self.timed.borrow_mut().push(current.clone());
+
+            // Block before registering, so that the timer won't find it running.
+            current.set_status(Status::Blocked);
+            Manager::get().register_sleep_thread(current.clone(), deadline);
+
+            self.lock.release();
+            thread::schedule();
+            self.lock.acquire();
+
+            // Still waiting, so it's the timer that woke it up. Otherwise `up` has
+            // taken it out of both lists.
+            if self.remove_waiter(&current) {
+                self.timed
+                    .borrow_mut()
+                    .retain(|t| !Arc::ptr_eq(t, &current));
+            }
+        }
+
+        if !timed_out {
+            self.value.set(self.value() - 1);
+        }
+
+        self.lock.release();
+        sbi::interrupt::set(old);
+        timed_out
+    }
+
+    /// P operation that never blocks. Returns whether it succeeded.
+    pub fn try_down(&self) -> bool {
+        let old = sbi::interrupt::set(false);
+        self.lock.acquire();
+
+        let acquired = self.value() > 0;
+        if acquired {
+            self.value.set(self.value() - 1);
+        }
+
+        self.lock.release();
+        sbi::interrupt::set(old);
+        acquired
+    }
+
+    /// Called by `up` with a waiter just popped. Returns whether `up` should wake
+    /// it up, i.e. it has no deadline, or the timer hasn't taken it yet.
+    fn take_from_timer(&self, thread: &Arc<thread::Thread>) -> bool {
+        let mut timed = self.timed.borrow_mut();
+        match timed.iter().position(|t| Arc::ptr_eq(t, thread)) {
+            Some(index) => {
+                timed.remove(index);
+                Manager::get().cancel_sleep(thread)
+            }
+            None => true,
+        }
+    }
+
+    #[cfg(feature = "thread-scheduler-priority")]
+    fn push_waiter(&self, thread: Arc<thread::Thread>) {
+        self.waiters.borrow_mut().push(thread.into());
+    }
+
+    #[cfg(not(feature = "thread-scheduler-priority"))]
+    fn push_waiter(&self, thread: Arc<Thread>) {
+        self.waiters.borrow_mut().push_front(thread);
+    }
+
+    /// Removes `thread` from waiters. Returns whether it was waiting.
+    #[cfg(feature = "thread-scheduler-priority")]
+    fn remove_waiter(&self, thread: &Arc<thread::Thread>) -> bool {
+        self.waiters
+            .borrow_mut()
+            .remove_by(|t| Arc::ptr_eq(&t.0, thread))
+            .is_some()
+    }
+
+    #[cfg(not(feature = "thread-scheduler-priority"))]
+    fn remove_waiter(&self, thread: &Arc<Thread>) -> bool {
+        let mut waiters = self.waiters.borrow_mut();
+        match waiters.iter().position(|t| Arc::ptr_eq(t, thread)) {
+            Some(index) => waiters.remove(index).is_some(),
+            None => false,
+        }
+    }
+
     /// Get the current value of a semaphore
     pub fn value(&self) -> usize {
         self.value.get()
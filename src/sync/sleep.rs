@@ -1,29 +1,31 @@
 use alloc::sync::Arc;
 #[cfg(feature = "thread-scheduler-priority")]
 use alloc::vec::Vec;
-use core::cell::RefCell;
 
 #[cfg(feature = "thread-scheduler-priority")]
 use crate::sbi;
 use crate::sync::{Lock, Semaphore};
-use crate::thread::{self, Thread};
+use crate::thread::{self, Mutex, Thread};
 
 /// Sleep lock. Uses [`Semaphore`] under the hood.
-#[derive(Clone)]
 pub struct Sleep {
     inner: Semaphore,
-    holder: RefCell<Option<Arc<Thread>>>,
+    /// Shared by all harts, so it's guarded by an intr lock.
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    holder: Option<Arc<Thread>>,
     #[cfg(feature = "thread-scheduler-priority")]
-    waiter: RefCell<Vec<Arc<Thread>>>,
+    waiter: Vec<Arc<Thread>>,
 }
 
 impl Default for Sleep {
     fn default() -> Self {
         Self {
             inner: Semaphore::new(1),
-            holder: Default::default(),
-            #[cfg(feature = "thread-scheduler-priority")]
-            waiter: Default::default(),
+            state: Default::default(),
         }
     }
 }
@@ -38,31 +40,34 @@ impl Lock for Sleep {
 
         assert!(current.dependency.lock().is_none());
 
-        self.waiter.borrow_mut().push(current.clone());
-        if let Some(holder) = self.holder.borrow().as_ref() {
+        let mut state = self.state.lock();
+        state.waiter.push(current.clone());
+        if let Some(holder) = state.holder.as_ref() {
             // kprintln!("... which is held by {}", holder.id());
             holder.add_donator(current.priority());
             current.dependency.lock().replace(holder.clone());
         }
+        drop(state);
 
         self.inner.down();
 
-        let index = self
+        let mut state = self.state.lock();
+        let index = state
             .waiter
-            .borrow_mut()
             .iter()
-            .position(|x| x.id() == thread::current().id())
+            .position(|x| x.id() == current.id())
             .unwrap();
-        self.waiter.borrow_mut().remove(index);
+        state.waiter.remove(index);
 
-        self.waiter.borrow_mut().iter().for_each(|x| {
+        state.waiter.iter().for_each(|x| {
             current.add_donator(x.priority());
             x.dependency.lock().replace(current.clone());
         });
 
         assert!(current.dependency.lock().is_none());
 
-        self.holder.borrow_mut().replace(current);
+        state.holder.replace(current);
+        drop(state);
 
         sbi::interrupt::set(old);
     }
@@ -74,13 +79,11 @@ impl Lock for Sleep {
 
         // kprintln!("thread {} releases the lock. ", current.id());
 
-        assert!(Arc::ptr_eq(
-            self.holder.borrow().as_ref().unwrap(),
-            &current
-        ));
+        let mut state = self.state.lock();
+        assert!(Arc::ptr_eq(state.holder.as_ref().unwrap(), &current));
 
         // kprint!("waiter list: ");
-        self.waiter.borrow_mut().iter().for_each(|x| {
+        state.waiter.iter().for_each(|x| {
             // kprint!("{}, ", x.id());
             current.remove_donator(x.priority());
             x.dependency.lock().take().unwrap();
@@ -89,7 +92,8 @@ impl Lock for Sleep {
 
         assert!(current.dependency.lock().is_none());
 
-        self.holder.borrow_mut().take().unwrap();
+        state.holder.take().unwrap();
+        drop(state);
         self.inner.up();
 
         sbi::interrupt::set(old);
@@ -100,18 +104,51 @@ impl Lock for Sleep {
 impl Lock for Sleep {
     fn acquire(&self) {
         self.inner.down();
-        self.holder.borrow_mut().replace(thread::current());
+        self.state.lock().holder.replace(thread::current());
     }
 
     fn release(&self) {
+        let mut state = self.state.lock();
         assert!(Arc::ptr_eq(
-            self.holder.borrow().as_ref().unwrap(),
+            state.holder.as_ref().unwrap(),
             &thread::current()
         ));
 
-        self.holder.borrow_mut().take().unwrap();
+        state.holder.take().unwrap();
+        drop(state);
         self.inner.up();
     }
 }
 
-unsafe impl Sync for Sleep {}
+impl Sleep {
+    /// Acquires the lock if nobody holds it, without blocking. Returns whether it
+    /// succeeded.
+    #[cfg(feature = "thread-scheduler-priority")]
+    pub fn try_acquire(&self) -> bool {
+        let old = sbi::interrupt::set(false);
+        let current = thread::current();
+
+        let acquired = self.inner.try_down();
+        if acquired {
+            // Threads which are about to wait for it donate to the new holder.
+            let mut state = self.state.lock();
+            state.waiter.iter().for_each(|x| {
+                current.add_donator(x.priority());
+                x.dependency.lock().replace(current.clone());
+            });
+            state.holder.replace(current);
+        }
+
+        sbi::interrupt::set(old);
+        acquired
+    }
+
+    #[cfg(not(feature = "thread-scheduler-priority"))]
+    pub fn try_acquire(&self) -> bool {
+        let acquired = self.inner.try_down();
+        if acquired {
+            self.state.lock().holder.replace(thread::current());
+        }
+        acquired
+    }
+}
@@ -5,9 +5,9 @@ pub mod manager;
 pub mod scheduler;
 pub mod switch;
 
-#[cfg(feature = "thread-scheduler-priority")]
 use crate::sbi;
 use crate::sbi::timer::timer_ticks;
+use crate::sync::Lock;
 
 pub use self::imp::*;
 pub use self::manager::Manager;
@@ -16,16 +16,17 @@ pub(self) use self::scheduler::{Schedule, Scheduler};
 use alloc::sync::Arc;
 
 /// Create a new thread
-pub fn spawn<F>(name: &'static str, f: F) -> Arc<Thread>
+pub fn spawn<F, T>(name: &'static str, f: F) -> JoinHandle<T>
 where
-    F: FnOnce() + Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
 {
     Builder::new(f).name(name).spawn()
 }
 
 /// Get the current running thread
 pub fn current() -> Arc<Thread> {
-    Manager::get().current.lock().clone()
+    Manager::get().current()
 }
 
 /// Yield the control to another thread (if there's another one ready to run).
@@ -36,10 +37,15 @@ pub fn schedule() {
 /// Gracefully shut down the current thread, and schedule another one.
 pub fn exit() -> ! {
     {
-        let current = Manager::get().current.lock();
+        let current = current();
 
         #[cfg(feature = "debug")]
-        kprintln!("Exit: {:?}", *current);
+        kprintln!("Exit: {:?}", current);
+
+        let exited = current.exited.lock().take();
+        if let Some(exited) = exited {
+            exited.up();
+        }
 
         current.set_status(Status::Dying);
     }
@@ -49,6 +55,26 @@ pub fn exit() -> ! {
     unreachable!("An exited thread shouldn't be scheduled again");
 }
 
+/// Called by the panic handler with interrupts turned off. `intr` is the interrupt
+/// setting when the panic happened.
+///
+/// If the panic can be isolated (see [`JoinHandle`]), the current thread exits and
+/// this function never returns. Otherwise it returns, and the kernel should shut down.
+pub fn exit_on_panic(intr: bool) {
+    if !intr || !riscv::register::sstatus::read().sie() {
+        return;
+    }
+
+    if current().exited.lock().is_none() {
+        return;
+    }
+
+    kprintln!("[THREAD] {:?} panicked and exited", current());
+
+    sbi::interrupt::set(true);
+    exit()
+}
+
 /// Mark the current thread as [`Blocked`](Status::Blocked) and
 /// yield the control to another thread
 pub fn block() {
@@ -61,9 +87,31 @@ pub fn block() {
     schedule();
 }
 
+/// Like [`block`], but `lock` is released after the current thread is marked as
+/// [`Blocked`](Status::Blocked), and acquired again once it is woken up.
+///
+/// A waker on another hart, as long as it holds the same lock, will always find the
+/// thread blocked. Interrupts must be turned off, so that the lock won't re-enable them.
+pub fn block_with<L: Lock>(lock: &L) {
+    assert!(!sbi::interrupt::get());
+
+    let current = current();
+    current.set_status(Status::Blocked);
+
+    #[cfg(feature = "debug")]
+    kprintln!("[THREAD] Block {:?}", current);
+
+    drop(current);
+    lock.release();
+    schedule();
+    lock.acquire();
+}
+
 /// Wake up a previously blocked thread, mark it as [`Ready`](Status::Ready),
 /// and register it into the scheduler.
-
+///
+/// If the thread is still being switched out on another hart, waits until it's
+/// done, so that no hart finds a thread in a scheduler before it's off its hart.
 pub fn wake_up(thread: Arc<Thread>) {
     assert_eq!(thread.status(), Status::Blocked);
     thread.set_status(Status::Ready);
@@ -71,7 +119,23 @@ pub fn wake_up(thread: Arc<Thread>) {
     #[cfg(feature = "debug")]
     kprintln!("[THREAD] Wake up {:?}", thread);
 
-    Manager::get().scheduler.lock().register(thread.clone());
+    let old = sbi::interrupt::set(false);
+    let manager = Manager::get();
+
+    // It hasn't been switched out yet, so it simply goes on.
+    if Arc::ptr_eq(&thread, &manager.current()) {
+        thread.set_status(Status::Running);
+        sbi::interrupt::set(old);
+        return;
+    }
+
+    while thread.on_cpu() {
+        core::hint::spin_loop();
+    }
+    manager.scheduler().lock().register(thread.clone());
+    manager.kick_idle();
+
+    sbi::interrupt::set(old);
 
     #[cfg(feature = "thread-scheduler-priority")]
     if thread.priority() > get_priority() {
@@ -94,7 +158,7 @@ pub fn set_priority(p: u32) {
 
     let condition = priority < previous
         && Manager::get()
-            .scheduler
+            .scheduler()
             .lock()
             .next()
             .is_some_and(|thread| priority < thread.priority());
@@ -126,7 +190,13 @@ pub fn sleep(ticks: i64) {
         return;
     }
 
+    // Block before registering, so that the timer (maybe on another hart) won't
+    // find the thread still running and miss it.
+    let old = sbi::interrupt::set(false);
     let start = timer_ticks();
-    Manager::get().register_sleep_thread(current(), start + ticks);
-    block();
+    let current = current();
+    current.set_status(Status::Blocked);
+    Manager::get().register_sleep_thread(current, start + ticks);
+    schedule();
+    sbi::interrupt::set(old);
 }
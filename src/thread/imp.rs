@@ -1,27 +1,58 @@
 //! Implementation of kernel threads
 
 use alloc::boxed::Box;
-use alloc::collections::{BTreeMap, BinaryHeap};
+use alloc::collections::BinaryHeap;
 use alloc::sync::Arc;
 use core::arch::global_asm;
 
 use core::fmt::{self, Debug};
-use core::sync::atomic::{AtomicIsize, AtomicU32, Ordering::SeqCst};
+use core::sync::atomic::{AtomicBool, AtomicIsize, AtomicU32, Ordering::SeqCst};
 
-use crate::fs::File;
-use crate::mem::{kalloc, kfree, PageTable, PG_SIZE};
+use crate::mem::{kalloc, kfree, KernelPgTable, PG_SIZE};
 use crate::sbi::interrupt;
+use crate::smp::tlb::Shootdown;
+use crate::sync::Semaphore;
 use crate::thread::{current, schedule, Manager};
-use crate::userproc::UserProc;
+use crate::userproc::{Children, Process};
 
 pub const PRI_DEFAULT: u32 = 31;
 pub const PRI_MAX: u32 = 63;
 pub const PRI_MIN: u32 = 0;
 pub const STACK_SIZE: usize = PG_SIZE * 4;
-pub const STACK_ALIGN: usize = 16;
 pub const STACK_TOP: usize = 0x80500000;
+/// Written at the bottom of every kernel stack. It's overwritten when the stack
+/// is about to overflow into its guard page.
 pub const MAGIC: usize = 0xdeadbeef;
 
+/// Allocates a kernel stack of [`STACK_SIZE`] bytes, with an unmapped guard page
+/// right below it. Returns the bottom of the stack.
+pub(super) fn alloc_stack() -> usize {
+    let guard = kalloc(PG_SIZE + STACK_SIZE, PG_SIZE) as usize;
+
+    // Lower levels of the kernel page table are shared by all user page tables.
+    KernelPgTable::get()
+        .get_pte_mut(guard)
+        .unwrap()
+        .clean_valid_bit();
+    let mut shootdown = Shootdown::new();
+    shootdown.add(guard, PG_SIZE);
+    shootdown.flush();
+
+    let stack = guard + PG_SIZE;
+    unsafe { (stack as *mut usize).write(MAGIC) };
+    stack
+}
+
+/// Frees a stack from [`alloc_stack`], mapping its guard page back first.
+fn free_stack(stack: usize) {
+    let guard = stack - PG_SIZE;
+    KernelPgTable::get()
+        .get_pte_mut(guard)
+        .unwrap()
+        .set_valid_bit();
+    kfree(guard as *mut _, PG_SIZE + STACK_SIZE, PG_SIZE);
+}
+
 pub type Mutex<T> = crate::sync::Mutex<T, crate::sync::Intr>;
 
 // eraseable binary heap
@@ -50,12 +81,6 @@ impl EBinaryHeap {
     }
 }
 
-#[derive(Clone, Copy)]
-pub enum ChildStatus {
-    Alive,
-    Exited(isize),
-}
-
 /* --------------------------------- Thread --------------------------------- */
 /// All data of a kernel thread
 #[repr(C)]
@@ -66,8 +91,17 @@ pub struct Thread {
     status: Mutex<Status>,
     context: Mutex<Context>,
     priority: AtomicU32,
-    pub userproc: Option<UserProc>,
-    pub pagetable: Option<Mutex<PageTable>>,
+    /// Whether a hart is running on this thread's stack, including the time
+    /// during a switch away from it
+    on_cpu: AtomicBool,
+    /// Signaled when the thread exits, either normally or by a panic. Only threads
+    /// with a [`JoinHandle`] have it.
+    pub(super) exited: Mutex<Option<Arc<Semaphore>>>,
+    /// The user process this thread belongs to
+    pub process: Option<Arc<Process>>,
+    /// Processes started by a kernel thread. Dropped with the thread, along with
+    /// the exit statuses no one has waited for.
+    pub children: Option<Arc<Children>>,
     // lock holder
     // Add Mutex<> only to make rust happy
     #[cfg(feature = "thread-scheduler-priority")]
@@ -80,8 +114,6 @@ pub struct Thread {
     // happen during the modification. Add Mutex<> only to make rust happy.
     #[cfg(feature = "thread-scheduler-priority")]
     pub donated_priorities: Mutex<EBinaryHeap>,
-    pub children: Mutex<BTreeMap<isize, ChildStatus>>,
-    pub descriptors: Mutex<BTreeMap<usize, (File, usize)>>,
 }
 
 impl Thread {
@@ -95,8 +127,7 @@ impl Thread {
         stack: usize,
         priority: u32,
         entry: usize,
-        userproc: Option<UserProc>,
-        pagetable: Option<PageTable>,
+        process: Option<Arc<Process>>,
         id: Option<isize>,
     ) -> Self {
         let tid = match id {
@@ -111,14 +142,14 @@ impl Thread {
             status: Mutex::new(Status::Ready),
             context: Mutex::new(Context::new(stack, entry)),
             priority: AtomicU32::new(priority),
-            userproc,
-            pagetable: pagetable.map(Mutex::new),
+            on_cpu: AtomicBool::new(false),
+            exited: Mutex::new(None),
+            children: process.is_none().then(|| Arc::new(Children::new())),
+            process,
             #[cfg(feature = "thread-scheduler-priority")]
             dependency: Mutex::new(None),
             #[cfg(feature = "thread-scheduler-priority")]
             donated_priorities: Mutex::new(EBinaryHeap::default()),
-            children: Mutex::new(BTreeMap::new()),
-            descriptors: Mutex::new(BTreeMap::new()),
         }
     }
 
@@ -142,6 +173,14 @@ impl Thread {
         (&*self.context.lock()) as *const _ as *mut _
     }
 
+    pub(super) fn on_cpu(&self) -> bool {
+        self.on_cpu.load(SeqCst)
+    }
+
+    pub(super) fn set_on_cpu(&self, on_cpu: bool) {
+        self.on_cpu.store(on_cpu, SeqCst);
+    }
+
     pub fn set_priority(&self, p: u32) {
         self.priority.store(p, SeqCst);
     }
@@ -200,8 +239,15 @@ impl Thread {
         self.donated_priorities.lock().erase(priority);
     }
 
+    /// Bottom of the kernel stack, or `0` for the initial thread, which runs on the
+    /// boot stack.
+    pub fn stack(&self) -> usize {
+        self.stack
+    }
+
+    /// Whether the kernel stack has been used up.
     pub fn overflow(&self) -> bool {
-        unsafe { (self.stack as *const usize).read() != MAGIC }
+        self.stack != 0 && unsafe { (self.stack as *const usize).read() != MAGIC }
     }
 }
 
@@ -221,28 +267,36 @@ impl Drop for Thread {
         #[cfg(feature = "debug")]
         kprintln!("[THREAD] {:?}'s resources are released", self);
 
-        kfree(self.stack as *mut _, STACK_SIZE, STACK_ALIGN);
-        if let Some(pt) = &self.pagetable {
-            unsafe { pt.lock().destroy() };
+        if self.stack != 0 {
+            free_stack(self.stack);
         }
     }
 }
 
 /* --------------------------------- BUILDER -------------------------------- */
-pub struct Builder {
+/// Configures a thread whose main function returns `T`.
+pub struct Builder<T = ()> {
     priority: u32,
     name: &'static str,
     function: usize,
-    userproc: Option<UserProc>,
-    pagetable: Option<PageTable>,
+    process: Option<Arc<Process>>,
     id: Option<isize>,
+    /// Where the main function stores its return value
+    result: Arc<Mutex<Option<T>>>,
 }
 
-impl Builder {
+impl<T: Send + 'static> Builder<T> {
     pub fn new<F>(function: F) -> Self
     where
-        F: FnOnce() + Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
     {
+        let result = Arc::new(Mutex::new(None));
+        let packet = result.clone();
+        let function = move || {
+            let value = function();
+            packet.lock().replace(value);
+        };
+
         // `*mut dyn FnOnce()` is a fat pointer, box it again to ensure FFI-safety.
         let function: *mut Box<dyn FnOnce()> = Box::into_raw(Box::new(Box::new(function)));
 
@@ -250,9 +304,9 @@ impl Builder {
             priority: PRI_DEFAULT,
             name: "Default",
             function: function as usize,
-            userproc: None,
-            pagetable: None,
+            process: None,
             id: None,
+            result,
         }
     }
 
@@ -266,13 +320,8 @@ impl Builder {
         self
     }
 
-    pub fn pagetable(mut self, pagetable: PageTable) -> Self {
-        self.pagetable = Some(pagetable);
-        self
-    }
-
-    pub fn userproc(mut self, userproc: UserProc) -> Self {
-        self.userproc = Some(userproc);
+    pub fn process(mut self, process: Arc<Process>) -> Self {
+        self.process = Some(process);
         self
     }
 
@@ -282,26 +331,28 @@ impl Builder {
     }
 
     pub fn build(self) -> Arc<Thread> {
-        let stack = kalloc(STACK_SIZE, STACK_ALIGN) as usize;
+        let stack = alloc_stack();
 
         Arc::new(Thread::new(
             self.name,
             stack,
             self.priority,
             self.function,
-            self.userproc,
-            self.pagetable,
+            self.process,
             self.id,
         ))
     }
 
     /// Spawns a kernel thread and registers it to the [`Manager`].
-    /// If it will run in the user environment, then two fields
-    /// `userproc` and `pagetable` have to be set properly.
+    /// If it will run in the user environment, then `process` has to be set.
     ///
     /// Note that this function CANNOT be called during [`Manager`]'s initialization.
-    pub fn spawn(self) -> Arc<Thread> {
+    pub fn spawn(self) -> JoinHandle<T> {
+        let result = self.result.clone();
+        let exited = Arc::new(Semaphore::new(0));
+
         let new_thread = self.build();
+        new_thread.exited.lock().replace(exited.clone());
 
         #[cfg(feature = "debug")]
         kprintln!("[THREAD] create {:?}", new_thread);
@@ -312,15 +363,53 @@ impl Builder {
             schedule()
         }
 
-        if let Some(userproc) = new_thread.userproc.as_ref() {
-            userproc
-                .parent
-                .children
-                .lock()
-                .insert(new_thread.id(), ChildStatus::Alive);
-        }
         // Off you go
-        new_thread
+        JoinHandle {
+            thread: new_thread,
+            exited,
+            result,
+        }
+    }
+}
+
+/* ------------------------------- JOIN HANDLE ------------------------------ */
+/// The thread panicked before returning a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Panicked;
+
+/// An owned permission to join on a thread (block on its termination).
+///
+/// Dropping the handle detaches the thread, which goes on running.
+///
+/// A panic in a thread that has a join handle doesn't bring down the kernel, if it
+/// happens with interrupts on (i.e. outside critical sections). The panic is reported,
+/// the thread exits, and [`JoinHandle::join`] returns [`Panicked`]. Since the stack
+/// is not unwound, whatever the thread owns is leaked, including locks it is holding.
+///
+/// ## Examples
+/// ```
+/// let handle = thread::spawn("adder", || 1 + 1);
+/// assert_eq!(handle.join(), Ok(2));
+///
+/// let handle = thread::spawn("bomb", || assert!(false));
+/// assert_eq!(handle.join(), Err(Panicked));
+/// ```
+pub struct JoinHandle<T> {
+    thread: Arc<Thread>,
+    exited: Arc<Semaphore>,
+    result: Arc<Mutex<Option<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// The thread of this handle.
+    pub fn thread(&self) -> &Arc<Thread> {
+        &self.thread
+    }
+
+    /// Blocks until the thread exits, and returns its return value.
+    pub fn join(self) -> Result<T, Panicked> {
+        self.exited.down();
+        self.result.lock().take().ok_or(Panicked)
     }
 }
 
@@ -3,26 +3,65 @@
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use core::mem;
-use core::ops::DerefMut;
+use core::arch::asm;
+use core::sync::atomic::{AtomicIsize, AtomicUsize, Ordering::SeqCst};
+
+use riscv::register::sstatus;
 
 use crate::mem::KernelPgTable;
-use crate::sbi::interrupt;
+use crate::sbi::{interrupt, timer};
+use crate::smp::{self, MAX_HARTS};
 use crate::sync::Lazy;
 use crate::thread::{
-    schedule, switch, Builder, Mutex, Schedule, Scheduler, Status, Thread, PRI_DEFAULT, PRI_MIN,
+    alloc_stack, schedule, switch, Builder, Mutex, Schedule, Scheduler, Status, Thread,
+    PRI_DEFAULT, PRI_MIN, STACK_SIZE,
 };
+use crate::userproc::Process;
 
 #[cfg(feature = "thread-scheduler-priority")]
 use super::get_priority;
 
+/* ---------------------------------- HART ---------------------------------- */
+/// Scheduling states of a hart.
+#[derive(Default)]
+pub struct Hart {
+    /// Threads that are ready to run on this hart. Other harts may steal them.
+    pub scheduler: Mutex<Scheduler>,
+    /// The thread running on this hart
+    current: Mutex<Option<Arc<Thread>>>,
+    /// The thread to run when nothing else is ready. It never enters a scheduler.
+    idle: Mutex<Option<Arc<Thread>>>,
+}
+
+/// Id of the thread running on each hart. Unlike [`Manager::current`], reading it
+/// takes no lock.
+static RUNNING: [AtomicIsize; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INITIAL: AtomicIsize = AtomicIsize::new(0);
+    [INITIAL; MAX_HARTS]
+};
+
+/// Bottom of the kernel stack of the thread running on each hart, or `0` if it's
+/// not known. `trap_entry_k` checks it before pushing a frame, since a frame pushed
+/// onto the guard page would fault again without end.
+#[no_mangle]
+static STACK_LIMIT: [AtomicUsize; MAX_HARTS] = {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INITIAL: AtomicUsize = AtomicUsize::new(0);
+    [INITIAL; MAX_HARTS]
+};
+
+/// Records that `thread` is now running on `hart`.
+fn set_running(hart: usize, thread: &Thread) {
+    RUNNING[hart].store(thread.id(), SeqCst);
+    STACK_LIMIT[hart].store(thread.stack(), SeqCst);
+}
+
 /* --------------------------------- MANAGER -------------------------------- */
-/// Global thread manager, contains a scheduler and a current thread.
+/// Global thread manager, contains a scheduler and a current thread for each hart.
 pub struct Manager {
-    /// Global thread scheduler
-    pub scheduler: Mutex<Scheduler>,
-    /// The current running thread
-    pub current: Mutex<Arc<Thread>>,
+    /// Per-hart states, indexed by hart id
+    harts: [Hart; MAX_HARTS],
     /// All sleeping threads waiting to wake up
     pub sleep_threads: Mutex<BTreeMap<i64, Vec<Arc<Thread>>>>,
     /// All alive and not yet destroyed threads
@@ -32,24 +71,26 @@ pub struct Manager {
 impl Manager {
     pub fn get() -> &'static Self {
         static TMANAGER: Lazy<Manager> = Lazy::new(|| {
-            let initial = Arc::new(Thread::new("Initial", 0, PRI_DEFAULT, 0, None, None, None));
+            let initial = Arc::new(Thread::new("Initial", 0, PRI_DEFAULT, 0, None, None));
 
             initial.set_status(Status::Running);
+            initial.set_on_cpu(true);
 
             let manager = Manager {
-                scheduler: Mutex::new(Scheduler::default()),
+                harts: core::array::from_fn(|_| Hart::default()),
                 all: Mutex::new(Vec::from([initial.clone()])),
-                current: Mutex::new(initial),
                 sleep_threads: Mutex::new(BTreeMap::new()),
             };
 
-            let idle = Builder::new(|| loop {
-                schedule()
-            })
-            .name("Idle")
-            .priority(PRI_MIN)
-            .build();
-            manager.register(idle);
+            let hart = &manager.harts[smp::hart_id()];
+            set_running(smp::hart_id(), &initial);
+            hart.current.lock().replace(initial);
+            hart.idle.lock().replace(
+                Builder::new(|| idle())
+                    .name("Idle")
+                    .priority(PRI_MIN)
+                    .build(),
+            );
 
             manager
         });
@@ -57,12 +98,66 @@ impl Manager {
         &TMANAGER
     }
 
+    /// States of the hart that executes the caller. Interrupts must be turned off
+    /// so that the caller stays on this hart.
+    fn hart(&self) -> &Hart {
+        assert!(!interrupt::get());
+        &self.harts[smp::hart_id()]
+    }
+
+    /// The scheduler of this hart.
+    pub fn scheduler(&self) -> &Mutex<Scheduler> {
+        let old = interrupt::set(false);
+        let scheduler = &self.hart().scheduler;
+        interrupt::set(old);
+        scheduler
+    }
+
+    /// The thread running on this hart.
+    pub fn current(&self) -> Arc<Thread> {
+        let old = interrupt::set(false);
+        let current = self.hart().current.lock().clone().unwrap();
+        interrupt::set(old);
+        current
+    }
+
+    /// Id of the thread running on this hart, without taking any lock. Used by
+    /// [`lockdep`](crate::sync::lockdep), which can't take locks itself.
+    pub fn current_id() -> isize {
+        let old = interrupt::set(false);
+        let id = RUNNING[smp::hart_id()].load(SeqCst);
+        interrupt::set(old);
+        id
+    }
+
+    /// Creates the idle thread of a secondary hart, which is also the first thread
+    /// running on it. Returns the top of its stack, where the hart should start from.
+    pub fn create_idle(&self, hart: usize) -> usize {
+        let stack = alloc_stack();
+        let idle = Arc::new(Thread::new("Idle", stack, PRI_MIN, 0, None, None));
+
+        idle.set_status(Status::Running);
+        idle.set_on_cpu(true);
+
+        set_running(hart, &idle);
+        self.harts[hart].current.lock().replace(idle.clone());
+        self.harts[hart].idle.lock().replace(idle);
+
+        stack + STACK_SIZE
+    }
+
+    /// Undoes [`Manager::create_idle`] if the hart failed to start.
+    pub fn remove_idle(&self, hart: usize) {
+        self.harts[hart].current.lock().take();
+        self.harts[hart].idle.lock().take();
+    }
+
     pub(super) fn register(&self, thread: Arc<Thread>) {
         // Register it into the scheduler
-        self.scheduler.lock().register(thread.clone());
+        self.scheduler().lock().register(thread.clone());
 
         // Store it in all list.
-        self.all.lock().push(thread.clone());
+        self.all.lock().push(thread);
     }
 
     /// register a sleeping thread, then the manager will block it and check the time barrier per tick
@@ -74,9 +169,51 @@ impl Manager {
             .and_modify(|list| list.push(thread.clone()))
             .or_insert(Vec::from([thread]));
         drop(threads_lock);
+
+        // The boot hart may be idle without a tick, and miss the new deadline.
+        if smp::hart_id() != smp::boot_hart() {
+            smp::ipi::kick(smp::boot_hart());
+        }
+
         interrupt::set(old);
     }
 
+    /// Wakes up the boot hart if it's idle. It doesn't tick while idle, so it won't
+    /// steal a thread made ready on another hart by itself.
+    pub fn kick_idle(&self) {
+        let boot = smp::boot_hart();
+        if boot == smp::hart_id() {
+            return;
+        }
+
+        let idle = self.harts[boot].idle.lock().as_ref().map(|idle| idle.id());
+        if idle == Some(RUNNING[boot].load(SeqCst)) {
+            smp::ipi::kick(boot);
+        }
+    }
+
+    /// Removes `thread` from sleeping threads, so that it won't be woken up by the
+    /// timer. Returns `false` if it's not sleeping, e.g. the timer has taken it.
+    pub fn cancel_sleep(&self, thread: &Arc<Thread>) -> bool {
+        let old = interrupt::set(false);
+        let mut threads_lock = self.sleep_threads.lock();
+
+        let found = threads_lock.iter_mut().find_map(|(barrier, list)| {
+            let index = list.iter().position(|t| Arc::ptr_eq(t, thread))?;
+            list.remove(index);
+            Some(*barrier)
+        });
+        if let Some(barrier) = found {
+            if threads_lock[&barrier].is_empty() {
+                threads_lock.remove(&barrier);
+            }
+        }
+
+        drop(threads_lock);
+        interrupt::set(old);
+        found.is_some()
+    }
+
     /// invoked by tick() in timer.rs. unfrozen all ready threads
     pub fn check_sleep_threads(&self) {
         use crate::sbi::timer::timer_ticks;
@@ -103,67 +240,100 @@ impl Manager {
         interrupt::set(old);
     }
 
+    /// Whether `next` should replace `current` on this hart.
+    #[allow(unused_variables)]
+    fn preempts(current: &Thread, next: &Thread) -> bool {
+        #[cfg(feature = "thread-scheduler-priority")]
+        return next.priority() >= get_priority() || current.status() != Status::Running;
+        #[cfg(not(feature = "thread-scheduler-priority"))]
+        return true;
+    }
+
+    /// Takes the next thread from this hart's scheduler. If it has nothing to run,
+    /// steals one from other harts.
+    fn pick_next(&self, current: &Thread) -> Option<Arc<Thread>> {
+        let take = |scheduler: &Mutex<Scheduler>| {
+            let mut scheduler = scheduler.lock();
+            scheduler
+                .next()
+                .filter(|next| Self::preempts(current, next))
+                .and_then(|_| scheduler.schedule())
+        };
+
+        let this = smp::hart_id();
+        if let Some(next) = take(&self.harts[this].scheduler) {
+            return Some(next);
+        }
+
+        (1..MAX_HARTS)
+            .map(|i| (this + i) % MAX_HARTS)
+            .find_map(|other| take(&self.harts[other].scheduler))
+    }
+
     /// Choose a `ready` thread to run if possible. If found, do as follows:
     ///
     /// 1. Turn off intr. Mark the `next` thread as [`Running`](Status::Running) and
-    /// change manager's current thread.
+    ///    change this hart's current thread. If no thread is ready and the current
+    ///    thread can't go on, this hart's idle thread is chosen.
     ///
     /// 2. Forward the `previous` thread to [`schedule_tail`] through [`switch`].
-    /// In [`schedule_tail`], the finishing touches of the schedule is done in the
-    /// new chosen thread, including releasing a dead thread's resources.
+    ///    In [`schedule_tail`], the finishing touches of the schedule is done in the
+    ///    new chosen thread, including releasing a dead thread's resources.
     ///
-    /// 3. Get back from the other thread and restore the intr setting.
+    /// 3. Get back from the other thread (possibly on another hart) and restore
+    ///    the intr setting.
     pub fn schedule(&self) {
         let old = interrupt::set(false);
 
-        let next = self.scheduler.lock().next();
+        let hart = self.hart();
+        let current = hart.current.lock().clone().unwrap();
 
-        // Make sure there's at least one thread runnable.
         assert!(
-            self.current.lock().status() == Status::Running || next.is_some(),
-            "no thread is ready"
+            !current.overflow(),
+            "stack overflow in thread {}",
+            current.name()
         );
 
-        // assert!(
-        //     !self.current.lock().overflow(),
-        //     "Current thread has overflowed its stack."
-        // );
-
-        #[cfg(feature = "thread-scheduler-priority")]
-        let condition = next.clone().is_some_and(|next| {
-            next.priority() >= get_priority() || self.current.lock().status() != Status::Running
-        });
-        #[cfg(not(feature = "thread-scheduler-priority"))]
-        let condition = next.is_some();
-
-        if condition {
-            let next = self.scheduler.lock().schedule().unwrap();
-
-            assert_eq!(next.status(), Status::Ready);
-            next.set_status(Status::Running);
+        let next = match self.pick_next(&current) {
+            Some(next) => next,
+            None if current.status() == Status::Running => {
+                interrupt::set(old);
+                return;
+            }
+            None => hart.idle.lock().clone().unwrap(),
+        };
+
+        // Threads enter schedulers only once they are off their previous harts, see
+        // `wake_up` and `schedule_tail`.
+        assert!(!next.on_cpu());
+        next.set_on_cpu(true);
+
+        assert_ne!(next.status(), Status::Running);
+        next.set_status(Status::Running);
+
+        // Update the current thread to the next running thread
+        let new_ctx = next.context();
+        set_running(smp::hart_id(), &next);
+        let previous = hart.current.lock().replace(next).unwrap();
+        drop(current);
+        #[cfg(feature = "debug")]
+        kprintln!("[THREAD] switch from {:?}", previous);
 
-            // Update the current thread to the next running thread
-            let previous = mem::replace(self.current.lock().deref_mut(), next);
-            #[cfg(feature = "debug")]
-            kprintln!("[THREAD] switch from {:?}", previous);
+        // Retrieve the raw pointers of two threads' context
+        let old_ctx = previous.context();
 
-            // Retrieve the raw pointers of two threads' context
-            let old_ctx = previous.context();
-            let new_ctx = self.current.lock().context();
+        // WARNING: This function call may not return, so don't expect any value to be dropped.
 
-            // WARNING: This function call may not return, so don't expect any value to be dropped.
+        unsafe { switch::switch(Arc::into_raw(previous).cast(), old_ctx, new_ctx) }
 
-            unsafe { switch::switch(Arc::into_raw(previous).cast(), old_ctx, new_ctx) }
-
-            // Back to this location (which `ra` points to), indicating that another thread
-            // has yielded its control or simply exited. Also, it means now the running
-            // thread has been shceudled for more than one time, otherwise it would return
-            // to `kernel_thread_entry` (See `create` where the initial context is set).
-            //
-            // Then, we restore the interrupt setting, and back to where we were before the
-            // scheduling, usually inside a trap handler, a method of semaphore, or anywhere
-            // `schedule` was invoked.
-        }
+        // Back to this location (which `ra` points to), indicating that another thread
+        // has yielded its control or simply exited. Also, it means now the running
+        // thread has been shceudled for more than one time, otherwise it would return
+        // to `kernel_thread_entry` (See `create` where the initial context is set).
+        //
+        // Then, we restore the interrupt setting, and back to where we were before the
+        // scheduling, usually inside a trap handler, a method of semaphore, or anywhere
+        // `schedule` was invoked.
 
         interrupt::set(old);
     }
@@ -176,29 +346,57 @@ impl Manager {
     pub fn schedule_tail(&self, previous: Arc<Thread>) {
         assert!(!interrupt::get());
 
+        let hart = self.hart();
+
         #[cfg(feature = "debug")]
-        kprintln!("[THREAD] switch to {:?}", *self.current.lock());
+        kprintln!(
+            "[THREAD] switch to {:?}",
+            hart.current.lock().as_ref().unwrap()
+        );
 
-        match previous.status() {
+        let is_idle = hart
+            .idle
+            .lock()
+            .as_ref()
+            .map_or(false, |idle| Arc::ptr_eq(idle, &previous));
+
+        let runnable = match previous.status() {
             Status::Dying => {
                 // A thread's resources should be released at this point
                 self.all.lock().retain(|t| t.id() != previous.id());
+                false
             }
             Status::Running => {
                 previous.set_status(Status::Ready);
-                self.scheduler.lock().register(previous);
+                !is_idle
             }
-            Status::Blocked => {}
-            Status::Ready => unreachable!(),
+            // A blocked thread is registered by `wake_up` once it's off this hart.
+            Status::Blocked | Status::Ready => false,
+        };
+
+        // Switch the address space before `previous` may take its own away.
+        match hart.current.lock().as_ref().unwrap().process.as_ref() {
+            Some(process) => process.pagetable.lock().activate(),
+            None => KernelPgTable::get().activate(),
         }
 
-        if let Some(pt) = self.current.lock().pagetable.as_ref() {
-            pt.lock().activate();
-        } else {
-            KernelPgTable::get().activate();
+        // Other harts may run `previous` from now on.
+        previous.set_on_cpu(false);
+        if runnable {
+            hart.scheduler.lock().register(previous);
         }
     }
 
+    /// Finds a user process by its pid, through the threads belonging to it.
+    pub fn get_process(&self, pid: isize) -> Option<Arc<Process>> {
+        self.all
+            .lock()
+            .iter()
+            .filter_map(|thread| thread.process.as_ref())
+            .find(|process| process.pid() == pid)
+            .cloned()
+    }
+
     pub fn get_by_id(&self, id: isize) -> Option<Arc<Thread>> {
         self.all
             .lock()
@@ -207,3 +405,48 @@ impl Manager {
             .map(|x| x.clone())
     }
 }
+
+/// Body of idle threads.
+///
+/// Once nothing is ready to run, the hart halts with `wfi` until an interrupt
+/// arrives. The boot hart also stops its tick, and only wakes up at the earliest
+/// deadline of sleeping threads (see [`timer::stop_tick`]). Other harts keep ticking,
+/// so that they can steal threads from busy harts.
+pub fn idle() -> ! {
+    let manager = Manager::get();
+
+    loop {
+        schedule();
+
+        // With `sstatus.SIE` cleared, a pending interrupt still wakes up `wfi` but
+        // is not taken until `SIE` is set again. Therefore, nothing can be made ready
+        // between the check and `wfi` unnoticed.
+        unsafe { sstatus::clear_sie() };
+        let old = interrupt::set(true);
+
+        let hart = smp::hart_id();
+        let tickless = hart == smp::boot_hart();
+        let halt = manager.harts[hart].scheduler.lock().next().is_none();
+
+        if halt {
+            if tickless {
+                let deadline = manager.sleep_threads.lock().keys().next().copied();
+                timer::stop_tick(deadline);
+            }
+
+            unsafe { asm!("wfi") };
+
+            if tickless {
+                // Also clears the pending timer interrupt.
+                timer::restart_tick();
+            }
+        }
+
+        interrupt::set(old);
+        unsafe { sstatus::set_sie() };
+
+        if halt && tickless {
+            manager.check_sleep_threads();
+        }
+    }
+}
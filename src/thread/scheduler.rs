@@ -17,6 +17,12 @@ pub type Scheduler = self::pirority::Priority;
 #[cfg(not(feature = "thread-scheduler-priority"))]
 pub type Scheduler = self::fcfs::Fcfs;
 
+/// Name of the built-in scheduler, as accepted by the `sched=` boot option.
+#[cfg(feature = "thread-scheduler-priority")]
+pub const NAME: &str = "priority";
+#[cfg(not(feature = "thread-scheduler-priority"))]
+pub const NAME: &str = "fcfs";
+
 /// Basic functionalities of thread schedulers
 pub trait Schedule: Default {
     /// Notify the scheduler that a thread is able to run. Then, this thread
@@ -1,12 +1,16 @@
 //! Trap handler
 //!
 
+mod fault;
 mod pagefault;
 mod syscall;
 
 use crate::device::{plic, virtio};
+use crate::mem::PG_SIZE;
 use crate::sbi;
+use crate::smp;
 use crate::thread;
+use crate::userproc;
 use core::arch;
 
 use riscv::register::scause::{Exception::*, Interrupt::*, Trap::*};
@@ -27,6 +31,10 @@ pub struct Frame {
     pub sstatus: Sstatus,
     /// CSR sepc.
     pub sepc: usize,
+    /// Kernel `tp` (i.e. the hart id) saved on return to user mode.
+    pub ktp: usize,
+    /// Keeps the frame 16-byte aligned.
+    _pad: usize,
 }
 
 pub fn set_strap_entry() {
@@ -65,7 +73,7 @@ pub extern "C" fn trap_handler(frame: &mut Frame) {
             unsafe { riscv::register::sstatus::set_sie() };
             // Increase sepc by 1 to skip ecall.
             frame.sepc += 4;
-            frame.x[10] = syscall::syscall_handler(id, args) as usize;
+            frame.x[10] = syscall::syscall_handler(id, args, frame) as usize;
         }
 
         Interrupt(SupervisorTimer) => {
@@ -74,6 +82,8 @@ pub extern "C" fn trap_handler(frame: &mut Frame) {
             thread::schedule();
         }
 
+        Interrupt(SupervisorSoft) => smp::ipi::handle(),
+
         Interrupt(SupervisorExternal) => {
             unsafe {
                 // Get the interrupt source.
@@ -93,17 +103,15 @@ pub extern "C" fn trap_handler(frame: &mut Frame) {
             }
         }
 
-        Exception(InstructionFault) | Exception(IllegalInstruction) => {
-            // TODO: kill user process but not panic kernel
-            panic!("Instruction failure");
-        }
-
         Exception(f @ LoadPageFault)
         | Exception(f @ StorePageFault)
         | Exception(f @ InstructionPageFault) => {
             pagefault::handler(frame, f, stval);
         }
 
+        // Any other exception of a user thread is its own fault.
+        Exception(f) if frame.sstatus.spp() == SPP::User => fault::handler(frame, f, stval),
+
         _ => {
             unimplemented!(
                 "Unsupported trap {:?}, stval={:#x}, sepc={:#x}",
@@ -114,10 +122,33 @@ pub extern "C" fn trap_handler(frame: &mut Frame) {
         }
     }
 
+    if frame.sstatus.spp() == SPP::User {
+        unsafe { riscv::register::sstatus::set_sie() };
+
+        // Another thread has exited the process.
+        if userproc::exiting() {
+            userproc::thread_exit(-1);
+        }
+
+        // Deliver a signal before `trap_exit_u` returns to the user.
+        userproc::signal::deliver(frame);
+    }
+
     #[cfg(feature = "debug")]
     kprintln!("[TRAP] exit");
 }
 
+/// Size of the spare stack of each hart, where [`stack_overflow`] runs. It must be
+/// a power of two.
+const OVERFLOW_STACK_SIZE: usize = 2 * PG_SIZE;
+
+/// Entered from `trap_entry_k` when the kernel stack of the current thread can't
+/// hold a trap frame, which means it has overflowed into its guard page.
+#[no_mangle]
+extern "C" fn stack_overflow() -> ! {
+    panic!("stack overflow in thread {}", thread::current().name());
+}
+
 extern "C" {
     pub fn trap_entry_u();
     pub fn trap_exit_u();
@@ -143,7 +174,7 @@ arch::global_asm! {r#"
     # See the comments in `trap_exit_u` for details of `sscratch` management.
 
     # (2) Stores Frame on kernel stack.
-        addi sp, sp, -34*8
+        addi sp, sp, -36*8
 
     # (2.1) Save general purpose regs except sp and x0.
         # sd x0, 0*8(sp)
@@ -193,6 +224,9 @@ arch::global_asm! {r#"
         sd t1, 33*8(sp)
         sd t2,  2*8(sp)  # save to `x2`
 
+    # (2.4) Restore kernel `tp` (the hart id), which the user may have changed.
+        ld tp, 34*8(sp)
+
     # (3) Call trap handler.
     # Must use `call`.
         mv   a0, sp   # pass frame
@@ -223,6 +257,10 @@ arch::global_asm! {r#"
         la t0, trap_entry_u
         csrw stvec, t0
 
+    # (2.1) Save kernel `tp`. The thread may be scheduled to another hart
+    # during the trap, so it's saved on every exit rather than at entry.
+        sd tp, 34*8(sp)
+
     # (3) Restore general-purpose regs.
         # ld x0, 0*8(sp)
         ld x1,   1*8(sp)
@@ -258,7 +296,7 @@ arch::global_asm! {r#"
         ld x31, 31*8(sp)
 
     # (4) Restore kernel stack (pop frame).
-        addi sp, sp, 34*8
+        addi sp, sp, 36*8
 
     # (5) Restore `sscratch` and user stack.
         csrrw sp, sscratch, sp
@@ -268,7 +306,7 @@ arch::global_asm! {r#"
     # It might be wondered that whether `sscratch` can remain correct during
     # context switch. We rely on four principles:
     #  - In U-mode, `sscratch` can not be touched;
-    #  - In K-mode, `sscratch` is not used;
+    #  - In K-mode, `sscratch` is only a scratch register at `trap_entry_k`;
     #  - K-mode doesn't rely on `sscratch` to switch `sp` (saved by Context);
     #  - When switching from a U-proc to another U-proc, it first enters trap_entry_u.
     # Based on these principles, it is fine that we just restore `sscratch` at
@@ -291,7 +329,20 @@ arch::global_asm! {r#"
     # https://five-embeddev.com/riscv-isa-manual/latest/supervisor.html#supervisor-trap-vector-base-address-register-stvec
 
     trap_entry_k:
-        addi sp, sp, -34*8
+    # Check that the frame fits in the current kernel stack. Below it lies an
+    # unmapped guard page, and pushing the frame there would trap again and again.
+    # `sscratch` is free to use in K-mode, see `trap_exit_u`.
+        csrw sscratch, t0
+        la   t0, STACK_LIMIT
+        slli tp, tp, 3
+        add  t0, t0, tp
+        srli tp, tp, 3
+        ld   t0, 0(t0)
+        addi t0, t0, 36*8
+        bltu sp, t0, stack_overflow_k
+        csrr t0, sscratch
+
+        addi sp, sp, -36*8
 
     # save general-purpose registers
         # sd x0, 0*8(sp)
@@ -350,11 +401,13 @@ arch::global_asm! {r#"
         csrw stvec, t0
 
     # load general-purpose registers
+    # `tp` is not restored, since the thread may be scheduled to another hart
+    # during the trap.
         # ld x0, 0*8(sp)
         ld x1,   1*8(sp)
         # ld x2, 2*8(sp)
         ld x3,   3*8(sp)
-        ld x4,   4*8(sp)
+        # ld x4, 4*8(sp)
         ld x5,   5*8(sp)
         ld x6,   6*8(sp)
         ld x7,   7*8(sp)
@@ -383,6 +436,23 @@ arch::global_asm! {r#"
         ld x30, 30*8(sp)
         ld x31, 31*8(sp)
 
-        addi sp, sp, 34*8
+        addi sp, sp, 36*8
         sret
-"#}
+
+    # Report the overflow on the spare stack of this hart. It never returns.
+    stack_overflow_k:
+        la   sp, overflow_stacks
+        addi t0, tp, 1
+        slli t0, t0, {overflow_stack_shift}
+        add  sp, sp, t0
+        call stack_overflow
+
+    .section .bss
+    .align 4
+    overflow_stacks:
+        .space {overflow_stack_size} * {harts}
+"#,
+    overflow_stack_size = const OVERFLOW_STACK_SIZE,
+    overflow_stack_shift = const OVERFLOW_STACK_SIZE.trailing_zeros(),
+    harts = const smp::MAX_HARTS,
+}
@@ -0,0 +1,41 @@
+//! Faults of user threads other than page faults, such as illegal instructions,
+//! misaligned or denied accesses, and breakpoints.
+//!
+//! A fault is raised as a synchronous signal (see [`signal::catch`]). If the
+//! process doesn't handle it, only the process is terminated, with the exit
+//! status [`signal::killed`] of the signal, like any process terminated by a
+//! signal.
+
+use riscv::register::scause::Exception::{self, *};
+use riscv::register::sstatus;
+
+use crate::thread;
+use crate::trap::Frame;
+use crate::userproc::{self, signal};
+
+pub fn handler(frame: &mut Frame, fault: Exception, addr: usize) {
+    let signal = match fault {
+        IllegalInstruction => signal::SIGILL,
+        Breakpoint => signal::SIGTRAP,
+        InstructionMisaligned | StoreMisaligned => signal::SIGBUS,
+        InstructionFault | LoadFault | StoreFault => signal::SIGSEGV,
+        // Misaligned loads are reported as unknown by the `riscv` crate.
+        _ => signal::SIGBUS,
+    };
+
+    if signal::catch(frame, signal) {
+        return;
+    }
+
+    unsafe { sstatus::set_sie() };
+    klog!(
+        Info,
+        "User thread {} dying due to {:?} (signal {}), sepc={:#x}, stval={:#x}.",
+        thread::current().name(),
+        fault,
+        signal,
+        frame.sepc,
+        addr,
+    );
+    userproc::exit(signal::killed(signal));
+}
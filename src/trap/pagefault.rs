@@ -1,35 +1,95 @@
-use crate::mem::userbuf::{
-    __knrl_read_usr_byte, __knrl_read_usr_exit, __knrl_write_usr_byte, __knrl_write_usr_exit,
-};
-use crate::mem::{FrameTable, KernelPgTable, PTEFlags, PageAlign, PhysAddr, PG_SIZE};
-use crate::thread::{self, Mutex};
+use crate::mem::{replace, userbuf, FrameTable, KernelPgTable, PageAlign};
+use crate::thread;
 use crate::trap::Frame;
-use crate::userproc;
+use crate::userproc::{self, heap, stack, vma, vma::Access, Process};
+use crate::Result;
 
 use riscv::register::scause::Exception::{self, *};
 use riscv::register::sstatus::{self, SPP};
 
+/// Maps the page at `addr` of `process`, if it's in swap, or it's a heap page, a
+/// stack page or an anonymous page not allocated yet. `sp` is the one of a user
+/// fault.
+fn populate(process: &Process, addr: usize, sp: Option<usize>, access: Access) -> Result<bool> {
+    // Taken before any lock of the process, since making room may write a page to
+    // swap, which sleeps.
+    let frame = FrameTable::alloc_page()?;
+
+    let page = addr.floor();
+    let populated = FrameTable::swap_in(
+        &process.pagetable,
+        frame,
+        process.pid(),
+        page,
+        access.flag(),
+    ) || access != Access::Execute
+        && (heap::populate(process, addr, frame) || stack::populate(process, addr, sp, frame))
+        || vma::populate(process, addr, access, frame);
+    if !populated {
+        FrameTable::dealloc_page(frame);
+    }
+    Ok(populated)
+}
+
 pub fn handler(frame: &mut Frame, fault: Exception, addr: usize) {
     let privilege = frame.sstatus.spp();
     let sp = frame.x[2];
 
     let current = thread::current();
 
-    // does this fault trigger a stack growth?
-    if addr == sp && fault == StorePageFault {
-        let mut table = match &current.pagetable {
-            Some(table) => table.lock(),
-            None => unreachable!(),
-        };
-        let flags = PTEFlags::V | PTEFlags::R | PTEFlags::W | PTEFlags::U;
-        let page_begin = sp.floor();
-        let stack_va = unsafe { FrameTable::alloc_page(current.id(), page_begin, true, flags) };
-        table.map(PhysAddr::from(stack_va), page_begin, PG_SIZE, flags);
-        return;
+    // A user stack has run into the guard page below it.
+    if privilege == SPP::User && userproc::in_stack_guard(addr) {
+        unsafe { sstatus::set_sie() };
+        kprintln!("stack overflow in thread {}", current.name());
+        drop(current);
+        userproc::exit(userproc::signal::killed(userproc::signal::SIGSEGV));
     }
 
-    let present = {
-        let pt = current.pagetable.as_ref().map(Mutex::lock);
+    let access = match fault {
+        StorePageFault => Access::Write,
+        LoadPageFault => Access::Read,
+        _ => Access::Execute,
+    };
+
+    // Is it a page in swap, or a heap page, an anonymous page or a stack page not
+    // allocated yet?
+    if let Some(process) = current.process.as_ref() {
+        replace::STATS.fault();
+        // `sp` of the kernel has nothing to do with the user stack.
+        let user_sp = (privilege == SPP::User).then_some(sp);
+        match populate(process, addr, user_sp, access) {
+            Ok(true) => return,
+            Ok(false) => {}
+            // Out of memory, and a process has been killed to make room. Unless it's
+            // this one, the access is retried after others have run.
+            Err(_) if !process.exiting() => {
+                drop(current);
+                thread::schedule();
+                return;
+            }
+            // This process is killed. A user thread leaves on its way back to the
+            // user, and an access of the kernel fails below.
+            Err(_) if privilege == SPP::User => return,
+            Err(_) => {}
+        }
+    }
+
+    // The kernel failed to access user memory, which the routine reports itself.
+    if privilege == SPP::Supervisor {
+        if let Some(fixup) = userbuf::fixup(frame.sepc) {
+            frame.sepc = fixup;
+            return;
+        }
+    }
+
+    // A page of an area is not present only if it's not allocated yet, so a
+    // fault left in an area is always a protection fault.
+    let in_area = current
+        .process
+        .as_ref()
+        .is_some_and(|process| vma::contains(process, addr));
+    let present = in_area || {
+        let pt = current.process.as_ref().map(|p| p.pagetable.lock());
         let table = pt.as_deref().unwrap_or(KernelPgTable::get());
         match table.get_pte(addr) {
             Some(entry) => entry.is_valid(),
@@ -57,24 +117,18 @@ pub fn handler(frame: &mut Frame, fault: Exception, addr: usize) {
 
     match privilege {
         SPP::Supervisor => {
-            if frame.sepc == __knrl_read_usr_byte as _ {
-                // Failed to read user byte from kernel space when trap in pagefault
-                frame.x[11] = 1; // set a1 to non-zero
-                frame.sepc = __knrl_read_usr_exit as _;
-            } else if frame.sepc == __knrl_write_usr_byte as _ {
-                // Failed to write user byte from kernel space when trap in pagefault
-                frame.x[11] = 1; // set a1 to non-zero
-                frame.sepc = __knrl_write_usr_exit as _;
-            } else {
-                panic!("Kernel page fault");
-            }
+            panic!("Kernel page fault");
         }
         SPP::User => {
-            kprintln!(
+            if userproc::signal::catch(frame, userproc::signal::SIGSEGV) {
+                return;
+            }
+            klog!(
+                Info,
                 "User thread {} dying due to page fault.",
                 thread::current().name()
             );
-            userproc::exit(-1);
+            userproc::exit(userproc::signal::killed(userproc::signal::SIGSEGV));
         }
     }
 }
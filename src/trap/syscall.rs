@@ -7,9 +7,8 @@
 /*                               SYSCALL NUMBER                               */
 /* -------------------------------------------------------------------------- */
 
-use core::slice::{from_raw_parts, from_raw_parts_mut};
-
-use alloc::{string::String, vec::Vec};
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::mem::size_of;
 
 use crate::{
     fs::{
@@ -17,10 +16,18 @@ use crate::{
         FileSys,
     },
     io::{Read, Seek, SeekFrom, Write},
+    mem::{
+        userbuf::{copy_from_user, copy_to_user, read_user, read_user_str, write_user},
+        PG_SIZE,
+    },
     sbi::{console_getchar, shutdown},
     thread::current,
-    userproc::{execute, exit, wait},
-    OsError,
+    trap::Frame,
+    userproc::{
+        execute, exit, futex, heap, signal, stack, thread_create, thread_exit, thread_join, vma,
+        wait, waitpid, Process,
+    },
+    OsError, Result,
 };
 
 const SYS_HALT: usize = 1;
@@ -35,12 +42,39 @@ const SYS_SEEK: usize = 9;
 const SYS_TELL: usize = 10;
 const SYS_CLOSE: usize = 11;
 const SYS_FSTAT: usize = 12;
-
-pub fn syscall_handler(_id: usize, _args: [usize; 3]) -> isize {
+const SYS_THREAD_CREATE: usize = 17;
+const SYS_THREAD_EXIT: usize = 18;
+const SYS_THREAD_JOIN: usize = 19;
+const SYS_FUTEX_WAIT: usize = 20;
+const SYS_FUTEX_WAKE: usize = 21;
+const SYS_KILL: usize = 22;
+const SYS_SIGACTION: usize = 23;
+const SYS_SIGRETURN: usize = 24;
+const SYS_WAITPID: usize = 25;
+const SYS_EXECVE: usize = 26;
+const SYS_BRK: usize = 27;
+const SYS_SBRK: usize = 28;
+const SYS_MMAP_ANON: usize = 29;
+const SYS_MUNMAP_RANGE: usize = 30;
+const SYS_MPROTECT: usize = 31;
+const SYS_GETRLIMIT: usize = 32;
+const SYS_SETRLIMIT: usize = 33;
+
+/// `frame` is the user context, which only [`signal::sigreturn`] touches.
+pub fn syscall_handler(_id: usize, _args: [usize; 3], frame: &mut Frame) -> isize {
     match _id {
         SYS_HALT => halt(),
         SYS_EXIT => exit(_args[0] as isize),
-        SYS_EXEC => raw_execute_handler(_args),
+        SYS_EXEC => raw_execute_handler([_args[0], _args[1], 0]),
+        SYS_EXECVE => raw_execute_handler(_args),
+        SYS_BRK => heap::brk(_args[0]),
+        SYS_SBRK => heap::sbrk(_args[0] as isize),
+        // The fourth argument is in `a3`.
+        SYS_MMAP_ANON => vma::mmap(_args[0], _args[1], _args[2], frame.x[13]),
+        SYS_MUNMAP_RANGE => vma::munmap(_args[0], _args[1]),
+        SYS_MPROTECT => vma::mprotect(_args[0], _args[1], _args[2]),
+        SYS_GETRLIMIT => getrlimit(_args[0], _args[1]),
+        SYS_SETRLIMIT => setrlimit(_args[0], _args[1]),
         SYS_WAIT => wait(_args[0] as isize).unwrap_or(-1),
         SYS_OPEN => open(_args[0], _args[1]),
         SYS_CLOSE => close(_args[0]),
@@ -50,10 +84,24 @@ pub fn syscall_handler(_id: usize, _args: [usize; 3]) -> isize {
         SYS_SEEK => seek(_args[0], _args[1]),
         SYS_TELL => tell(_args[0]),
         SYS_FSTAT => fstat(_args[0], _args[1]),
+        SYS_THREAD_CREATE => thread_create(_args[0], _args[1]),
+        SYS_THREAD_EXIT => thread_exit(_args[0] as isize),
+        SYS_THREAD_JOIN => thread_join(_args[0] as isize).unwrap_or(-1),
+        SYS_FUTEX_WAIT => futex::wait(_args[0], _args[1] as u32, _args[2] as i32 as i64),
+        SYS_FUTEX_WAKE => futex::wake(_args[0], _args[1]),
+        SYS_KILL => signal::kill(_args[0] as isize, _args[1]),
+        SYS_SIGACTION => signal::sigaction(_args[0], _args[1], _args[2]),
+        SYS_SIGRETURN => signal::sigreturn(frame),
+        SYS_WAITPID => raw_waitpid(_args[0] as isize, _args[1], _args[2]),
         _ => -1,
     }
 }
 
+/// The process of the calling thread.
+fn process() -> Arc<Process> {
+    current().process.clone().unwrap()
+}
+
 fn halt() -> ! {
     kprintln!("Goodbye, World!");
     shutdown()
@@ -98,7 +146,7 @@ fn open(ptr: usize, flag: usize) -> isize {
     }
 
     let id: Path = file_name.as_str().into();
-    let exist = DISKFS.get().root_dir.lock().exists(&id);
+    let exist = DISKFS.get().root_dir.read().exists(&id);
 
     let result = {
         if has!(flag, O_TRUNC) || (!exist && has!(flag, O_CREATE)) {
@@ -112,8 +160,8 @@ fn open(ptr: usize, flag: usize) -> isize {
 
     let file = unwrap!(result.ok());
 
-    let current = current();
-    let mut descriptors = current.descriptors.lock();
+    let process = process();
+    let mut descriptors = process.descriptors.lock();
     let id = descriptors
         .last_key_value()
         .map(|(k, _)| *k + 1)
@@ -129,7 +177,7 @@ fn close(fd: usize) -> isize {
         return 0;
     }
 
-    match current().descriptors.lock().remove(&fd) {
+    match process().descriptors.lock().remove(&fd) {
         Some((file, _)) => {
             kprintln!("closing...");
             DISKFS.get().close(file);
@@ -139,64 +187,77 @@ fn close(fd: usize) -> isize {
     }
 }
 
-fn read(fd: usize, buffer: usize, size: usize) -> isize {
-    if size == 0 {
-        return 0;
-    }
+/// Data is moved between the file and the user in chunks of this size, through a
+/// kernel buffer, so that the user buffer is never touched with a lock held.
+const CHUNK: usize = PG_SIZE;
 
-    // validate buffer pointer
-    unwrap!(Pointer::<u8>::from(buffer).check());
-    unwrap!(Pointer::<u8>::from(buffer + size - 1).check());
+fn read(fd: usize, buffer: usize, size: usize) -> isize {
+    let mut buf = vec![0; size.min(CHUNK)];
+    let mut done = 0;
 
-    let mut ptr = buffer;
+    loop {
+        let chunk = &mut buf[..(size - done).min(CHUNK)];
 
-    unsafe {
         // read from stdin?
-        if fd == 0 {
-            for _ in 0..size {
-                *(ptr as *mut u8) = console_getchar() as u8;
-                ptr += 1;
+        let n = if fd == STDIN {
+            for byte in chunk.iter_mut() {
+                *byte = console_getchar() as u8;
             }
-            size as isize
+            chunk.len()
         } else {
-            let current = current();
-            let mut descriptor = current.descriptors.lock();
+            let process = process();
+            let mut descriptor = process.descriptors.lock();
 
             let result = descriptor.get_mut(&fd).and_then(|(file, flag)| {
                 if has!(*flag, O_WRONLY) {
                     None
                 } else {
-                    file.read(from_raw_parts_mut(ptr as *mut u8, size)).ok()
+                    file.read(chunk).ok()
                 }
             });
 
-            unwrap!(result) as isize
+            unwrap!(result)
+        };
+
+        unwrap!(copy_to_user(buffer + done, &chunk[..n]).ok());
+        done += n;
+        if done == size || n < chunk.len() {
+            return done as isize;
         }
     }
 }
 
 fn write(fd: usize, buffer: usize, size: usize) -> isize {
-    unwrap!(Pointer::<u8>::from(buffer).check());
-    unwrap!(Pointer::<u8>::from(buffer + size - 1).check());
-
-    if fd == STDOUT || fd == STDERR {
-        let mut str = unwrap!(get_str(buffer));
-        str.truncate(size);
-        kprint!("{}", str);
-        str.len() as isize
-    } else {
-        let current = current();
-        let mut descriptor = current.descriptors.lock();
-
-        let result = descriptor.get_mut(&fd).and_then(|(file, flag)| {
-            if has!(*flag, O_WRONLY) || has!(*flag, O_RDWR) {
-                unsafe { file.write(from_raw_parts(buffer as *mut u8, size)).ok() }
-            } else {
-                None
-            }
-        });
+    let mut buf = vec![0; size.min(CHUNK)];
+    let mut done = 0;
+
+    loop {
+        let chunk = &mut buf[..(size - done).min(CHUNK)];
+        unwrap!(copy_from_user(chunk, buffer + done).ok());
 
-        unwrap!(result) as isize
+        let n = if fd == STDOUT || fd == STDERR {
+            let str: String = chunk.iter().map(|&byte| char::from(byte)).collect();
+            kprint!("{}", str);
+            chunk.len()
+        } else {
+            let process = process();
+            let mut descriptor = process.descriptors.lock();
+
+            let result = descriptor.get_mut(&fd).and_then(|(file, flag)| {
+                if has!(*flag, O_WRONLY) || has!(*flag, O_RDWR) {
+                    file.write(chunk).ok()
+                } else {
+                    None
+                }
+            });
+
+            unwrap!(result)
+        };
+
+        done += n;
+        if done == size || n < chunk.len() {
+            return done as isize;
+        }
     }
 }
 
@@ -207,8 +268,8 @@ fn remove(ptr: usize) -> isize {
 }
 
 fn seek(fd: usize, position: usize) -> isize {
-    let current = current();
-    let mut descriptor = current.descriptors.lock();
+    let process = process();
+    let mut descriptor = process.descriptors.lock();
 
     let result = descriptor
         .get_mut(&fd)
@@ -218,8 +279,8 @@ fn seek(fd: usize, position: usize) -> isize {
 }
 
 fn tell(fd: usize) -> isize {
-    let current = current();
-    let mut descriptor = current.descriptors.lock();
+    let process = process();
+    let mut descriptor = process.descriptors.lock();
 
     let result = descriptor
         .get_mut(&fd)
@@ -229,42 +290,64 @@ fn tell(fd: usize) -> isize {
 }
 
 fn fstat(fd: usize, ptr: usize) -> isize {
-    unwrap!(Pointer::<usize>::from(ptr).check());
-    unwrap!(Pointer::<usize>::from(ptr + 8usize).check());
+    let stat = {
+        let process = process();
+        let mut descriptor = process.descriptors.lock();
+        let (file, _) = unwrap!(descriptor.get_mut(&fd));
+        [file.ino(), unwrap!(file.len().ok())]
+    };
 
-    let current = current();
-    let mut descriptor = current.descriptors.lock();
-    let (file, _) = unwrap!(descriptor.get_mut(&fd));
+    unwrap!(write_user(ptr, &stat).ok());
+    0
+}
 
-    unsafe {
-        *(ptr as *mut usize) = file.ino();
-        *((ptr + 8) as *mut usize) = unwrap!(file.len().ok());
+/// Waits like [`waitpid`], and stores the exit status at `status` unless it's null.
+fn raw_waitpid(pid: isize, status: usize, options: usize) -> isize {
+    // Check it before a child is reaped, since the status would be lost.
+    if status != 0 {
+        unwrap!(read_user::<i32>(status).ok());
     }
 
-    0
+    let (pid, exit_value) = unwrap!(waitpid(pid, options));
+    if status != 0 && pid != 0 {
+        unwrap!(write_user(status, &(exit_value as i32)).ok());
+    }
+    pid
 }
 
-fn raw_execute_handler(_args: [usize; 3]) -> isize {
-    let file_name = unwrap!(get_str(_args[0]));
-    let mut ptr = _args[1];
-    let mut argv = Vec::new();
+/// Limits of a resource, as laid out by the user.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Rlimit {
+    cur: usize,
+    max: usize,
+}
 
-    loop {
-        let argv_ptr = unwrap!(Pointer::<usize>::from(ptr).take());
+fn getrlimit(resource: usize, rlimit: usize) -> isize {
+    let (cur, max) = unwrap!(stack::getrlimit(resource));
+    unwrap!(write_user(rlimit, &Rlimit { cur, max }).ok());
+    0
+}
 
-        if argv_ptr == 0 {
-            break;
-        }
+fn setrlimit(resource: usize, rlimit: usize) -> isize {
+    let Rlimit { cur, max } = unwrap!(read_user(rlimit).ok());
+    stack::setrlimit(resource, cur, max)
+}
 
-        argv.push(unwrap!(get_str(argv_ptr)));
-        ptr += 8;
-    }
+/// Executes `_args[0]` with the argument array `_args[1]` and the environment
+/// array `_args[2]`. A null environment means an empty one.
+fn raw_execute_handler(_args: [usize; 3]) -> isize {
+    let file_name = unwrap!(get_str(_args[0]));
+    let (argv, envp) = match get_args(_args[1], _args[2]) {
+        Ok(args) => args,
+        Err(err) => return err as isize,
+    };
 
     kprintln!("prog to execute: {}.", file_name);
     let result = DISKFS.get().open(file_name.as_str().into());
 
     match result {
-        Ok(file) => execute(file, argv),
+        Ok(file) => execute(file, argv, envp),
         Err(err) => {
             kprintln!("file system err: {:?}", err);
             -1
@@ -272,43 +355,51 @@ fn raw_execute_handler(_args: [usize; 3]) -> isize {
     }
 }
 
-fn get_str(mut ptr: usize) -> Option<String> {
-    let mut str: Vec<char> = Vec::new();
-    loop {
-        let lastchar = Pointer::<u8>::from(ptr).take()?;
-        if lastchar == 0u8 {
-            break;
-        }
-        str.push(lastchar as char);
-        ptr += 1
-    }
-    Some(str.iter().collect())
+/// Reads the argument and environment arrays of `exec`. A null environment means
+/// an empty one.
+///
+/// ## Return
+/// - `Err(ArgumentTooLong)`: As soon as the arrays can't fit in the initial user
+///   stack, which is one page, so that a huge array is never copied in whole.
+/// - `Err(BadPtr)`: If any pointer is invalid.
+fn get_args(argv: usize, envp: usize) -> Result<(Vec<String>, Vec<String>)> {
+    let mut room = PG_SIZE;
+    let argv = get_strs(argv, &mut room)?;
+    let envp = match envp {
+        0 => Vec::new(),
+        ptr => get_strs(ptr, &mut room)?,
+    };
+    Ok((argv, envp))
 }
 
-#[derive(Clone, Copy)]
-pub struct Pointer<T>(*mut T);
+/// Reads a null-terminated array of strings, each of which takes its pointer and
+/// its bytes out of `room`.
+fn get_strs(mut ptr: usize, room: &mut usize) -> Result<Vec<String>> {
+    let mut strs = Vec::new();
 
-impl<T> From<usize> for Pointer<T> {
-    fn from(value: usize) -> Self {
-        Pointer(value as *mut T)
-    }
-}
+    loop {
+        let str_ptr = read_user::<usize>(ptr)?;
 
-impl<T> Pointer<T> {
-    pub fn check(&self) -> Option<*mut T> {
-        let current = current();
-        let pt = current.pagetable.as_ref().unwrap().lock();
-        let entry = pt.get_pte(self.0 as usize);
+        if str_ptr == 0 {
+            break;
+        }
 
-        entry.and_then(|e| match e.is_user() && e.is_valid() {
-            true => Some(self.0),
-            false => None,
-        })
+        *room = room
+            .checked_sub(size_of::<usize>())
+            .ok_or(OsError::ArgumentTooLong)?;
+        let string = match read_user_str(str_ptr, *room) {
+            Err(OsError::CstrFormatErr) => return Err(OsError::ArgumentTooLong),
+            result => result?,
+        };
+        *room -= string.len() + 1;
+
+        strs.push(string);
+        ptr += 8;
     }
+    Ok(strs)
 }
 
-impl<T: Clone> Pointer<T> {
-    pub fn take(&self) -> Option<T> {
-        self.check().and_then(|ptr| unsafe { Some((*ptr).clone()) })
-    }
+/// Reads a string shorter than a page, which holds all arguments of `exec` too.
+fn get_str(ptr: usize) -> Option<String> {
+    read_user_str(ptr, PG_SIZE).ok()
 }
@@ -1,150 +1,513 @@
 //! User process.
 //!
+//! A [`Process`] owns an address space, a file descriptor table, its children
+//! and its exit status. It is shared by all of its threads, each of which has a
+//! kernel thread and a user stack of its own.
+//!
+//! ## User stacks
+//! The stack of the first thread ends at [`STACK_TOP`]. Below it, the address
+//! space is divided into slots of [`STACK_SPAN`] bytes, one for each thread. A slot
+//! is released when its thread exits, and reused by later threads. The lowest page
+//! of a slot is a guard page, which is never mapped, so that a stack overflowing
+//! its slot faults instead of running into the stack below.
+//!
+//! ## Exit
+//! A process ends when its last thread exits. Calling [`exit`] from any thread sets
+//! the exit status, and makes all other threads exit the next time they get back
+//! to the kernel. [`signal::SIGKILL`] ends a process in the same way.
+//!
+//! ## Children
+//! The exit status of a process goes to the [`Children`] of its parent, where
+//! [`waitpid`] finds it. The parent is either a process, or the kernel thread
+//! which started it. When the parent ends before its children, they become
+//! orphans, and their exit statuses are dropped as soon as they exit, since no
+//! one can wait for them. So are the statuses the parent never waited for.
 
+pub mod futex;
+pub mod heap;
 mod load;
+pub mod signal;
+pub mod stack;
+pub mod vma;
 
 use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
 use alloc::string::String;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
 use core::arch::asm;
-use core::mem::MaybeUninit;
-use core::ptr::copy_nonoverlapping;
+use core::mem::{self, MaybeUninit};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst};
 use riscv::register::sstatus;
 
-use crate::fs::File;
-use crate::mem::pagetable::KernelPgTable;
-use crate::thread::{self, current, schedule, ChildStatus, Thread};
+use crate::fs::{disk::DISKFS, File, FileSys};
+use crate::mem::pagetable::{KernelPgTable, PTEFlags, PageTable};
+use crate::mem::{FrameTable, PG_SIZE};
+use crate::sbi::timer::clock;
+use crate::sync::Condvar;
+use crate::thread::{self, current, JoinHandle, Mutex, Thread, STACK_TOP};
 use crate::trap::{trap_exit_u, Frame};
 
-pub struct UserProc {
-    #[allow(dead_code)]
+use self::heap::Heap;
+use self::signal::Signals;
+use self::vma::Vmas;
+
+/// Size of the address space reserved for each user stack, including the guard
+/// page.
+pub const STACK_SPAN: usize = stack::STACK_MAX + PG_SIZE;
+/// The maximum number of threads in a process.
+pub const MAX_THREADS: usize = 16;
+
+#[derive(Clone, Copy)]
+pub enum ChildStatus {
+    Alive,
+    Exited(isize),
+}
+
+/// Return immediately from [`waitpid`] if no child has exited.
+pub const WNOHANG: usize = 1;
+
+/// Children of a process or of a kernel thread.
+pub struct Children {
+    /// Statuses of children not yet waited for, indexed by pid
+    statuses: Mutex<BTreeMap<isize, ChildStatus>>,
+    /// Notified when a child exits
+    exited: Condvar,
+}
+
+impl Children {
+    pub(crate) fn new() -> Self {
+        Self {
+            statuses: Mutex::new(BTreeMap::new()),
+            exited: Condvar::new(),
+        }
+    }
+}
+
+/// A thread of a process.
+struct UserThread {
+    /// Set by [`Process::spawn`] once the thread is spawned, which may be after it
+    /// has started running. Taken by the thread joining it, which waits on
+    /// [`Process::spawned`] until then.
+    handle: Option<JoinHandle<()>>,
+    /// Whether a thread has taken the handle
+    joined: bool,
+    /// The stack slot, released when the thread exits
+    slot: Option<usize>,
+    /// Set when the thread exits
+    exit_value: Option<isize>,
+}
+
+impl UserThread {
+    fn new(slot: usize) -> Self {
+        Self {
+            handle: None,
+            joined: false,
+            slot: Some(slot),
+            exit_value: None,
+        }
+    }
+}
+
+/* --------------------------------- PROCESS -------------------------------- */
+pub struct Process {
+    pid: isize,
     bin: File,
-    pub parent: Arc<Thread>,
+    pub pagetable: Mutex<PageTable>,
+    pub descriptors: Mutex<BTreeMap<usize, (File, usize)>>,
+    pub children: Arc<Children>,
+    /// Children of the parent, where the exit status goes. Gone once the parent
+    /// has ended.
+    parent: Weak<Children>,
+    /// All threads not yet joined, indexed by tid
+    threads: Mutex<BTreeMap<isize, UserThread>>,
+    /// Notified when the join handle of a thread is set
+    spawned: Condvar,
+    /// The number of threads that have not exited
+    alive: AtomicUsize,
+    /// Set by [`exit`]. Other threads exit once they notice it.
+    exiting: AtomicBool,
+    status: Mutex<Option<isize>>,
+    pub signals: Signals,
+    pub heap: Mutex<Heap>,
+    /// Anonymous mappings
+    pub vmas: Mutex<Vmas>,
+    /// How far each user stack may grow, see [`stack`]
+    pub stack_limit: AtomicUsize,
 }
 
-impl UserProc {
-    pub fn new(file: File) -> Self {
+impl Process {
+    /// The heap starts at `brk`.
+    fn new(pid: isize, bin: File, pagetable: PageTable, brk: usize) -> Self {
+        let current = current();
+        let (parent, stack_limit) = match current.process.as_ref() {
+            Some(process) => (&process.children, process.stack_limit.load(SeqCst)),
+            None => (current.children.as_ref().unwrap(), stack::STACK_MAX),
+        };
+        parent.statuses.lock().insert(pid, ChildStatus::Alive);
+
         Self {
-            bin: file,
-            parent: current(),
+            pid,
+            bin,
+            pagetable: Mutex::new(pagetable),
+            descriptors: Mutex::new(BTreeMap::new()),
+            children: Arc::new(Children::new()),
+            parent: Arc::downgrade(parent),
+            threads: Mutex::new(BTreeMap::new()),
+            spawned: Condvar::new(),
+            alive: AtomicUsize::new(0),
+            exiting: AtomicBool::new(false),
+            status: Mutex::new(None),
+            signals: Signals::new(),
+            heap: Mutex::new(Heap::new(brk)),
+            vmas: Mutex::new(Vmas::new()),
+            stack_limit: AtomicUsize::new(stack_limit),
         }
     }
+
+    pub fn pid(&self) -> isize {
+        self.pid
+    }
+
+    /// Whether [`exit`] has been called by some thread.
+    pub fn exiting(&self) -> bool {
+        self.exiting.load(SeqCst)
+    }
+
+    /// Makes all threads exit, with `status` unless it has been set. The exit
+    /// status is the first one set.
+    pub fn kill(&self, status: isize) {
+        self.status.lock().get_or_insert(status);
+        self.exiting.store(true, SeqCst);
+
+        // Wake up threads waiting for children, so that they can leave.
+        let _statuses = self.children.statuses.lock();
+        self.children.exited.notify_all();
+    }
+
+    /// Spawns thread `id` running `frame`. It must have got a stack slot.
+    fn spawn(self: &Arc<Self>, name: &'static str, id: isize, frame: Frame) {
+        self.alive.fetch_add(1, SeqCst);
+
+        let handle = thread::Builder::new(move || start(frame))
+            .name(name)
+            .process(self.clone())
+            .id(id)
+            .spawn();
+        assert_eq!(handle.thread().id(), id);
+
+        // The thread may have run and exited on another hart already. If it was the
+        // last one, the process has finished, and nobody can join it any more.
+        let mut threads = self.threads.lock();
+        if let Some(thread) = threads.get_mut(&id) {
+            thread.handle = Some(handle);
+        }
+        self.spawned.notify_all();
+    }
+
+    /// Finds a free stack slot for thread `id`, and maps the top page of it.
+    /// `None` if all slots are taken, or the page can't be allocated.
+    fn alloc_stack(&self, id: isize) -> Option<usize> {
+        let slot = {
+            let mut threads = self.threads.lock();
+            let slot = (0..MAX_THREADS)
+                .find(|i| threads.values().all(|thread| thread.slot != Some(*i)))?;
+            threads.insert(id, UserThread::new(slot));
+            slot
+        };
+
+        let Ok(frame) = FrameTable::alloc_page() else {
+            self.threads.lock().remove(&id);
+            return None;
+        };
+        let flags = PTEFlags::V | PTEFlags::R | PTEFlags::W | PTEFlags::U;
+        self.populate(frame, stack_top(slot) - PG_SIZE, flags);
+
+        Some(slot)
+    }
+
+    /// Maps `frame`, taken by [`FrameTable::alloc_page`], zero-filled at `page` with
+    /// `flags`, unless it has been mapped by another thread in the meantime.
+    ///
+    /// The frame is allocated by the caller before locking anything of the process,
+    /// since making room may write a page to swap, which sleeps.
+    fn populate(&self, frame: usize, page: usize, flags: PTEFlags) {
+        unsafe { (frame as *mut u8).write_bytes(0, PG_SIZE) };
+        FrameTable::map(&mut self.pagetable.lock(), frame, self.pid, page, flags);
+    }
+
+    /// Whether a thread is using stack slot `slot`.
+    fn owns_slot(&self, slot: usize) -> bool {
+        self.threads
+            .lock()
+            .values()
+            .any(|thread| thread.slot == Some(slot))
+    }
+
+    /// Unmaps and frees all pages of a stack slot.
+    fn free_stack(&self, slot: usize) {
+        let (start, end) = (stack_top(slot) - STACK_SPAN, stack_top(slot));
+        FrameTable::unmap(&mut self.pagetable.lock(), self.pid, start, end);
+    }
+
+    /// Releases everything but the address space, and reports the exit status
+    /// to the parent. Called by the last thread.
+    fn finish(&self) {
+        let status = self.status.lock().unwrap_or(0);
+
+        // Shared text must not outlive the executable being writable again.
+        FrameTable::unshare(self.pid, &mut self.pagetable.lock());
+        self.bin.to_owned().allow_write();
+        let descriptors = mem::take(&mut *self.descriptors.lock());
+        for (_, (file, _)) in descriptors {
+            DISKFS.get().close(file);
+        }
+
+        // Break the reference cycles through join handles.
+        mem::take(&mut *self.threads.lock());
+
+        // Children still running become orphans, and the statuses of the others
+        // are dropped. An orphan finds no entry to report to.
+        mem::take(&mut *self.children.statuses.lock());
+
+        if let Some(parent) = self.parent.upgrade() {
+            let mut statuses = parent.statuses.lock();
+            statuses
+                .entry(self.pid)
+                .and_modify(|x| *x = ChildStatus::Exited(status));
+            parent.exited.notify_all();
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        unsafe { self.pagetable.lock().destroy(self.pid) };
+    }
+}
+
+/// Top of the stack in `slot`.
+fn stack_top(slot: usize) -> usize {
+    STACK_TOP - slot * STACK_SPAN
 }
 
-/// Execute an object file with arguments.
+/// Whether `addr` is in the guard page at the bottom of a stack slot.
+pub fn in_stack_guard(addr: usize) -> bool {
+    (load::SEGMENT_LIMIT..STACK_TOP).contains(&addr)
+        && (addr - load::SEGMENT_LIMIT) % STACK_SPAN < PG_SIZE
+}
+
+/// Execute an object file with arguments and environment variables.
 ///
 /// ## Return
-/// - `-1`: On error.
-/// - `tid`: Tid of the newly spawned thread.
+/// - `-11`: [`OsError::ArgumentTooLong`](crate::OsError) if the arguments don't fit
+///   in the initial user stack.
+/// - `-1`: On other errors.
+/// - `pid`: Pid of the new process, which is also the tid of its first thread.
 #[allow(unused_variables)]
-pub fn execute(mut file: File, argv: Vec<String>) -> isize {
+pub fn execute(mut file: File, argv: Vec<String>, envp: Vec<String>) -> isize {
     #[cfg(feature = "debug")]
     kprintln!(
-        "[PROCESS] Kernel thread {} prepare to execute a process with args {:?}",
+        "[PROCESS] Kernel thread {} prepare to execute a process with args {:?}, envs {:?}",
         thread::current().name(),
-        argv
+        argv,
+        envp
     );
 
+    if let Err(err) = load::check_args(&argv, &envp) {
+        return err as isize;
+    }
+
     // It only copies L2 pagetable. This approach allows the new thread
     // to access kernel code and data during syscall without the need to
     // swithch pagetables.
     let mut pt = KernelPgTable::clone();
     let id = Thread::get_and_increase_id();
 
-    let (exec_info, stack_va) = match load::load_executable(&mut file, &mut pt, id) {
+    // Arguments are passed to user on the initial stack.
+    let seed = (clock() as u64) ^ ((id as u64) << 32);
+    let (exec_info, args) = match load::load_executable(&mut file, &mut pt, id, &argv, &envp, seed)
+    {
         Ok(x) => x,
         Err(_) => unsafe {
-            pt.destroy();
+            pt.destroy(id);
             return -1;
         },
     };
 
-    // Initialize frame, pass argument to user.
+    // Here the new process will be created.
+    let process = Arc::new(Process::new(id, file, pt, exec_info.brk));
+
+    // Initialize frame.
     let mut frame = unsafe { MaybeUninit::<Frame>::zeroed().assume_init() };
     frame.sepc = exec_info.entry_point;
-    frame.x[2] = exec_info.init_sp;
+    frame.x[2] = args.sp;
+    frame.x[10] = args.argc;
+    frame.x[11] = args.argv;
+    frame.x[12] = args.envp;
 
-    // Here the new process will be created.
-    let userproc = UserProc::new(file);
+    // The first thread's stack has been set up by the loader.
+    process.threads.lock().insert(id, UserThread::new(0));
+    process.spawn("Default", id, frame);
+    id
+}
+
+/// Creates a thread in the current process, which starts from `entry` with `arg`
+/// in `a0` on a new user stack. The entry should never return, but end with
+/// [`thread_exit`].
+///
+/// ## Return
+/// - `-1`: If the process has too many threads, or memory is exhausted.
+/// - `tid`: Tid of the new thread.
+pub fn thread_create(entry: usize, arg: usize) -> isize {
+    let current = current();
+    let process = current.process.as_ref().unwrap();
+
+    let id = Thread::get_and_increase_id();
+    let Some(slot) = process.alloc_stack(id) else {
+        return -1;
+    };
+
+    let mut frame = unsafe { MaybeUninit::<Frame>::zeroed().assume_init() };
+    frame.sepc = entry;
+    frame.x[2] = stack_top(slot);
+    frame.x[10] = arg;
 
-    let mut argv_p: Vec<usize> = Vec::new();
-    let mut current_p = stack_va as usize; // rsp
+    process.spawn(current.name(), id, frame);
+    id
+}
+
+/// Exits the current thread. If it's the last one, the process ends, with the
+/// status set by [`exit`], or 0 if nobody called it.
+///
+/// Panic if the current thread doesn't belong to a user process.
+pub fn thread_exit(value: isize) -> ! {
+    let current = current();
+    let (tid, process) = (current.id(), current.process.clone().unwrap());
+    drop(current);
 
-    unsafe fn fill<T>(pointer: usize, value: T) {
-        *(pointer as *mut T) = value
+    let slot = {
+        let mut threads = process.threads.lock();
+        let thread = threads.get_mut(&tid).unwrap();
+        thread.exit_value = Some(value);
+        thread.slot.take()
+    };
+    if let Some(slot) = slot {
+        process.free_stack(slot);
     }
 
-    unsafe {
-        for argv in argv.iter().rev() {
-            fill(current_p - 1, 0u8);
-            current_p -= argv.len() + 1;
-            copy_nonoverlapping(argv.as_ptr(), current_p as *mut u8, argv.len());
-            argv_p.push(current_p);
-        }
+    if process.alive.fetch_sub(1, SeqCst) == 1 {
+        process.finish();
+    }
+    drop(process);
 
-        current_p = align_ptr_floor(current_p - 1);
-        current_p = align_ptr_floor(current_p - 1);
-        // last pointer in argv is left zero
-        fill(current_p, 0usize);
+    thread::exit();
+}
 
-        for ptr in argv_p {
-            current_p = align_ptr_floor(current_p - 1);
-            fill(current_p, ptr - stack_va as usize + exec_info.init_sp)
-        }
+/// Waits for a thread of the current process.
+///
+/// ## Return
+/// - `Some(exit_value)`
+/// - `None`: if tid is not a thread of the current process, has been joined, or is
+///   the current thread itself.
+pub fn thread_join(tid: isize) -> Option<isize> {
+    let current = current();
+    let process = current.process.clone().unwrap();
+    if tid == current.id() {
+        return None;
     }
+    drop(current);
 
-    frame.x[2] += current_p - stack_va as usize;
-    frame.x[10] = argv.len();
-    frame.x[11] = frame.x[2];
+    let mut threads = process.threads.lock();
+    let handle = loop {
+        let thread = threads.get_mut(&tid)?;
+        if thread.joined {
+            return None;
+        }
+        if let Some(handle) = thread.handle.take() {
+            thread.joined = true;
+            break handle;
+        }
+        // Still being spawned.
+        process.spawned.wait(&mut threads);
+    };
+    drop(threads);
+    let _ = handle.join();
 
-    let real_id = thread::Builder::new(move || start(frame))
-        .pagetable(pt)
-        .userproc(userproc)
-        .id(id)
-        .spawn()
-        .id();
-    assert!(real_id == id);
-    real_id
+    let thread = process.threads.lock().remove(&tid);
+    thread.and_then(|thread| thread.exit_value)
 }
 
 /// Exits a process.
 ///
 /// Panic if the current thread doesn't own a user process.
-pub fn exit(_value: isize) -> ! {
+pub fn exit(value: isize) -> ! {
     let current = current();
-    // kprintln!("thread {} exited with value {}", current.id(), _value);
-    if let Some(userproc) = current.userproc.as_ref() {
-        userproc.bin.to_owned().allow_write();
-        let parent = userproc.parent.as_ref();
-        parent
-            .children
-            .lock()
-            .entry(current.id())
-            .and_modify(|x| *x = ChildStatus::Exited(_value));
-    } else {
-        // panic!("current exiting thread doesn't own a user process");
-    }
-    thread::exit();
+    // kprintln!("thread {} exited with value {}", current.id(), value);
+    current.process.as_ref().unwrap().kill(value);
+    drop(current);
+
+    thread_exit(value);
+}
+
+/// Whether the current thread should leave because its process is exiting.
+pub fn exiting() -> bool {
+    current()
+        .process
+        .as_ref()
+        .is_some_and(|process| process.exiting())
 }
 
-/// Waits for a child thread, which must own a user process.
+/// Waits for a child process.
 ///
 /// ## Return
 /// - `Some(exit_value)`
-/// - `None`: if tid was not created by the current thread.
-pub fn wait(_tid: isize) -> Option<isize> {
+/// - `None`: if pid was not created by the current process or kernel thread.
+///
+/// # See
+/// [`waitpid`].
+pub fn wait(pid: isize) -> Option<isize> {
+    waitpid(pid, 0).map(|(_, status)| status)
+}
+
+/// Waits for child `pid`, or any child if `pid` is `-1`, until it exits. With
+/// [`WNOHANG`] in `options`, returns `Some((0, 0))` at once if it's still running.
+///
+/// ## Return
+/// - `Some((pid, exit_value))`
+/// - `None`: if there's no such child, or the current process is exiting.
+pub fn waitpid(pid: isize, options: usize) -> Option<(isize, isize)> {
     let current = current();
+    let process = current.process.clone();
+    let children = match process.as_ref() {
+        Some(process) => process.children.clone(),
+        None => current.children.clone().unwrap(),
+    };
 
+    let mut statuses = children.statuses.lock();
     loop {
-        let status = current.children.lock().get(&_tid)?.clone();
-        match status {
-            ChildStatus::Alive => schedule(),
-            ChildStatus::Exited(status) => {
-                current.children.lock().remove(&_tid);
-                return Some(status);
-            }
+        let mut candidates = statuses
+            .iter()
+            .filter(|(child, _)| pid == -1 || **child == pid)
+            .peekable();
+        candidates.peek()?;
+
+        let exited = candidates.find_map(|(child, status)| match status {
+            ChildStatus::Exited(status) => Some((*child, *status)),
+            ChildStatus::Alive => None,
+        });
+        if let Some((child, status)) = exited {
+            statuses.remove(&child);
+            return Some((child, status));
+        }
+
+        if options & WNOHANG != 0 {
+            return Some((0, 0));
+        }
+        if process.as_ref().is_some_and(|process| process.exiting()) {
+            return None;
         }
+        children.exited.wait(&mut statuses);
     }
 }
 
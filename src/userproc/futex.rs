@@ -0,0 +1,166 @@
+//! Fast Userspace Mutex
+//!
+//! A user thread waits on a 32-bit word in its address space, until another thread
+//! wakes it up with the same word. Waiters are queued by the physical address of
+//! the word, so that threads mapping the same frame at different addresses meet
+//! in the same queue.
+//!
+//! Like [`Semaphore`](crate::sync::Semaphore), the queues are protected by an
+//! [`Intr`] lock which is released only after the waiter is marked as blocked.
+//! Timeouts are handled by [`Manager::register_sleep_thread`]. Whoever takes the
+//! waiter out of the sleeping threads (see [`Manager::cancel_sleep`]), the timer
+//! or a waker, wakes it up.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering::SeqCst};
+
+use crate::mem::{userbuf::read_user, PG_MASK};
+use crate::sbi::{interrupt, timer::timer_ticks};
+use crate::sync::{Intr, Lock};
+use crate::thread::{self, schedule, Manager, Status, Thread};
+
+/// Returned by [`wait`] if the word doesn't hold the expected value.
+pub const MISMATCH: isize = -1;
+/// Returned by [`wait`] if nobody wakes the waiter up in time.
+pub const TIMEDOUT: isize = -2;
+
+/// A waiting thread, and whether it also sleeps with a timeout.
+type Waiter = (Arc<Thread>, bool);
+
+struct Futexes {
+    lock: Intr,
+    queues: RefCell<BTreeMap<usize, VecDeque<Waiter>>>,
+}
+
+unsafe impl Sync for Futexes {}
+
+static FUTEXES: Futexes = Futexes {
+    lock: Intr::new(),
+    queues: RefCell::new(BTreeMap::new()),
+};
+
+/// Translates the user address of a word into the kernel address of it, which is
+/// also the key of its queue.
+fn translate(addr: usize) -> Option<&'static AtomicU32> {
+    if addr % 4 != 0 {
+        return None;
+    }
+    // Fault in the page if it's not allocated yet.
+    read_user::<u32>(addr).ok()?;
+
+    let current = thread::current();
+    let pagetable = current.process.as_ref()?.pagetable.lock();
+    let entry = pagetable
+        .get_pte(addr)
+        .filter(|entry| entry.is_valid() && entry.is_user())?;
+
+    let word = entry.pa().into_va() + (addr & PG_MASK);
+    Some(unsafe { &*(word as *const AtomicU32) })
+}
+
+/// Blocks the current thread if the word at `addr` equals `expected`, until it's
+/// woken up by [`wake`] or `timeout` ticks have passed. A negative `timeout` waits
+/// forever.
+///
+/// ## Return
+/// - `0`: Woken up.
+/// - [`MISMATCH`]: The word doesn't equal `expected`, or `addr` is invalid.
+/// - [`TIMEDOUT`]: Timed out.
+pub fn wait(addr: usize, expected: u32, timeout: i64) -> isize {
+    let Some(word) = translate(addr) else {
+        return MISMATCH;
+    };
+    let key = word as *const _ as usize;
+
+    let old = interrupt::set(false);
+    FUTEXES.lock.acquire();
+
+    if word.load(SeqCst) != expected {
+        FUTEXES.lock.release();
+        interrupt::set(old);
+        return MISMATCH;
+    }
+
+    let current = thread::current();
+    let timed = timeout >= 0;
+    FUTEXES
+        .queues
+        .borrow_mut()
+        .entry(key)
+        .or_default()
+        .push_back((current.clone(), timed));
+
+    // Block before releasing the lock or sleeping, so that wakers always find it blocked.
+    current.set_status(Status::Blocked);
+    if timed {
+        Manager::get().register_sleep_thread(current.clone(), timer_ticks() + timeout);
+    }
+
+    FUTEXES.lock.release();
+    schedule();
+    FUTEXES.lock.acquire();
+
+    // Still in the queue, so it must have been woken up by the timer.
+    let mut queues = FUTEXES.queues.borrow_mut();
+    let result = match queues.get_mut(&key) {
+        Some(queue) => match queue.iter().position(|(t, _)| Arc::ptr_eq(t, &current)) {
+            Some(index) => {
+                queue.remove(index);
+                if queue.is_empty() {
+                    queues.remove(&key);
+                }
+                TIMEDOUT
+            }
+            None => 0,
+        },
+        None => 0,
+    };
+    drop(queues);
+
+    FUTEXES.lock.release();
+    interrupt::set(old);
+    result
+}
+
+/// Wakes up at most `n` threads waiting on the word at `addr`.
+///
+/// ## Return
+/// - `-1`: `addr` is invalid.
+/// - The number of threads woken up.
+pub fn wake(addr: usize, n: usize) -> isize {
+    let Some(word) = translate(addr) else {
+        return -1;
+    };
+    let key = word as *const _ as usize;
+
+    let old = interrupt::set(false);
+    FUTEXES.lock.acquire();
+
+    let mut woken = VecDeque::new();
+    let mut queues = FUTEXES.queues.borrow_mut();
+    if let Some(queue) = queues.get_mut(&key) {
+        while woken.len() < n {
+            let Some((thread, timed)) = queue.pop_front() else {
+                break;
+            };
+            // Otherwise the timer has taken it, and will wake it up.
+            if !timed || Manager::get().cancel_sleep(&thread) {
+                woken.push_back(thread);
+            }
+        }
+        if queue.is_empty() {
+            queues.remove(&key);
+        }
+    }
+    drop(queues);
+
+    FUTEXES.lock.release();
+
+    let count = woken.len();
+    woken.into_iter().for_each(thread::wake_up);
+
+    interrupt::set(old);
+    count as isize
+}
@@ -0,0 +1,106 @@
+//! User Heap
+//!
+//! The heap of a process starts right after the highest segment of its
+//! executable, and ends at the program break, which is moved by [`brk`] and
+//! [`sbrk`]. Heap pages are not allocated when the break grows. They are
+//! allocated and zero-filled by [`populate`] on the first access, either by a
+//! page fault of the user, or by a syscall checking a user pointer. Pages above
+//! a shrunk break are freed at once, and the others go with the page table when
+//! the process exits. The break can't run into [anonymous mappings](super::vma).
+
+use crate::mem::{FrameTable, PTEFlags, PageAlign};
+use crate::thread::current;
+use crate::userproc::{load, Process};
+
+/// The heap of a process.
+pub struct Heap {
+    /// The end of the executable, which the break can't go below
+    start: usize,
+    /// The program break
+    brk: usize,
+}
+
+impl Heap {
+    pub fn new(start: usize) -> Self {
+        Self { start, brk: start }
+    }
+
+    /// The program break.
+    pub fn brk(&self) -> usize {
+        self.brk
+    }
+
+    /// Whether `addr` is in the heap.
+    fn contains(&self, addr: usize) -> bool {
+        (self.start..self.brk).contains(&addr)
+    }
+}
+
+/// Moves the break of the current process to `addr`.
+///
+/// ## Return
+/// - `0`: On success.
+/// - `-1`: If `addr` is below the start of the heap, or runs into user stacks.
+pub fn brk(addr: usize) -> isize {
+    let current = current();
+    let process = current.process.as_ref().unwrap();
+
+    let mut heap = process.heap.lock();
+    match resize(process, &mut heap, addr) {
+        true => 0,
+        false => -1,
+    }
+}
+
+/// Moves the break of the current process by `increment` bytes.
+///
+/// ## Return
+/// - The previous break.
+/// - `-1`: If the new break is out of range, see [`brk`].
+pub fn sbrk(increment: isize) -> isize {
+    let current = current();
+    let process = current.process.as_ref().unwrap();
+
+    // Hold the heap lock, so that the old break stays the same.
+    let mut heap = process.heap.lock();
+    let old = heap.brk;
+    match old.checked_add_signed(increment) {
+        Some(new) if resize(process, &mut heap, new) => old as isize,
+        _ => -1,
+    }
+}
+
+/// Moves the break to `new`, and frees pages above it if it shrinks.
+fn resize(process: &Process, heap: &mut Heap, new: usize) -> bool {
+    if new < heap.start || new > load::SEGMENT_LIMIT {
+        return false;
+    }
+    if new > heap.brk && process.vmas.lock().overlaps(heap.brk.ceil(), new.ceil()) {
+        return false;
+    }
+
+    if new < heap.brk {
+        // Pages holding the executable lie below `start.ceil()`.
+        let mut pagetable = process.pagetable.lock();
+        FrameTable::unmap(&mut pagetable, process.pid(), new.ceil(), heap.brk.ceil());
+    }
+
+    heap.brk = new;
+    true
+}
+
+/// Maps `frame` zero-filled at `addr`, if it's in the heap and not mapped yet.
+///
+/// ## Return
+/// - Whether `addr` is now accessible, i.e. it's in the heap. The frame is used up
+///   if so.
+pub fn populate(process: &Process, addr: usize, frame: usize) -> bool {
+    let heap = process.heap.lock();
+    if !heap.contains(addr) {
+        return false;
+    }
+
+    let flags = PTEFlags::V | PTEFlags::R | PTEFlags::W | PTEFlags::U;
+    process.populate(frame, addr.floor(), flags);
+    true
+}
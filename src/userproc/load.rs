@@ -1,145 +1,383 @@
+use alloc::string::String;
 use alloc::vec;
-use elf_rs::{Elf, ElfFile, ProgramHeaderEntry, ProgramHeaderFlags, ProgramType};
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr::copy_nonoverlapping;
+use elf_rs::{
+    Elf, ElfEndian, ElfFile, ElfMachine, ElfType, ProgramHeader64, ProgramHeaderEntry,
+    ProgramHeaderFlags, ProgramType,
+};
 
 use crate::fs::File;
 use crate::io::prelude::*;
 use crate::mem::pagetable::{PTEFlags, PageTable};
 
-use crate::mem::{div_round_up, FrameTable, PageAlign, PhysAddr, PG_MASK, PG_SIZE};
+use crate::mem::{FrameTable, PageAlign, PG_MASK, PG_SIZE};
+use crate::thread::STACK_TOP;
+use crate::userproc::{MAX_THREADS, STACK_SPAN};
 use crate::{OsError, Result};
 
 #[derive(Debug, Clone, Copy)]
 pub(super) struct ExecInfo {
     pub entry_point: usize,
     pub init_sp: usize,
+    /// The end of the highest segment, where the heap starts
+    pub brk: usize,
+}
+
+/// Auxiliary vector entry types
+const AT_NULL: usize = 0;
+const AT_PAGESZ: usize = 6;
+const AT_ENTRY: usize = 9;
+const AT_RANDOM: usize = 25;
+
+/// Entries in the auxiliary vector, including `AT_NULL`.
+const AUXV_LEN: usize = 4;
+/// Bytes pointed by `AT_RANDOM`
+const RANDOM_LEN: usize = 16;
+
+/// Where the initial arguments of a process are, in user addresses.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Args {
+    pub sp: usize,
+    pub argc: usize,
+    pub argv: usize,
+    pub envp: usize,
 }
 
 /// Loads an executable file
 ///
 /// ## Params
 /// - `pagetable`: User's pagetable. We install the mapping to executable codes into it.
+/// - `argv`, `envp`, `seed`: Pushed onto the user stack, see [`push_args`].
 ///
 /// ## Return
-/// On success, returns `Ok(usize, usize)`:
-/// - arg0: the entry point of user program
-/// - arg1: the initial sp of user program
+/// On success, returns `Ok(ExecInfo, Args)`:
+/// - arg0: the entry point of user program, etc.
+/// - arg1: the initial sp of user program, and where the arguments are
 pub(super) fn load_executable(
     file: &mut File,
     pagetable: &mut PageTable,
     thread: isize,
-) -> Result<(ExecInfo, *mut u8)> {
+    argv: &[String],
+    envp: &[String],
+    seed: u64,
+) -> Result<(ExecInfo, Args)> {
     let exec_info = load_elf(file, pagetable, thread)?;
 
     // Initialize user stack.
-    let stack_va = init_user_stack(pagetable, exec_info.init_sp, thread);
+    let args = init_user_stack(pagetable, &exec_info, thread, argv, envp, seed)?;
 
     // Forbid modifying executable file when running
     file.deny_write();
 
-    Ok((exec_info, stack_va))
+    Ok((exec_info, args))
 }
 
-/// Parses the specified executable file and loads segments
+/// The ELF header and program headers must lie in the first page of the file.
+const HEADERS_LIMIT: usize = PG_SIZE;
+/// Segments and the heap must end below the user stacks.
+pub(super) const SEGMENT_LIMIT: usize = STACK_TOP - MAX_THREADS * STACK_SPAN;
+
+/// A validated loadable segment.
+struct Segment {
+    vaddr: usize,
+    offset: usize,
+    filesz: usize,
+    memsz: usize,
+    flags: PTEFlags,
+}
+
+impl Segment {
+    /// Checks a program header of a file of `len` bytes.
+    fn parse(phdr: &ProgramHeaderEntry, len: usize) -> Result<Self> {
+        let (vaddr, offset) = (phdr.vaddr() as usize, phdr.offset() as usize);
+        let (filesz, memsz) = (phdr.filesz() as usize, phdr.memsz() as usize);
+
+        let in_file = offset.checked_add(filesz).is_some_and(|end| end <= len);
+        let in_user = vaddr
+            .checked_add(memsz)
+            .is_some_and(|end| end <= SEGMENT_LIMIT);
+        if filesz > memsz || !in_file || !in_user || offset & PG_MASK != vaddr & PG_MASK {
+            return Err(OsError::UnknownFormat);
+        }
+
+        // W^X, and a leaf entry needs at least one of R and X. RISC-V doesn't
+        // allow writable pages that are not readable.
+        let phflags = phdr.flags();
+        let (read, write, execute) = (
+            phflags.contains(ProgramHeaderFlags::READ),
+            phflags.contains(ProgramHeaderFlags::WRITE),
+            phflags.contains(ProgramHeaderFlags::EXECUTE),
+        );
+        if (write && execute) || !(read || write || execute) {
+            return Err(OsError::UnknownFormat);
+        }
+
+        let mut flags = PTEFlags::V | PTEFlags::U;
+        if read || write {
+            flags |= PTEFlags::R;
+        }
+        if write {
+            flags |= PTEFlags::W;
+        }
+        if execute {
+            flags |= PTEFlags::X;
+        }
+
+        Ok(Self {
+            vaddr,
+            offset,
+            filesz,
+            memsz,
+            flags,
+        })
+    }
+
+    /// Pages covered by this segment.
+    fn pages(&self) -> core::ops::Range<usize> {
+        self.vaddr.floor()..(self.vaddr + self.memsz).ceil()
+    }
+}
+
+/// Parses the specified executable file and loads segments. Only the headers
+/// are buffered, and segments are read straight into their frames.
 fn load_elf(file: &mut File, pagetable: &mut PageTable, thread: isize) -> Result<ExecInfo> {
     // Ensure cursor is at the beginning
     file.rewind()?;
 
     let len = file.len()?;
-    let mut buf = vec![0u8; len];
-    file.read(&mut buf)?;
+    let mut buf = vec![0u8; len.min(HEADERS_LIMIT)];
+    file.read_exact(&mut buf)?;
 
     let elf = match Elf::from_bytes(&buf) {
         Ok(Elf::Elf64(elf)) => elf,
         Ok(Elf::Elf32(_)) | Err(_) => return Err(OsError::UnknownFormat),
     };
 
-    // load each loadable segment into memory
-    elf.program_header_iter()
-        .filter(|p| p.ph_type() == ProgramType::LOAD)
-        .for_each(|p| load_segment(&buf, &p, pagetable, thread));
+    let header = elf.elf_header();
+    if header.endianness() != ElfEndian::LittleEndian
+        || header.machine() != ElfMachine::RISC_V
+        || header.elftype() != ElfType::ET_EXEC
+        || header.program_header_entry_size() as usize != size_of::<ProgramHeader64>()
+        || elf.program_headers_raw().is_none()
+    {
+        return Err(OsError::UnknownFormat);
+    }
+    let entry_point = header.entry_point() as usize;
+
+    let mut segments = elf
+        .program_header_iter()
+        .filter(|p| p.ph_type() == ProgramType::LOAD && p.memsz() != 0)
+        .map(|p| Segment::parse(&p, len))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Segments can't share pages, each of which has a single set of permissions.
+    segments.sort_by_key(|s| s.vaddr);
+    if segments
+        .windows(2)
+        .any(|w| w[0].pages().end > w[1].pages().start)
+    {
+        return Err(OsError::UnknownFormat);
+    }
+
+    let executable = segments.iter().any(|s| {
+        s.flags.contains(PTEFlags::X) && (s.vaddr..s.vaddr + s.memsz).contains(&entry_point)
+    });
+    if !executable {
+        return Err(OsError::UnknownFormat);
+    }
+
+    for segment in segments.iter() {
+        load_segment(file, segment, pagetable, thread)?;
+    }
 
     Ok(ExecInfo {
-        entry_point: elf.elf_header().entry_point() as _,
-        init_sp: 0x80500000,
+        entry_point,
+        init_sp: STACK_TOP,
+        brk: segments.last().map_or(0, |s| s.vaddr + s.memsz),
     })
 }
 
-/// Loads one segment and installs pagetable mappings
+/// Loads one segment and installs pagetable mappings. Bytes beyond `filesz`,
+/// e.g. the BSS, are zeroed.
 fn load_segment(
-    filebuf: &[u8],
-    phdr: &ProgramHeaderEntry,
+    file: &mut File,
+    segment: &Segment,
     pagetable: &mut PageTable,
     thread: isize,
-) {
-    assert_eq!(phdr.ph_type(), ProgramType::LOAD);
-
-    // Meaningful contents of this segment starts from `fileoff`.
-    let fileoff = phdr.offset() as usize;
-    // But we will read and install from `read_pos`.
-    let mut readpos = fileoff & !PG_MASK;
-
-    // Install flags.
-    let mut leaf_flag = PTEFlags::V | PTEFlags::U | PTEFlags::R;
-    if phdr.flags().contains(ProgramHeaderFlags::EXECUTE) {
-        leaf_flag |= PTEFlags::X;
-    }
-    if phdr.flags().contains(ProgramHeaderFlags::WRITE) {
-        leaf_flag |= PTEFlags::W;
+) -> Result<()> {
+    let file_end = segment.vaddr + segment.filesz;
+    // Read-only pages are the same in every process running the executable.
+    let shared = !segment.flags.contains(PTEFlags::W);
+    // The content is not zero-filled, so it goes to swap when evicted, see
+    // `Frames::evictable`.
+    let flags = segment.flags | PTEFlags::D;
+
+    for uaddr in segment.pages().step_by(PG_SIZE) {
+        if shared {
+            if let Some(frame) = FrameTable::get_shared(file.ino(), uaddr) {
+                FrameTable::map(pagetable, frame, thread, uaddr, flags);
+                continue;
+            }
+        }
+
+        let buf = FrameTable::alloc_page()?;
+        let page = unsafe { (buf as *mut [u8; PG_SIZE]).as_mut().unwrap() };
+        page.fill(0);
+
+        // Filled before it's mapped, since it may be evicted from then on.
+        let start = segment.vaddr.max(uaddr);
+        let end = file_end.min(uaddr + PG_SIZE);
+        if start < end {
+            let read = file
+                .seek(SeekFrom::Start(segment.offset + start - segment.vaddr))
+                .and_then(|_| file.read_exact(&mut page[start - uaddr..end - uaddr]));
+            if let Err(err) = read {
+                FrameTable::dealloc_page(buf);
+                return Err(err);
+            }
+        }
+        if shared {
+            FrameTable::share(buf, file.ino(), uaddr);
+        }
+        FrameTable::map(pagetable, buf, thread, uaddr, flags);
     }
 
-    // Install position: `ubase`.
-    let ubase = (phdr.vaddr() as usize) & !PG_MASK;
-    let pageoff = (phdr.vaddr() as usize) & PG_MASK;
-    assert_eq!(fileoff & PG_MASK, pageoff);
+    Ok(())
+}
 
-    // How many pages need to be allocated
-    let pages = div_round_up(pageoff + phdr.memsz() as usize, PG_SIZE);
-    let mut readbytes = phdr.filesz() as usize + pageoff;
+/// Bytes taken by the initial arguments on the user stack.
+fn args_size(argv: &[String], envp: &[String]) -> usize {
+    let strings: usize = argv.iter().chain(envp).map(|s| s.len() + 1).sum();
+    // argc, argv, envp with their terminators, and auxv
+    let words = 1 + (argv.len() + 1) + (envp.len() + 1) + 2 * AUXV_LEN;
 
-    // Allocate & map pages
-    for p in 0..pages {
-        let readsz = readbytes.min(PG_SIZE);
-        let uaddr = ubase + p * PG_SIZE;
+    let size = (strings + 7) / 8 * 8 + RANDOM_LEN + words * size_of::<usize>();
+    (size + 15) / 16 * 16
+}
 
-        let buf = unsafe { FrameTable::alloc_page(thread, uaddr, true, leaf_flag) };
-        let page = unsafe { (buf as *mut [u8; PG_SIZE]).as_mut().unwrap() };
+/// Checks that the arguments fit in the initial user stack, which is one page.
+pub(super) fn check_args(argv: &[String], envp: &[String]) -> Result<()> {
+    if args_size(argv, envp) > PG_SIZE {
+        return Err(OsError::ArgumentTooLong);
+    }
+    Ok(())
+}
 
-        page[..readsz].copy_from_slice(&filebuf[readpos..readpos + readsz]);
-        pagetable.map(buf.into(), uaddr, 1, leaf_flag);
+/// Pushes the initial arguments onto the user stack, which must have passed
+/// [`check_args`]. From the stack pointer up, it looks like:
+///
+/// ```text
+///   argc
+///   argv[0] .. argv[argc - 1], NULL
+///   envp[0] .. envp[n - 1], NULL
+///   auxv: (AT_PAGESZ, _), (AT_ENTRY, _), (AT_RANDOM, _), (AT_NULL, 0)
+///   16 random bytes
+///   strings of argv and envp
+/// ```
+///
+/// ## Params
+/// - `stack`: The top of the user stack, in kernel address.
+/// - `seed`: Where the random bytes come from. They are not meant for cryptography.
+fn push_args(
+    stack: *mut u8,
+    exec_info: &ExecInfo,
+    argv: &[String],
+    envp: &[String],
+    seed: u64,
+) -> Args {
+    let stack = stack as usize;
+    let to_user = |kaddr: usize| kaddr - stack + exec_info.init_sp;
+
+    let mut cursor = stack;
+    let mut push_str = |s: &String| {
+        cursor -= s.len() + 1;
+        unsafe {
+            copy_nonoverlapping(s.as_ptr(), cursor as *mut u8, s.len());
+            *((cursor + s.len()) as *mut u8) = 0;
+        }
+        to_user(cursor)
+    };
+    let argv_u: Vec<usize> = argv.iter().map(&mut push_str).collect();
+    let envp_u: Vec<usize> = envp.iter().map(&mut push_str).collect();
 
-        readbytes -= readsz;
-        readpos += readsz;
+    // Random bytes, by splitmix64.
+    cursor = (cursor & !7) - RANDOM_LEN;
+    let mut state = seed;
+    for i in 0..RANDOM_LEN / size_of::<u64>() {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        unsafe { *((cursor as *mut u64).add(i)) = z ^ (z >> 31) };
     }
+    let random = to_user(cursor);
 
-    assert_eq!(readbytes, 0);
+    let auxv = [
+        (AT_PAGESZ, PG_SIZE),
+        (AT_ENTRY, exec_info.entry_point),
+        (AT_RANDOM, random),
+        (AT_NULL, 0),
+    ];
+    let words: Vec<usize> = core::iter::once(argv.len())
+        .chain(argv_u)
+        .chain([0])
+        .chain(envp_u)
+        .chain([0])
+        .chain(auxv.iter().flat_map(|&(key, value)| [key, value]))
+        .collect();
+
+    let sp = (cursor - words.len() * size_of::<usize>()) & !15;
+    assert!(stack - sp <= PG_SIZE, "arguments overflow the user stack");
+    unsafe { copy_nonoverlapping(words.as_ptr(), sp as *mut usize, words.len()) };
+
+    let sp = to_user(sp);
+    Args {
+        sp,
+        argc: argv.len(),
+        argv: sp + size_of::<usize>(),
+        envp: sp + (argv.len() + 2) * size_of::<usize>(),
+    }
 }
 
-/// Initializes the user stack.
-/// stack_va is required to locate the stack we're going to modify, since
-/// we can't use init_sp directly. stack_page is not activated yet.
-fn init_user_stack(pagetable: &mut PageTable, init_sp: usize, thread: isize) -> *mut u8 {
+/// Initializes the user stack, and pushes the arguments onto it.
+/// The stack page is written through its kernel address, since the page
+/// table is not activated yet. It's mapped only after that, since it may
+/// be evicted from then on.
+fn init_user_stack(
+    pagetable: &mut PageTable,
+    exec_info: &ExecInfo,
+    thread: isize,
+    argv: &[String],
+    envp: &[String],
+    seed: u64,
+) -> Result<Args> {
+    let init_sp = exec_info.init_sp;
     assert!(init_sp % PG_SIZE == 0, "initial sp address misaligns");
 
     let stack_page_begin = PageAlign::floor(init_sp - 1);
-    let flags = PTEFlags::V | PTEFlags::R | PTEFlags::W | PTEFlags::U;
+    // Arguments are pushed by the kernel, so the page is dirty from the start.
+    let flags = PTEFlags::V | PTEFlags::R | PTEFlags::W | PTEFlags::U | PTEFlags::D;
 
     // Allocate a page from UserPool as user stack.
-    let stack_va = unsafe { FrameTable::alloc_page(thread, stack_page_begin, true, flags) };
-    let stack_pa = PhysAddr::from(stack_va);
+    let stack_va = FrameTable::alloc_page()?;
+    // A frame may have been used by another process.
+    unsafe { (stack_va as *mut u8).write_bytes(0, PG_SIZE) };
 
-    // Get the start address of stack page
+    // Now stack_va points to the bottom of this newly allowcated page
+    // Adjust it to the top of this page
+    let args = push_args((stack_va + PG_SIZE) as *mut u8, exec_info, argv, envp, seed);
 
     // Install mapping
-    pagetable.map(stack_pa, stack_page_begin, PG_SIZE, flags);
+    FrameTable::map(pagetable, stack_va, thread, stack_page_begin, flags);
 
     #[cfg(feature = "debug")]
     kprintln!(
-        "[USERPROC] User Stack Mapping: (k){:p} -> (u) {:#x}",
+        "[USERPROC] User Stack Mapping: (k){:#x} -> (u) {:#x}",
         stack_va,
         stack_page_begin
     );
 
-    // Now stack_va points to the bottom of this newly allowcated page
-    // Adjust it to the top of this page
-    (stack_va as usize + PG_SIZE) as *mut u8
+    Ok(args)
 }
@@ -0,0 +1,239 @@
+//! Signals
+//!
+//! A signal is sent to a whole process by [`kill`], and stays pending until one
+//! of its threads is about to return to user mode (see [`deliver`]), where the
+//! action registered by [`sigaction`] is taken:
+//!
+//! - [`SIG_DFL`]: the default action, which terminates the process;
+//! - [`SIG_IGN`]: nothing happens;
+//! - otherwise it's the address of a user handler. The user context is saved in
+//!   a [`SigFrame`] pushed onto the user stack, and the thread returns to the
+//!   handler, with the signal number in `a0`, and `ra` set to the restorer given
+//!   by [`sigaction`]. The restorer is expected to call [`sigreturn`], which pops
+//!   the frame and resumes the interrupted context.
+//!
+//! [`SIGKILL`] can be neither caught nor ignored. It terminates the process right
+//! away, in the same way as [`exit`](super::exit).
+//!
+//! Faults of a user thread are turned into synchronous signals by [`catch`], which
+//! enters the handler immediately instead of waiting for a pending bit.
+//!
+//! Signals don't interrupt blocked threads, and are not masked while their
+//! handlers run.
+
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU32, Ordering::SeqCst};
+
+use crate::mem::userbuf::{read_user, write_user};
+use crate::thread::{self, current, Manager, Mutex};
+use crate::trap::Frame;
+
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGBUS: usize = 7;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGTERM: usize = 15;
+/// Signals are numbered from 1 to `NSIG - 1`.
+pub const NSIG: usize = 32;
+
+/// Take the default action.
+pub const SIG_DFL: usize = 0;
+/// Ignore the signal.
+pub const SIG_IGN: usize = 1;
+
+/// Exit status of a process terminated by `signal`, including the default action
+/// of a signal raised by a fault. It's distinct from any status a process may pass
+/// to `exit` by convention.
+pub fn killed(signal: usize) -> isize {
+    -128 - signal as isize
+}
+
+/// What a process does on each signal.
+#[derive(Clone, Copy)]
+struct Action {
+    handler: usize,
+    restorer: usize,
+}
+
+/// Signal states of a process.
+pub struct Signals {
+    /// Bit `i` is set if signal `i` is pending.
+    pending: AtomicU32,
+    actions: Mutex<[Action; NSIG]>,
+}
+
+impl Signals {
+    pub fn new() -> Self {
+        Self {
+            pending: AtomicU32::new(0),
+            actions: Mutex::new(
+                [Action {
+                    handler: SIG_DFL,
+                    restorer: 0,
+                }; NSIG],
+            ),
+        }
+    }
+}
+
+impl Default for Signals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The user context saved on the user stack while a handler runs.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SigFrame {
+    /// General regs of the interrupted context
+    x: [usize; 32],
+    /// Where the interrupted context resumes
+    sepc: usize,
+    signal: usize,
+}
+
+fn valid(signal: usize) -> bool {
+    (1..NSIG).contains(&signal)
+}
+
+/// Sends `signal` to process `pid`. A zero `signal` only checks whether the
+/// process exists.
+///
+/// ## Return
+/// - `0`: On success.
+/// - `-1`: If `signal` or `pid` is invalid.
+pub fn kill(pid: isize, signal: usize) -> isize {
+    if signal != 0 && !valid(signal) {
+        return -1;
+    }
+    let Some(process) = Manager::get().get_process(pid) else {
+        return -1;
+    };
+
+    match signal {
+        0 => {}
+        SIGKILL => process.kill(killed(SIGKILL)),
+        _ => {
+            process.signals.pending.fetch_or(1 << signal, SeqCst);
+        }
+    }
+    0
+}
+
+/// Sets the action of `signal` in the current process. `restorer` is where a
+/// handler returns to.
+///
+/// ## Return
+/// - The previous handler.
+/// - `-1`: If `signal` is invalid, or is [`SIGKILL`].
+pub fn sigaction(signal: usize, handler: usize, restorer: usize) -> isize {
+    if !valid(signal) || signal == SIGKILL {
+        return -1;
+    }
+
+    let current = current();
+    let process = current.process.as_ref().unwrap();
+    let mut actions = process.signals.actions.lock();
+    let old = actions[signal].handler;
+    actions[signal] = Action { handler, restorer };
+    old as isize
+}
+
+/// Takes the action of a pending signal, if any. Called on the way back to
+/// user mode.
+pub fn deliver(frame: &mut Frame) {
+    let current = current();
+    let process = current.process.as_ref().unwrap();
+
+    let pending = process.signals.pending.load(SeqCst);
+    if pending == 0 {
+        return;
+    }
+    let signal = pending.trailing_zeros() as usize;
+    if process.signals.pending.fetch_and(!(1 << signal), SeqCst) & (1 << signal) == 0 {
+        // Taken by another thread.
+        return;
+    }
+
+    let action = process.signals.actions.lock()[signal];
+    drop(current);
+
+    match action.handler {
+        SIG_IGN => {}
+        SIG_DFL => terminate(signal),
+        _ => {
+            if !enter(frame, signal, action) {
+                terminate(SIGSEGV);
+            }
+        }
+    }
+}
+
+/// Raises `signal` for a fault of the current thread. If the process has a
+/// handler for it, the thread enters the handler on return. Otherwise the fault
+/// can't be ignored, and the caller should terminate the process.
+///
+/// ## Return
+/// Whether the signal is caught by a handler.
+pub fn catch(frame: &mut Frame, signal: usize) -> bool {
+    let current = current();
+    let action = current.process.as_ref().unwrap().signals.actions.lock()[signal];
+    drop(current);
+
+    match action.handler {
+        SIG_DFL | SIG_IGN => false,
+        _ => enter(frame, signal, action),
+    }
+}
+
+/// Pops the frame pushed by [`deliver`], and resumes the interrupted context.
+///
+/// ## Return
+/// The restored `a0`, which the syscall return value is about to overwrite.
+pub fn sigreturn(frame: &mut Frame) -> isize {
+    let Ok(saved) = read_user::<SigFrame>(frame.x[2]) else {
+        terminate(SIGSEGV);
+    };
+
+    frame.x = saved.x;
+    frame.sepc = saved.sepc;
+    frame.x[10] as isize
+}
+
+/// Pushes a [`SigFrame`] and redirects `frame` to the handler. Returns `false` if
+/// the user stack can't hold the frame.
+fn enter(frame: &mut Frame, signal: usize, action: Action) -> bool {
+    let Some(sp) = frame.x[2].checked_sub(size_of::<SigFrame>()) else {
+        return false;
+    };
+    let sp = sp & !0xf;
+    let sigframe = SigFrame {
+        x: frame.x,
+        sepc: frame.sepc,
+        signal,
+    };
+    if write_user(sp, &sigframe).is_err() {
+        return false;
+    }
+
+    frame.x[1] = action.restorer;
+    frame.x[2] = sp;
+    frame.x[10] = signal;
+    frame.sepc = action.handler;
+    true
+}
+
+/// Terminates the current process with the default action of `signal`.
+fn terminate(signal: usize) -> ! {
+    klog!(
+        Info,
+        "User thread {} terminated by signal {}.",
+        thread::current().name(),
+        signal
+    );
+    super::exit(killed(signal));
+}
@@ -0,0 +1,93 @@
+//! User Stack Growth
+//!
+//! Only the top page of a user stack is mapped when its thread starts. Pages below
+//! it are allocated and zero-filled by [`populate`] on the first access, as long as
+//! they are within the stack limit of the process, which is set by [`setrlimit`],
+//! and inherited by the processes it starts.
+//!
+//! A fault of the user grows the stack only if it's in the slot of the faulting
+//! thread, and not far below `sp`: RISC-V code moves `sp` down before storing to a
+//! new frame, so an access way below `sp` is a bug rather than a push. A syscall
+//! checking a user pointer doesn't know `sp`, and only checks the limit.
+//!
+//! The limit can't exceed [`STACK_MAX`], which keeps the guard page at the bottom
+//! of each slot unmapped.
+
+use core::mem::size_of;
+use core::sync::atomic::Ordering::SeqCst;
+
+use crate::mem::{PTEFlags, PageAlign, PG_SIZE};
+use crate::thread::{current, STACK_TOP};
+use crate::userproc::{stack_top, Process, MAX_THREADS, STACK_SPAN};
+
+/// Resource number of the stack size.
+pub const RLIMIT_STACK: usize = 3;
+
+/// The hard limit of the stack size, which is also the default one.
+pub const STACK_MAX: usize = 8 << 20;
+
+/// How far below `sp` an access still grows the stack.
+const SLACK: usize = 32 * size_of::<usize>();
+
+/// The slot containing `addr`, if any.
+fn slot_of(addr: usize) -> Option<usize> {
+    if addr >= STACK_TOP {
+        return None;
+    }
+    let slot = (STACK_TOP - 1 - addr) / STACK_SPAN;
+    (slot < MAX_THREADS).then_some(slot)
+}
+
+/// The soft and the hard limits of `resource`, if it's supported.
+pub fn getrlimit(resource: usize) -> Option<(usize, usize)> {
+    if resource != RLIMIT_STACK {
+        return None;
+    }
+    let current = current();
+    let process = current.process.as_ref().unwrap();
+    Some((process.stack_limit.load(SeqCst), STACK_MAX))
+}
+
+/// Sets the soft limit of `resource` to `limit`. The hard limit can't be changed.
+/// Pages already mapped beyond a lowered limit stay mapped.
+///
+/// ## Return
+/// - `0`: On success.
+/// - `-1`: If `resource` is not supported, `limit` is below a page or above the
+///   hard limit, or `max` is not the hard limit.
+pub fn setrlimit(resource: usize, limit: usize, max: usize) -> isize {
+    if resource != RLIMIT_STACK || !(PG_SIZE..=STACK_MAX).contains(&limit) || max != STACK_MAX {
+        return -1;
+    }
+    let current = current();
+    let process = current.process.as_ref().unwrap();
+    process.stack_limit.store(limit, SeqCst);
+    0
+}
+
+/// Maps `frame` zero-filled at `addr`, if it's within the stack limit of a thread
+/// of `process`. With `sp` of a faulting thread, `addr` must also be in the slot
+/// of that thread, and at most [`SLACK`] bytes below `sp`.
+///
+/// ## Return
+/// - Whether `addr` is now accessible. The frame is used up if so.
+pub fn populate(process: &Process, addr: usize, sp: Option<usize>, frame: usize) -> bool {
+    let Some(slot) = slot_of(addr) else {
+        return false;
+    };
+    if addr < stack_top(slot) - process.stack_limit.load(SeqCst) {
+        return false;
+    }
+    if let Some(sp) = sp {
+        if slot_of(sp) != Some(slot) || addr.saturating_add(SLACK) < sp {
+            return false;
+        }
+    }
+    if !process.owns_slot(slot) {
+        return false;
+    }
+
+    let flags = PTEFlags::V | PTEFlags::R | PTEFlags::W | PTEFlags::U;
+    process.populate(frame, addr.floor(), flags);
+    true
+}
@@ -0,0 +1,361 @@
+//! Virtual Memory Areas
+//!
+//! Anonymous memory of a process is described by a list of areas, each of which
+//! is a page-aligned range with a single set of permissions. [`mmap`] adds an
+//! area, [`munmap`] removes a range from them, and [`mprotect`] changes the
+//! permissions of a range. Both may split an area at the boundaries of the range,
+//! and neighbouring areas with the same permissions are merged afterwards.
+//!
+//! Pages of an area are allocated and zero-filled by [`populate`] on the first
+//! access. An area without any permission (`PROT_NONE`) can't be mapped by the
+//! page table, so its frames are parked in the area until it becomes accessible
+//! again.
+//!
+//! New areas are placed top-down below the user stacks, and above the heap.
+//!
+//! An area is never both writable and executable.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::mem::pagetable::PTEFlags;
+use crate::mem::{FrameTable, PageAlign, PG_SIZE};
+use crate::smp::tlb::Shootdown;
+use crate::thread::current;
+use crate::userproc::{load, Process};
+
+pub const PROT_NONE: usize = 0;
+pub const PROT_READ: usize = 1;
+pub const PROT_WRITE: usize = 2;
+pub const PROT_EXEC: usize = 4;
+
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+/// How a page is accessed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl Access {
+    /// The permission a page needs for the access.
+    pub fn flag(self) -> PTEFlags {
+        match self {
+            Access::Read => PTEFlags::R,
+            Access::Write => PTEFlags::W,
+            Access::Execute => PTEFlags::X,
+        }
+    }
+}
+
+struct Vma {
+    /// The process owning the area
+    pid: isize,
+    end: usize,
+    prot: usize,
+    /// Frames of pages not mapped while the area is `PROT_NONE`, by user address
+    parked: BTreeMap<usize, usize>,
+}
+
+impl Vma {
+    fn allows(&self, access: Access) -> bool {
+        let bit = match access {
+            Access::Read => PROT_READ,
+            Access::Write => PROT_WRITE,
+            Access::Execute => PROT_EXEC,
+        };
+        self.prot & bit != 0
+    }
+}
+
+impl Drop for Vma {
+    fn drop(&mut self) {
+        // Mapped frames are freed by the owner, or go with the page table.
+        for &frame in self.parked.values() {
+            FrameTable::dealloc_page(frame);
+        }
+    }
+}
+
+/// Whether `prot` is made of known bits, and is not both writable and executable.
+fn valid(prot: usize) -> bool {
+    prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) == 0
+        && prot & (PROT_WRITE | PROT_EXEC) != PROT_WRITE | PROT_EXEC
+}
+
+/// Page table flags of pages with permissions `prot`, which must not be
+/// `PROT_NONE`. RISC-V doesn't allow writable pages that are not readable.
+fn flags(prot: usize) -> PTEFlags {
+    let mut flags = PTEFlags::V | PTEFlags::U;
+    if prot & (PROT_READ | PROT_WRITE) != 0 {
+        flags |= PTEFlags::R;
+    }
+    if prot & PROT_WRITE != 0 {
+        flags |= PTEFlags::W;
+    }
+    if prot & PROT_EXEC != 0 {
+        flags |= PTEFlags::X;
+    }
+    flags
+}
+
+/// Areas of a process, indexed by their start addresses.
+pub struct Vmas(BTreeMap<usize, Vma>);
+
+impl Vmas {
+    pub fn new() -> Self {
+        Self(BTreeMap::new())
+    }
+
+    /// The area containing `addr`.
+    fn find(&self, addr: usize) -> Option<&Vma> {
+        self.0
+            .range(..=addr)
+            .next_back()
+            .map(|(_, vma)| vma)
+            .filter(|vma| addr < vma.end)
+    }
+
+    /// Whether any area overlaps `[start, end)`.
+    pub fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.0
+            .range(..end)
+            .next_back()
+            .is_some_and(|(_, vma)| start < vma.end)
+    }
+
+    /// Whether `[start, end)` is entirely covered by areas.
+    fn covers(&self, start: usize, end: usize) -> bool {
+        let mut next = start;
+        for (&vma_start, vma) in self.0.range(..end) {
+            if vma.end <= next {
+                continue;
+            }
+            if vma_start > next {
+                return false;
+            }
+            next = vma.end;
+        }
+        next >= end
+    }
+
+    /// The highest free range of `len` bytes in `[floor, SEGMENT_LIMIT)`.
+    fn find_free(&self, len: usize, floor: usize) -> Option<usize> {
+        let mut top = load::SEGMENT_LIMIT;
+        for (&start, vma) in self.0.iter().rev() {
+            if top.checked_sub(len).is_some_and(|free| free >= vma.end) {
+                break;
+            }
+            top = start;
+        }
+        top.checked_sub(len).filter(|start| *start >= floor)
+    }
+
+    /// Splits the area containing `addr`, if `addr` is inside of it.
+    fn split(&mut self, addr: usize) {
+        let Some((&start, vma)) = self.0.range_mut(..addr).next_back() else {
+            return;
+        };
+        if addr >= vma.end {
+            return;
+        }
+
+        let upper = Vma {
+            pid: vma.pid,
+            end: vma.end,
+            prot: vma.prot,
+            parked: vma.parked.split_off(&addr),
+        };
+        vma.end = addr;
+        debug_assert!(start < addr);
+        self.0.insert(addr, upper);
+    }
+
+    /// Merges neighbouring areas with the same permissions.
+    fn merge(&mut self) {
+        let starts: Vec<usize> = self.0.keys().copied().collect();
+        for start in starts.into_iter().rev() {
+            let Some((&prev_start, prev)) = self.0.range(..start).next_back() else {
+                continue;
+            };
+            let (prev_end, prev_prot) = (prev.end, prev.prot);
+            let vma = &self.0[&start];
+            if prev_end != start || prev_prot != vma.prot {
+                continue;
+            }
+
+            let mut vma = self.0.remove(&start).unwrap();
+            let prev = self.0.get_mut(&prev_start).unwrap();
+            prev.end = vma.end;
+            prev.parked.append(&mut vma.parked);
+        }
+    }
+}
+
+impl Default for Vmas {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Maps `len` bytes of anonymous memory with permissions `prot`. Only private
+/// mappings are supported. A non-null `addr` is a hint, which is taken if the
+/// range there is free.
+///
+/// ## Return
+/// - The start of the mapping.
+/// - `-1`: If the arguments are invalid, or there's not enough space.
+pub fn mmap(addr: usize, len: usize, prot: usize, flags: usize) -> isize {
+    if flags != MAP_PRIVATE | MAP_ANONYMOUS || len == 0 || !valid(prot) || !addr.is_aligned() {
+        return -1;
+    }
+    let Some(len) = len.checked_add(PG_SIZE - 1).map(|len| len.floor()) else {
+        return -1;
+    };
+
+    let current = current();
+    let process = current.process.as_ref().unwrap();
+
+    // The heap can't grow while the areas are being changed.
+    let heap = process.heap.lock();
+    let floor = heap.brk().ceil();
+    let mut vmas = process.vmas.lock();
+
+    let hinted = addr != 0
+        && addr >= floor
+        && addr
+            .checked_add(len)
+            .is_some_and(|end| end <= load::SEGMENT_LIMIT && !vmas.overlaps(addr, end));
+    let start = match hinted {
+        true => addr,
+        false => match vmas.find_free(len, floor) {
+            Some(start) => start,
+            None => return -1,
+        },
+    };
+
+    vmas.0.insert(
+        start,
+        Vma {
+            pid: process.pid(),
+            end: start + len,
+            prot,
+            parked: BTreeMap::new(),
+        },
+    );
+    vmas.merge();
+
+    start as isize
+}
+
+/// Unmaps the pages in `[addr, addr + len)` and frees them. Pages outside any
+/// area are skipped.
+///
+/// ## Return
+/// - `0`: On success.
+/// - `-1`: If `addr` is not page-aligned, or `len` is zero.
+pub fn munmap(addr: usize, len: usize) -> isize {
+    let Some(end) = range_end(addr, len) else {
+        return -1;
+    };
+
+    let current = current();
+    let process = current.process.as_ref().unwrap();
+    let mut vmas = process.vmas.lock();
+
+    vmas.split(addr);
+    vmas.split(end);
+    let starts: Vec<usize> = vmas.0.range(addr..end).map(|(&s, _)| s).collect();
+
+    let mut pagetable = process.pagetable.lock();
+    for start in starts {
+        let vma = vmas.0.remove(&start).unwrap();
+        FrameTable::unmap(&mut pagetable, process.pid(), start, vma.end);
+        // Parked frames are freed by `vma` on drop.
+    }
+    0
+}
+
+/// Changes the permissions of `[addr, addr + len)` to `prot`, and rewrites the
+/// entries of pages already mapped.
+///
+/// ## Return
+/// - `0`: On success.
+/// - `-1`: If the arguments are invalid, or the range is not entirely mapped.
+pub fn mprotect(addr: usize, len: usize, prot: usize) -> isize {
+    let Some(end) = range_end(addr, len) else {
+        return -1;
+    };
+    if !valid(prot) {
+        return -1;
+    }
+
+    let current = current();
+    let process = current.process.as_ref().unwrap();
+    let mut vmas = process.vmas.lock();
+    if !vmas.covers(addr, end) {
+        return -1;
+    }
+
+    vmas.split(addr);
+    vmas.split(end);
+
+    let mut pagetable = process.pagetable.lock();
+    let mut shootdown = Shootdown::new();
+    for (&start, vma) in vmas.0.range_mut(addr..end) {
+        vma.prot = prot;
+
+        for page in (start..vma.end).step_by(PG_SIZE) {
+            if prot == PROT_NONE {
+                if let Some(frame) = FrameTable::park(&mut pagetable, process.pid(), page) {
+                    vma.parked.insert(page, frame);
+                    shootdown.add(page, PG_SIZE);
+                }
+            } else if let Some(frame) = vma.parked.remove(&page) {
+                FrameTable::unpark(&mut pagetable, frame, process.pid(), page, flags(prot));
+            } else if FrameTable::protect(&pagetable, process.pid(), page, flags(prot)) {
+                shootdown.add(page, PG_SIZE);
+            }
+        }
+    }
+    shootdown.flush();
+    drop(pagetable);
+
+    vmas.merge();
+    0
+}
+
+/// Maps `frame` zero-filled at `addr`, if it's in an area allowing `access`, and
+/// not mapped yet.
+///
+/// ## Return
+/// - Whether `addr` is now accessible by `access`. The frame is used up if so.
+pub fn populate(process: &Process, addr: usize, access: Access, frame: usize) -> bool {
+    let vmas = process.vmas.lock();
+    let Some(vma) = vmas.find(addr).filter(|vma| vma.allows(access)) else {
+        return false;
+    };
+
+    process.populate(frame, addr.floor(), flags(vma.prot));
+    true
+}
+
+/// Whether `addr` is in an area, i.e. a fault there is a protection fault if the
+/// page is present or its area is `PROT_NONE`.
+pub fn contains(process: &Process, addr: usize) -> bool {
+    process.vmas.lock().find(addr).is_some()
+}
+
+/// The end of `[addr, addr + len)` rounded up to pages, if `addr` is aligned and
+/// the range is not empty.
+fn range_end(addr: usize, len: usize) -> Option<usize> {
+    if !addr.is_aligned() || len == 0 {
+        return None;
+    }
+    addr.checked_add(len)?
+        .checked_add(PG_SIZE - 1)
+        .map(|end| end.floor())
+}
@@ -1,14 +1,10 @@
 #![allow(dead_code)]
 
-use alloc::sync::Arc;
-
-use crate::sync::Semaphore;
-
 mod schedule;
 mod unit;
 pub mod user;
 
-pub fn main(sema: Arc<Semaphore>, _bootargs: &str) {
+pub fn main(_bootargs: &str) {
     #[cfg(feature = "test-unit")]
     unit::main();
 
@@ -19,5 +15,4 @@ pub fn main(sema: Arc<Semaphore>, _bootargs: &str) {
     schedule::main(_bootargs);
 
     kprintln!("Leaving test...");
-    sema.up();
 }
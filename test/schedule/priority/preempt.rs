@@ -30,7 +30,7 @@ pub fn main() {
         .spawn();
 
     assert_eq!(
-        child.status(),
+        child.thread().status(),
         Status::Dying,
         "Thread 2 should have just exited."
     );
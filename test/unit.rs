@@ -9,11 +9,19 @@ pub fn main() {
     sync::condvar::main();
     #[cfg(any(feature = "test-sync", feature = "test-sync-sema_fifo"))]
     sync::sema_fifo::main();
+    #[cfg(any(feature = "test-sync", feature = "test-sync-timeout"))]
+    sync::timeout::main();
+    #[cfg(any(feature = "test-sync", feature = "test-sync-rwlock"))]
+    sync::rwlock::main();
+    #[cfg(any(feature = "test-sync", feature = "test-sync-barrier"))]
+    sync::barrier::main();
 
     #[cfg(any(feature = "test-thread", feature = "test-thread-adder"))]
     thread::adder::main();
     #[cfg(any(feature = "test-thread", feature = "test-thread-block"))]
     thread::block::main();
+    #[cfg(any(feature = "test-thread", feature = "test-thread-join"))]
+    thread::join::main();
 
     // ! This should fail.
     #[cfg(any(feature = "test-thread", feature = "test-thread-bomb"))]
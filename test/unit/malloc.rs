@@ -1,7 +1,8 @@
 use alloc::{boxed::Box, collections::VecDeque, vec::Vec};
 use core::alloc::Layout;
 
-use crate::mem::{kalloc, malloc::Heap};
+use crate::mem::malloc::{Heap, EMPTY_ARENAS};
+use crate::mem::{kalloc, kfree, Palloc, PG_SIZE};
 
 struct T<const N: usize> {
     data: [u8; N],
@@ -127,9 +128,37 @@ fn layout() {
     // let _ = Box::new([0xccu8; 4096 * 4]);
 }
 
+/// Arenas emptied by a burst of allocations go back to the page allocator, except
+/// for a few kept to absorb the next burst.
+fn reclaim() {
+    const BLOCKS: usize = 40 * PG_SIZE / 64;
+
+    let m = Heap::get();
+    let mut blocks = Vec::with_capacity(BLOCKS);
+    let (total, free_pages) = (m.total(), Palloc::free_pages());
+
+    (0..BLOCKS).for_each(|_| blocks.push(kalloc(64, 64)));
+    assert!(m.total() >= total + 30 * PG_SIZE);
+    assert!(Palloc::free_pages() <= free_pages - 30);
+
+    blocks.iter().for_each(|&p| kfree(p, 64, 64));
+    assert!(m.total() <= total + EMPTY_ARENAS * PG_SIZE);
+    assert!(Palloc::free_pages() + EMPTY_ARENAS >= free_pages);
+
+    // A block taken and returned over and over doesn't take a page each time.
+    let total = m.total();
+    for _ in 0..100 {
+        let p = kalloc(64, 64);
+        assert_eq!(m.total(), total);
+        kfree(p, 64, 64);
+    }
+    assert_eq!(m.total(), total);
+}
+
 pub fn main() {
     vec_simple();
     vec_exhaustive();
 
     layout();
+    reclaim();
 }
@@ -1,2 +1,5 @@
+pub mod barrier;
 pub mod condvar;
+pub mod rwlock;
 pub mod sema_fifo;
+pub mod timeout;
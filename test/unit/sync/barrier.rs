@@ -0,0 +1,39 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::sync::{Barrier, Mutex};
+use crate::thread;
+
+const N: usize = 4;
+const ROUNDS: usize = 3;
+
+pub fn main() {
+    let barrier: Arc<Barrier> = Arc::new(Barrier::new(N));
+    let arrived: Arc<Mutex<usize>> = Arc::new(Mutex::new(0));
+
+    let workers: Vec<_> = (0..N)
+        .map(|_| {
+            let barrier = barrier.clone();
+            let arrived = arrived.clone();
+            thread::spawn("worker", move || {
+                let mut leaders = 0;
+                for round in 0..ROUNDS {
+                    *arrived.lock() += 1;
+                    if barrier.wait() {
+                        leaders += 1;
+                    }
+                    // Nobody passes until all have arrived in this round.
+                    assert!(*arrived.lock() >= (round + 1) * N);
+                    barrier.wait();
+                }
+                leaders
+            })
+        })
+        .collect();
+
+    let leaders: usize = workers.into_iter().map(|w| w.join().unwrap()).sum();
+    assert_eq!(leaders, ROUNDS);
+    assert_eq!(*arrived.lock(), ROUNDS * N);
+
+    kprintln!("Barrier test passed.");
+}
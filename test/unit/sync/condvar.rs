@@ -16,7 +16,7 @@ pub fn main() {
     }
     thread::schedule();
 
-    assert_eq!(p.status(), Status::Dying);
+    assert_eq!(p.thread().status(), Status::Dying);
     kprintln!("Main continue.");
 }
 
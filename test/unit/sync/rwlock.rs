@@ -0,0 +1,66 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::sync::RwLock;
+use crate::thread::{self, JoinHandle, Status};
+
+pub fn main() {
+    let lock: Arc<RwLock<i32>> = Arc::new(RwLock::new(0));
+
+    // Readers share the lock.
+    {
+        let r1 = lock.read();
+        let r2 = lock.read();
+        assert_eq!(*r1 + *r2, 0);
+    }
+
+    // Writers exclude each other, and readers see whole updates only.
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let lock = lock.clone();
+            thread::spawn("writer", move || {
+                for _ in 0..10 {
+                    let mut guard = lock.write();
+                    let value = *guard;
+                    thread::schedule();
+                    *guard = value + 1;
+                }
+            })
+        })
+        .chain((0..4).map(|_| {
+            let lock = lock.clone();
+            thread::spawn("reader", move || {
+                for _ in 0..10 {
+                    let guard = lock.read();
+                    let value = *guard;
+                    thread::schedule();
+                    assert_eq!(*guard, value);
+                }
+            })
+        }))
+        .collect();
+
+    workers.into_iter().for_each(|w| w.join().unwrap());
+    assert_eq!(*lock.read(), 40);
+
+    // A waiting writer goes before new readers.
+    let guard = lock.read();
+    let l = lock.clone();
+    let writer = thread::spawn("writer", move || *l.write() = -1);
+    wait_blocked(&writer);
+    let l = lock.clone();
+    let reader = thread::spawn("reader", move || assert_eq!(*l.read(), -1));
+    wait_blocked(&reader);
+    drop(guard);
+
+    writer.join().unwrap();
+    reader.join().unwrap();
+
+    kprintln!("RwLock test passed.");
+}
+
+fn wait_blocked(handle: &JoinHandle<()>) {
+    while handle.thread().status() != Status::Blocked {
+        thread::schedule();
+    }
+}
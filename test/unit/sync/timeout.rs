@@ -0,0 +1,88 @@
+use alloc::sync::Arc;
+
+use crate::sbi::timer::timer_ticks;
+use crate::sync::{Condvar, Lock, Mutex, Semaphore, Sleep};
+use crate::thread;
+
+pub fn main() {
+    sema();
+    sleep();
+    condvar();
+    kprintln!("Timeout test passed.");
+}
+
+fn sema() {
+    let sema = Arc::new(Semaphore::new(1));
+
+    assert!(sema.try_down());
+    assert!(!sema.try_down());
+
+    // Nobody ups it, so the timer wakes it up.
+    let start = timer_ticks();
+    assert!(sema.down_timeout(5));
+    assert!(timer_ticks() >= start + 5);
+    assert!(sema.down_timeout(0));
+
+    // Woken up by `up` long before the deadline.
+    let s = sema.clone();
+    let upper = thread::spawn("upper", move || {
+        thread::sleep(2);
+        s.up();
+    });
+    assert!(!sema.down_timeout(1000));
+    upper.join().unwrap();
+
+    // Race `up` against the deadline. Either way, the thread must be woken exactly once.
+    for ticks in 0..4 {
+        let s = sema.clone();
+        let upper = thread::spawn("racer", move || {
+            thread::sleep(2);
+            s.up();
+        });
+        if sema.down_timeout(ticks) {
+            sema.down();
+        }
+        upper.join().unwrap();
+        assert_eq!(sema.value(), 0);
+    }
+}
+
+fn sleep() {
+    let lock = Arc::new(Sleep::default());
+
+    lock.acquire();
+    let l = lock.clone();
+    let other = thread::spawn("other", move || assert!(!l.try_acquire()));
+    other.join().unwrap();
+    lock.release();
+
+    let l = lock.clone();
+    let other = thread::spawn("other", move || {
+        assert!(l.try_acquire());
+        l.release();
+    });
+    other.join().unwrap();
+}
+
+fn condvar() {
+    let pair = Arc::new((Condvar::new(), Mutex::<_, Sleep>::new(false)));
+    let (cvar, lock) = &*pair;
+
+    let mut guard = lock.lock();
+    assert!(cvar.wait_timeout(&mut guard, 3));
+    assert!(!*guard);
+
+    let p = pair.clone();
+    let notifier = thread::spawn("notifier", move || {
+        let (cvar, lock) = &*p;
+        thread::sleep(2);
+        let mut guard = lock.lock();
+        *guard = true;
+        cvar.notify_one();
+    });
+    while !*guard {
+        assert!(!cvar.wait_timeout(&mut guard, 1000));
+    }
+    drop(guard);
+    notifier.join().unwrap();
+}
@@ -1,6 +1,4 @@
-use alloc::sync::Arc;
-
-use crate::sync::{self, Mutex, Semaphore};
+use crate::sync::{self, Mutex};
 use crate::thread;
 
 static mut X: Option<Mutex<usize, sync::Sleep>> = None;
@@ -10,32 +8,27 @@ static NUM: usize = 5000;
 
 #[allow(unused)]
 pub fn main() {
-    let finish = Arc::new(Semaphore::new(0));
-    let f1 = finish.clone();
-    let f2 = finish.clone();
-    let f3 = finish.clone();
-    let f4 = finish.clone();
     unsafe {
         X.replace(Mutex::new(0));
     }
 
-    thread::spawn("good_adder1", move || good_adder(f1));
-    thread::spawn("good_adder2", move || good_adder(f2));
-    finish.down();
-    finish.down();
+    let a1 = thread::spawn("good_adder1", good_adder);
+    let a2 = thread::spawn("good_adder2", good_adder);
+    a1.join().unwrap();
+    a2.join().unwrap();
     kprintln!("Good adder done.");
 
-    thread::spawn("bad_adder1", move || bad_adder(f3));
-    thread::spawn("bad_adder2", move || bad_adder(f4));
-    finish.down();
-    finish.down();
+    let a3 = thread::spawn("bad_adder1", bad_adder);
+    let a4 = thread::spawn("bad_adder2", bad_adder);
+    a3.join().unwrap();
+    a4.join().unwrap();
     kprintln!("Bad adder done.");
 
     assert_eq!(unsafe { *(X.as_ref().unwrap().lock()) }, 2 * NUM);
     kprintln!("Bad adder results: {}:{}", unsafe { Y }, 2 * NUM);
 }
 
-pub fn good_adder(finish: Arc<Semaphore>) {
+pub fn good_adder() {
     let mut i = 0;
     while i < NUM {
         let mut x = unsafe { X.as_ref().unwrap().lock() };
@@ -43,11 +36,9 @@ pub fn good_adder(finish: Arc<Semaphore>) {
         *x += 1;
         i += 1;
     }
-
-    finish.up();
 }
 
-pub fn bad_adder(finish: Arc<Semaphore>) {
+pub fn bad_adder() {
     let mut i = 0;
     while i < NUM {
         let mut y = unsafe { Y };
@@ -57,6 +48,4 @@ pub fn bad_adder(finish: Arc<Semaphore>) {
 
         i += 1;
     }
-
-    finish.up();
 }
@@ -20,6 +20,7 @@ pub fn main() {
     let s1 = s.clone();
 
     let waiter = thread::spawn("waiter", move || waiter_mutex(s1));
+    let waiter = waiter.thread();
     let guard = s.mutex.lock();
 
     s.sema.up();
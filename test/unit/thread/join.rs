@@ -0,0 +1,23 @@
+use alloc::vec::Vec;
+
+use crate::thread::{self, Panicked};
+
+const NUM: usize = 8;
+
+pub fn main() {
+    let handles: Vec<_> = (0..NUM)
+        .map(|i| thread::spawn("square", move || i * i))
+        .collect();
+
+    for (i, handle) in handles.into_iter().enumerate() {
+        assert_eq!(handle.join(), Ok(i * i));
+    }
+    kprintln!("Join values done.");
+
+    let bomb = thread::spawn("bomb", || {
+        assert!(false, "bomb exploded");
+        0
+    });
+    assert_eq!(bomb.join(), Err(Panicked));
+    kprintln!("Join panicked thread done.");
+}
@@ -1,22 +1,29 @@
 use alloc::string::ToString;
 use alloc::vec::Vec;
 
+use crate::cmdline;
 use crate::fs::disk::DISKFS;
 use crate::fs::FileSys;
+use crate::mem::replace::STATS;
 use crate::thread;
 use crate::userproc;
+use crate::userproc::signal::{killed, SIGILL, SIGSEGV, SIGTRAP};
 
-const LEN: usize = 6;
-const KILLED_USERPROC: [&str; LEN] = [
-    "bad-load",
-    "bad-load2",
-    "bad-jump",
-    "bad-jump2",
-    "bad-store",
-    "bad-store2",
+const LEN: usize = 8;
+/// Processes terminated by a fault, with the signal it raises.
+const KILLED_USERPROC: [(&str, usize); LEN] = [
+    ("bad-load", SIGSEGV),
+    ("bad-load2", SIGSEGV),
+    ("bad-jump", SIGSEGV),
+    ("bad-jump2", SIGSEGV),
+    ("bad-store", SIGSEGV),
+    ("bad-store2", SIGSEGV),
+    ("bad-insn", SIGILL),
+    ("bad-break", SIGTRAP),
 ];
-const KILLED_EXIT: isize = -1;
 const NORMAL_EXIT: isize = 0;
+/// Processes paging under a replacement policy, which must have evicted frames.
+const PAGING_USERPROC: [(&str, &str); 2] = [("page-clock", "clock"), ("page-aging", "aging")];
 
 pub fn main(cmd: &str) {
     kprintln!("Executing command {}", cmd);
@@ -25,12 +32,18 @@ pub fn main(cmd: &str) {
     let name = argv[0].clone();
     let file = DISKFS.open(name.as_str().into()).unwrap();
 
-    let r = userproc::wait(userproc::execute(file, argv)).unwrap();
-    if KILLED_USERPROC.iter().find(|n| name.eq(**n)).is_some() {
-        assert_eq!(r, KILLED_EXIT);
+    let r = userproc::wait(userproc::execute(file, argv, Vec::new())).unwrap();
+    if let Some((_, signal)) = KILLED_USERPROC.iter().find(|(n, _)| name.eq(*n)) {
+        assert_eq!(r, killed(*signal));
     } else {
         assert_eq!(r, NORMAL_EXIT);
     }
 
+    if let Some((_, policy)) = PAGING_USERPROC.iter().find(|(n, _)| name.eq(*n)) {
+        assert_eq!(cmdline::get().replace, *policy);
+        let (_, evictions, _) = STATS.get();
+        assert!(evictions > 0, "no frame is evicted under {}", policy);
+    }
+
     thread::schedule();
 }
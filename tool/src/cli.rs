@@ -66,6 +66,13 @@ pub struct TestArgs {
     #[arg(short, long)]
     pub previous_failed: bool,
 
+    /// Kernel boot options passed through `-append`, before the test's own arguments.
+    ///
+    /// Example:
+    /// `tool test -c args-none -a loglevel=debug,tick=100`
+    #[arg(short, long, value_delimiter = ',')]
+    pub append: Vec<String>,
+
     /// Only show the command line to run, without starting it.
     #[arg(long)]
     pub dry: bool,
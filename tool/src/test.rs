@@ -18,6 +18,7 @@ type Runner = fn(&String, Vec<&str>, &mut Record) -> Result<()>;
 static RUNNER: OnceCell<Runner> = OnceCell::new();
 static CTRLC: Lazy<Arc<AtomicBool>> = Lazy::new(|| Arc::new(AtomicBool::new(false)));
 static GDB: OnceCell<bool> = OnceCell::new();
+static BOOTARGS: OnceCell<String> = OnceCell::new();
 
 struct Record(Vec<String>, Vec<String>);
 
@@ -50,6 +51,8 @@ pub fn main(mut args: crate::cli::TestArgs) -> Result<()> {
     } else {
         GDB.get_or_init(|| false);
     }
+    // Kernel boot options shared by all cases.
+    BOOTARGS.get_or_init(|| args.append.join(" "));
     // Set runner.
     if args.dry {
         RUNNER.get_or_init(|| dry_run);
@@ -74,9 +77,30 @@ pub fn main(mut args: crate::cli::TestArgs) -> Result<()> {
     Ok(())
 }
 
+/// Splits the arguments of a test case into its own boot options, which come
+/// before a `--`, and the arguments of the program.
+fn case_args(args: &str) -> (String, String) {
+    let words: Vec<_> = args.split_whitespace().collect();
+    match words.iter().position(|&w| w == "--") {
+        Some(i) => (words[..i].join(" "), words[i + 1..].join(" ")),
+        None => (String::new(), words.join(" ")),
+    }
+}
+
+/// Joins the boot options and a test case's arguments into a kernel command line.
+/// Options must come before the program name, which ends them.
+fn bootargs(words: &[&str]) -> String {
+    std::iter::once(BOOTARGS.get().unwrap().as_str())
+        .chain(words.iter().copied())
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 fn test_user(cases: Cases, record: &mut Record) -> Result<()> {
     for (k, v) in cases.0 {
-        let args = format!("{}{}{}", &k, if !v.0.is_empty() { " " } else { "" }, &v.0);
+        let (options, argv) = case_args(&v.0);
+        let args = bootargs(&[&options, &k, &argv]);
         let mut cargo = vec!["run", "-r", "-q", "-F", "test-user", "--", "-append", &args];
         if *GDB.get().unwrap() {
             // to debug mode
@@ -96,11 +120,15 @@ fn test_user(cases: Cases, record: &mut Record) -> Result<()> {
 fn test_krnl(cases: Cases, record: &mut Record) -> Result<()> {
     for (k, _v) in cases.0 {
         let feature = format!("test-{}", &k);
-        let mut cargo = vec!["run", "-r", "-q", "-F", &feature];
+        let args = bootargs(&[]);
+        let mut cargo = vec!["run", "-r", "-q", "-F", &feature, "--"];
+        if !args.is_empty() {
+            cargo.extend(["-append", &args].iter());
+        }
         if *GDB.get().unwrap() {
             // to debug mode
             cargo.remove(1);
-            cargo.extend(["--", "-s", "-S"].iter());
+            cargo.extend(["-s", "-S"].iter());
         }
         let runner = RUNNER.get().unwrap();
         let _ = runner(&k, cargo, record);
@@ -114,7 +142,8 @@ fn test_krnl(cases: Cases, record: &mut Record) -> Result<()> {
 
 fn test_schedule(cases: Cases, record: &mut Record) -> Result<()> {
     for (k, v) in cases.0 {
-        let args = format!("{}{}{}", &k, if !v.0.is_empty() { " " } else { "" }, &v.0);
+        let (options, argv) = case_args(&v.0);
+        let args = bootargs(&[&options, &k, &argv]);
         let mut cargo = vec![
             "run",
             "-r",
@@ -0,0 +1,89 @@
+// A first-fit allocator on top of sbrk(). Free blocks are kept in a list
+// sorted by address, and neighbours are merged when a block is freed.
+
+#include "user.h"
+
+#define ALIGN 16
+// The heap grows by at least this many bytes at a time.
+#define CHUNK 4096
+
+typedef union header {
+    struct {
+        union header* next; // Next free block, only valid in the free list
+        size_t size;        // Size of the block in units, including the header
+    };
+    char align[ALIGN];
+} Header;
+
+static Header* freelist;
+
+// Inserts a block into the free list, and merges it with its neighbours.
+static void insert(Header* block) {
+    Header** link = &freelist;
+    while (*link && *link < block) {
+        Header* prev = *link;
+        if (prev + prev->size == block) {
+            // Merge with the previous one, and maybe the next one as well.
+            prev->size += block->size;
+            if (prev->next && prev + prev->size == prev->next) {
+                prev->size += prev->next->size;
+                prev->next = prev->next->next;
+            }
+            return;
+        }
+        link = &prev->next;
+    }
+
+    block->next = *link;
+    if (block->next && block + block->size == block->next) {
+        block->size += block->next->size;
+        block->next = block->next->next;
+    }
+    *link = block;
+}
+
+// Grows the heap by at least `units`, and adds the new memory to the free list.
+static int grow(size_t units) {
+    size_t bytes = ROUND_UP(units * sizeof(Header), CHUNK);
+    Header* block = sbrk(bytes);
+    if (block == (Header*)-1) return -1;
+    block->size = bytes / sizeof(Header);
+    insert(block);
+    return 0;
+}
+
+void* malloc(size_t size) {
+    if (size == 0) return NULL;
+    size_t units = (size + sizeof(Header) - 1) / sizeof(Header) + 1;
+
+    // sbrk() returns the end of the executable the first time, which may not be aligned.
+    static int aligned;
+    if (!aligned) {
+        char* end = sbrk(0);
+        if (sbrk(ROUND_UP(end, ALIGN) - (uint64)end) == (void*)-1) return NULL;
+        aligned = 1;
+    }
+
+    for (;;) {
+        for (Header** link = &freelist; *link; link = &(*link)->next) {
+            Header* block = *link;
+            if (block->size < units) continue;
+
+            if (block->size == units) {
+                *link = block->next;
+            } else {
+                // Take the tail of it.
+                block->size -= units;
+                block += block->size;
+                block->size = units;
+            }
+            return block + 1;
+        }
+        if (grow(units) < 0) return NULL;
+    }
+}
+
+void free(void* ptr) {
+    if (ptr == NULL) return;
+    insert((Header*)ptr - 1);
+}
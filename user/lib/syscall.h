@@ -18,3 +18,28 @@
 /* Project 4 only. */
 #define SYS_CHDIR 15 /**< Change the current directory. */
 #define SYS_MKDIR 16 /**< Create a directory. */
+
+/* Multi-threading. */
+#define SYS_THREAD_CREATE 17 /**< Create a thread in this process. */
+#define SYS_THREAD_EXIT 18   /**< Terminate this thread. */
+#define SYS_THREAD_JOIN 19   /**< Wait for a thread to die. */
+#define SYS_FUTEX_WAIT 20    /**< Wait on a word in memory. */
+#define SYS_FUTEX_WAKE 21    /**< Wake threads waiting on a word. */
+
+/* Signals. */
+#define SYS_KILL 22      /**< Send a signal to a process. */
+#define SYS_SIGACTION 23 /**< Set the action of a signal. */
+#define SYS_SIGRETURN 24 /**< Return from a signal handler. */
+
+/* Processes. */
+#define SYS_WAITPID 25 /**< Wait for a child, or any child. */
+#define SYS_EXECVE 26  /**< Start another process with environment variables. */
+
+/* Memory. */
+#define SYS_BRK 27  /**< Set the program break. */
+#define SYS_SBRK 28 /**< Move the program break. */
+#define SYS_MMAP_ANON 29    /**< Map anonymous memory. */
+#define SYS_MUNMAP_RANGE 30 /**< Unmap a range of anonymous memory. */
+#define SYS_MPROTECT 31     /**< Change permissions of anonymous memory. */
+#define SYS_GETRLIMIT 32    /**< Get the limits of a resource. */
+#define SYS_SETRLIMIT 33    /**< Set the limits of a resource. */
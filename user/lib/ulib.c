@@ -2,10 +2,13 @@
 
 #include "user.h"
 
+char** environ;
+
 // wrapper so that it's OK if main() does not call exit().
-void _main() {
-    extern void main();
-    main();
+void _main(int argc, char* argv[], char* envp[]) {
+    extern void main(int, char**, char**);
+    environ = envp;
+    main(argc, argv, envp);
     exit(NORMAL_EXIT);
 }
 
@@ -183,3 +186,15 @@ inline uint64 r_sp() {
     asm volatile("mv %0, sp" : "=r"(x));
     return x;
 }
+
+sighandler_t signal(int sig, sighandler_t handler) {
+    return sigaction(sig, handler, sigreturn);
+}
+
+char* getenv(const char* name) {
+    int len = strlen(name);
+
+    for (char** env = environ; env && *env; env++)
+        if (memcmp(*env, name, len) == 0 && (*env)[len] == '=') return *env + len + 1;
+    return NULL;
+}
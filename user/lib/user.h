@@ -13,6 +13,38 @@
 #define PANIC_EXIT 12345
 #define NORMAL_EXIT 0
 
+#define SIGILL 4
+#define SIGKILL 9
+#define SIGUSR1 10
+#define SIGSEGV 11
+#define SIGUSR2 12
+#define SIGTERM 15
+// Exit status of a process terminated by signal `sig`
+#define KILLED(sig) (-128 - (sig))
+
+typedef void (*sighandler_t)(int);
+#define SIG_DFL ((sighandler_t)0)
+#define SIG_IGN ((sighandler_t)1)
+#define SIG_ERR ((sighandler_t)-1)
+
+// Options of waitpid()
+#define WNOHANG 1
+
+// Permissions and flags of mmap_anon()
+#define PROT_NONE 0
+#define PROT_READ 1
+#define PROT_WRITE 2
+#define PROT_EXEC 4
+#define MAP_PRIVATE 0x02
+#define MAP_ANONYMOUS 0x20
+
+// Resources of getrlimit() and setrlimit()
+#define RLIMIT_STACK 3
+struct rlimit {
+    uint64 rlim_cur;
+    uint64 rlim_max;
+};
+
 #define panic(fmt, args...)                                                       \
     do {                                                                          \
         fprintf(2, "panicked at '" fmt "', %s:%d\n", ##args, __FILE__, __LINE__); \
@@ -42,6 +74,44 @@ int mmap(int fd, void* addr);
 void munmap(int mapid);
 int chdir(const char* dir);
 int mkdir(const char* dir);
+// `fn` runs on a stack of its own, and must end with thread_exit().
+int thread_create(void (*fn)(void*), void* arg);
+void thread_exit(int value);
+int thread_join(int tid);
+// Sleeps while `*addr == expected`, for at most `timeout` ticks (forever if negative).
+// Returns 0 if woken up, -1 if `*addr != expected`, and -2 if timed out.
+int futex_wait(int* addr, int expected, int timeout);
+// Wakes at most `n` threads waiting on `addr`. Returns the number woken up.
+int futex_wake(int* addr, int n);
+int kill(int pid, int sig);
+// A handler returns to `restorer`, which must call sigreturn(). Use signal() instead.
+sighandler_t sigaction(int sig, sighandler_t handler, void (*restorer)(void));
+void sigreturn(void);
+// Waits for child `pid`, or any child if `pid` is -1, and stores its exit status
+// at `status` unless it's null. Returns the pid of the child, 0 if none has exited
+// yet under WNOHANG, or -1 if there's no such child.
+int waitpid(int pid, int* status, int options);
+// Like exec(), with `envp` as the environment of the new process.
+// Both return ARGUMENT_TOO_LONG if the arguments don't fit in the initial stack.
+#define ARGUMENT_TOO_LONG (-11)
+int execve(const char* pathname, const char* argv[], const char* envp[]);
+// Sets the end of the heap to `addr`. Returns 0 on success, or -1.
+int brk(void* addr);
+// Moves the end of the heap by `increment` bytes. Returns the old end, or (void*)-1.
+void* sbrk(long increment);
+// Anonymous memory, apart from mmap() and munmap() which map files. Only
+// MAP_ANONYMOUS | MAP_PRIVATE is supported, and `addr` is a hint. Returns the
+// start of the mapping, or (void*)-1.
+void* mmap_anon(void* addr, size_t len, int prot, int flags);
+// Unmaps the pages in a range. Returns 0 on success, or -1.
+int munmap_range(void* addr, size_t len);
+// Changes the permissions of a range, which must be entirely mapped. Returns 0
+// on success, or -1.
+int mprotect(void* addr, size_t len, int prot);
+// Only RLIMIT_STACK is supported, whose hard limit is fixed. Processes started
+// by exec() inherit the limit. Both return 0 on success, or -1.
+int getrlimit(int resource, struct rlimit* rlim);
+int setrlimit(int resource, const struct rlimit* rlim);
 
 // ulib.c
 void fprintf(int fd, const char* fmt, ...);
@@ -57,5 +127,13 @@ void* memcpy(void*, const void*, size_t);
 void check_file(const char*, const void* buf, size_t);
 void check_file_handle(int fd, const char* file_name, const void* buf_, size_t size);
 uint64 r_sp();
+sighandler_t signal(int sig, sighandler_t handler);
+// Environment of this process, as passed to main()
+extern char** environ;
+char* getenv(const char* name);
+
+// malloc.c
+void* malloc(size_t size);
+void free(void* ptr);
 
 #endif
@@ -30,3 +30,20 @@ entry("mmap");
 entry("munmap");
 entry("chdir");
 entry("mkdir");
+entry("thread_create");
+entry("thread_exit");
+entry("thread_join");
+entry("futex_wait");
+entry("futex_wake");
+entry("kill");
+entry("sigaction");
+entry("sigreturn");
+entry("waitpid");
+entry("execve");
+entry("brk");
+entry("sbrk");
+entry("mmap_anon");
+entry("munmap_range");
+entry("mprotect");
+entry("getrlimit");
+entry("setrlimit");
@@ -0,0 +1,9 @@
+/** Hits a breakpoint without a debugger, which must terminate
+   the process with the exit status of SIGTRAP. */
+
+#include "user.h"
+
+void main() {
+    asm volatile("ebreak");
+    panic("should have exited");
+}
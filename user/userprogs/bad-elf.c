@@ -0,0 +1,137 @@
+/** Builds small executables by hand and runs them. A well-formed one exits
+   with 42 after reading zeros beyond its file size, and each malformed one
+   is refused by exec. */
+
+#include "user.h"
+
+#define PF_X 1
+#define PF_W 2
+#define PF_R 4
+#define PT_LOAD 1
+
+#define BASE 0x10000
+#define CODE_OFF 0x1000
+#define FILE_SIZE 0x1200
+
+typedef struct {
+    uchar ident[16];
+    uint16 type, machine;
+    uint32 version;
+    uint64 entry, phoff, shoff;
+    uint32 flags;
+    uint16 ehsize, phentsize, phnum, shentsize, shnum, shstrndx;
+} Ehdr;
+
+typedef struct {
+    uint32 type, flags;
+    uint64 offset, vaddr, paddr, filesz, memsz, align;
+} Phdr;
+
+// Loads a word right after the file contents, and exits with it plus 42.
+static const uint32 code[] = {
+    0x00000297, // auipc t0, 0
+    0x1002b503, // ld a0, 0x100(t0)
+    0x02a50513, // addi a0, a0, 42
+    0x00200893, // li a7, SYS_EXIT
+    0x00000073, // ecall
+};
+
+static uchar image[FILE_SIZE];
+
+static Ehdr* ehdr(void) { return (Ehdr*)image; }
+static Phdr* phdr(int i) { return (Phdr*)(image + sizeof(Ehdr)) + i; }
+
+// Resets the image to a well-formed executable.
+static void build(void) {
+    memset(image, 0, sizeof(image));
+    // Garbage where the loader should put zeros
+    memset(image + CODE_OFF + sizeof(code), 0xff, FILE_SIZE - CODE_OFF - sizeof(code));
+    memcpy(image + CODE_OFF, code, sizeof(code));
+
+    Ehdr* e = ehdr();
+    memcpy(e->ident, "\177ELF\2\1\1", 7);
+    e->type = 2;
+    e->machine = 0xf3;
+    e->version = 1;
+    e->entry = BASE;
+    e->phoff = sizeof(Ehdr);
+    e->ehsize = sizeof(Ehdr);
+    e->phentsize = sizeof(Phdr);
+    e->phnum = 1;
+
+    Phdr* p = phdr(0);
+    p->type = PT_LOAD;
+    p->flags = PF_R | PF_X;
+    p->offset = CODE_OFF;
+    p->vaddr = p->paddr = BASE;
+    p->filesz = sizeof(code);
+    p->memsz = 0x200;
+    p->align = 0x1000;
+}
+
+// Writes the image, and returns the exit status of it, or -1 if exec fails.
+static int run(const char* what) {
+    const char* args[] = {"elf-image", NULL};
+    int fd = open(args[0], O_CREATE | O_TRUNC | O_RDWR);
+    assert(fd > 2);
+    assert(write(fd, image, sizeof(image)) == sizeof(image));
+    close(fd);
+
+    int pid = exec(args[0], args);
+    if (pid == -1) return -1;
+    assert(pid > 0, "%s should be refused", what);
+    return wait(pid);
+}
+
+void main() {
+    build();
+    assert(run("well-formed") == 42);
+
+    build();
+    image[0] = 0;
+    assert(run("bad magic") == -1);
+
+    build();
+    ehdr()->machine = 0x3e;
+    assert(run("other machine") == -1);
+
+    build();
+    phdr(0)->filesz = 0x400;
+    assert(run("filesz > memsz") == -1);
+
+    build();
+    phdr(0)->filesz = phdr(0)->memsz = 0x400;
+    assert(run("past the end of file") == -1);
+
+    build();
+    phdr(0)->vaddr = 0xffffffc080200000;
+    ehdr()->entry = phdr(0)->vaddr;
+    assert(run("kernel address") == -1);
+
+    build();
+    phdr(0)->offset = CODE_OFF + 4;
+    assert(run("misaligned offset") == -1);
+
+    build();
+    phdr(0)->flags = PF_R | PF_W | PF_X;
+    assert(run("W+X") == -1);
+
+    build();
+    phdr(0)->flags = PF_R;
+    assert(run("entry not executable") == -1);
+
+    build();
+    ehdr()->entry = BASE + 0x1000;
+    assert(run("entry outside segments") == -1);
+
+    build();
+    ehdr()->phnum = 2;
+    *phdr(1) = *phdr(0);
+    phdr(1)->flags = PF_R | PF_W;
+    phdr(1)->vaddr += 0x100;
+    phdr(1)->offset += 0x100;
+    phdr(1)->filesz = 0;
+    assert(run("overlapping segments") == -1);
+
+    assert(remove("elf-image") == 0);
+}
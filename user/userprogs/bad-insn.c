@@ -0,0 +1,9 @@
+/** Executes an illegal instruction, which must terminate the
+   process with the exit status of SIGILL, but not the kernel. */
+
+#include "user.h"
+
+void main() {
+    asm volatile(".word 0");
+    panic("should have exited");
+}
@@ -1,7 +1,7 @@
 /** Child process run by wait-killed test.
    Sets the stack pointer (%esp) to an invalid value and invokes
-   a system call, which should then terminate the process with a
-   -1 exit code. */
+   a system call, which should then terminate the process with the
+   exit status of SIGSEGV. */
 
 #include "user.h"
 
@@ -0,0 +1,50 @@
+/** Passes syscalls buffers and strings crossing page boundaries, into pages not
+   allocated yet, and into pages the kernel must not access. The kernel copies
+   what it can reach, and fails the others without killing the process. */
+
+#include "sample.inc"
+#include "user.h"
+
+#define PG_SIZE 4096
+#define FLAGS (MAP_ANONYMOUS | MAP_PRIVATE)
+
+void main() {
+    int fd, len = sizeof(sample) - 1;
+    char* p = mmap_anon(NULL, 3 * PG_SIZE, PROT_READ | PROT_WRITE, FLAGS);
+    assert(p != (void*)-1);
+
+    // Pages not allocated yet are allocated in the middle of a copy.
+    char* buf = p + PG_SIZE - 10;
+    assert((fd = open("sample.txt", O_RDONLY)) > 2);
+    assert(read(fd, buf, len) == len);
+    assert(memcmp(buf, sample, len) == 0);
+
+    stat st;
+    stat* fresh = (stat*)(p + 2 * PG_SIZE);
+    assert(fstat(fd, fresh) == 0);
+    assert(fstat(fd, &st) == 0 && fresh->size == st.size && st.size == len);
+    close(fd);
+
+    // The file name runs across a page boundary.
+    char* name = p + 2 * PG_SIZE - 5;
+    strcpy(name, "sample.txt");
+    assert((fd = open(name, O_RDONLY)) > 2);
+    close(fd);
+
+    // The last page can't be read, nor written.
+    assert(mprotect(p + 2 * PG_SIZE, PG_SIZE, PROT_NONE) == 0);
+    assert(open(name, O_RDONLY) == -1);
+    assert(write(1, p + 2 * PG_SIZE - 4, 8) == -1);
+    assert((fd = open("sample.txt", O_RDONLY)) > 2);
+    assert(read(fd, p + 2 * PG_SIZE - 4, 8) == -1);
+    assert(fstat(fd, (stat*)(p + 2 * PG_SIZE)) == -1);
+
+    // A read-only page can't be written.
+    assert(mprotect(p + 2 * PG_SIZE, PG_SIZE, PROT_READ) == 0);
+    assert(read(fd, p + 2 * PG_SIZE, 8) == -1);
+    close(fd);
+
+    // Kernel addresses are refused before any access.
+    assert(write(1, (void*)0xffffffc080200000UL, 8) == -1);
+    assert(open((char*)0xffffffc080200000UL, O_RDONLY) == -1);
+}
@@ -0,0 +1,54 @@
+/** Passes environment variables through execve(), checks the auxiliary
+   vector after them, and makes sure that too long arguments are refused. */
+
+#include "user.h"
+
+#define AT_NULL 0
+#define AT_PAGESZ 6
+#define AT_ENTRY 9
+#define AT_RANDOM 25
+
+static char huge[8192];
+
+void main(int argc, char* argv[], char* envp[]) {
+    if (argc > 1) {
+        assert(strcmp(argv[1], "child") == 0);
+        assert(argv[argc] == NULL);
+        assert(envp == environ);
+        assert(strcmp(getenv("GREETING"), "hello") == 0);
+        assert(strcmp(getenv("EMPTY"), "") == 0);
+        assert(getenv("GREET") == NULL);
+
+        char** env = envp;
+        while (*env) env++;
+        uint64* auxv = (uint64*)(env + 1);
+        int found = 0;
+        for (; auxv[0] != AT_NULL; auxv += 2) {
+            if (auxv[0] == AT_PAGESZ) {
+                assert(auxv[1] == 4096);
+                found++;
+            }
+            if (auxv[0] == AT_ENTRY) {
+                assert(auxv[1] != 0);
+                found++;
+            }
+            if (auxv[0] == AT_RANDOM) {
+                assert(auxv[1] > (uint64)envp);
+                found++;
+            }
+        }
+        assert(found == 3);
+        exit(0);
+    }
+
+    const char* args[] = {"exec-env", "child", NULL};
+    const char* envs[] = {"GREETING=hello", "EMPTY=", NULL};
+    int pid = execve(args[0], args, envs);
+    assert(pid > 0);
+    assert(wait(pid) == 0);
+
+    memset(huge, 'x', sizeof(huge) - 1);
+    const char* huge_args[] = {"exec-env", huge, NULL};
+    assert(exec(huge_args[0], huge_args) == ARGUMENT_TOO_LONG);
+    assert(execve(args[0], args, huge_args) == ARGUMENT_TOO_LONG);
+}
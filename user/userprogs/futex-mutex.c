@@ -0,0 +1,61 @@
+/** Builds a mutex out of futexes, and uses it to protect a
+   counter shared by several threads. Also checks that a wait
+   returns at once on a mismatch, and returns after a timeout. */
+
+#include "user.h"
+
+#define WORKERS 4
+#define ROUNDS 1000
+
+/* 0: unlocked, 1: locked, 2: locked with waiters. */
+static int lock;
+static int counter;
+
+static void acquire() {
+    int c = __sync_val_compare_and_swap(&lock, 0, 1);
+    if (c == 0) return;
+
+    if (c != 2) c = __sync_lock_test_and_set(&lock, 2);
+    while (c != 0) {
+        futex_wait(&lock, 2, -1);
+        c = __sync_lock_test_and_set(&lock, 2);
+    }
+}
+
+static void release() {
+    if (__sync_fetch_and_sub(&lock, 1) != 1) {
+        lock = 0;
+        futex_wake(&lock, 1);
+    }
+}
+
+void worker(void* arg) {
+    for (int i = 0; i < ROUNDS; i++) {
+        acquire();
+        int c = counter;
+        /* Widen the window, so that others do contend for the lock. */
+        for (volatile int j = 0; j < 100; j++)
+            ;
+        counter = c + 1;
+        release();
+    }
+
+    thread_exit(0);
+}
+
+void main() {
+    int word = 0;
+    int tids[WORKERS];
+
+    assert(futex_wait(&word, 1, -1) == -1);
+    assert(futex_wait(&word, 0, 2) == -2);
+    assert(futex_wake(&word, 1) == 0);
+
+    for (int i = 0; i < WORKERS; i++) {
+        tids[i] = thread_create(worker, NULL);
+        assert(tids[i] != -1);
+    }
+    for (int i = 0; i < WORKERS; i++) assert(thread_join(tids[i]) == 0);
+
+    assert(counter == WORKERS * ROUNDS);
+}
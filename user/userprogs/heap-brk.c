@@ -0,0 +1,37 @@
+/** Grows and shrinks the heap with sbrk() and brk(), checks that new heap
+   pages are zero-filled, and that the kernel accepts heap buffers which have
+   not been touched yet. */
+
+#include "user.h"
+
+#define PAGES 8
+#define PG_SIZE 4096
+
+void main() {
+    char* start = sbrk(0);
+    assert(start != (char*)-1);
+    assert(brk(start - 1) == -1, "the heap can't go below the executable");
+
+    char* base = (char*)ROUND_UP(start, PG_SIZE);
+    assert(sbrk(base + PAGES * PG_SIZE - start) == start);
+    assert(sbrk(0) == base + PAGES * PG_SIZE);
+
+    for (int i = 0; i < PAGES * PG_SIZE; i++) assert(base[i] == 0);
+    memset(base, 0x5a, PAGES * PG_SIZE);
+
+    // Shrink it, then grow it again. The pages come back zero-filled.
+    assert(brk(base + PG_SIZE) == 0);
+    assert(base[PG_SIZE - 1] == 0x5a);
+    assert(sbrk(PG_SIZE) == base + PG_SIZE);
+    for (int i = PG_SIZE; i < 2 * PG_SIZE; i++) assert(base[i] == 0);
+
+    // The kernel writes to a heap page never touched by the user.
+    char* buf = sbrk(PG_SIZE);
+    int fd = open("sample.txt", O_RDONLY);
+    assert(fd > 2);
+    assert(read(fd, buf, 16) == 16);
+    close(fd);
+
+    assert(brk((void*)0x80000000) == -1, "the heap can't run into user stacks");
+    assert(brk(start) == 0);
+}
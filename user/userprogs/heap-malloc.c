@@ -0,0 +1,41 @@
+/** Allocates blocks of various sizes with malloc(), checks that they don't
+   overlap, and that freed memory is reused. */
+
+#include "user.h"
+
+#define BLOCKS 64
+
+static char* blocks[BLOCKS];
+
+static size_t size_of(int i) { return 1 + i * 37 % 600; }
+
+void main() {
+    for (int i = 0; i < BLOCKS; i++) {
+        blocks[i] = malloc(size_of(i));
+        assert(blocks[i] != NULL);
+        assert((uint64)blocks[i] % 16 == 0);
+        memset(blocks[i], i, size_of(i));
+    }
+    for (int i = 0; i < BLOCKS; i++)
+        for (int j = 0; j < size_of(i); j++) assert(blocks[i][j] == (char)i);
+
+    // Free every other block, and allocate them again.
+    for (int i = 0; i < BLOCKS; i += 2) free(blocks[i]);
+    for (int i = 0; i < BLOCKS; i += 2) {
+        blocks[i] = malloc(size_of(i));
+        memset(blocks[i], i, size_of(i));
+    }
+    for (int i = 0; i < BLOCKS; i++)
+        for (int j = 0; j < size_of(i); j++) assert(blocks[i][j] == (char)i);
+
+    // Once everything is freed, a large block fits without growing the heap.
+    for (int i = 0; i < BLOCKS; i++) free(blocks[i]);
+    char* end = sbrk(0);
+    char* large = malloc(8192);
+    assert(large != NULL);
+    assert(sbrk(0) == end);
+    free(large);
+
+    assert(malloc(0) == NULL);
+    assert(malloc(1L << 40) == NULL);
+}
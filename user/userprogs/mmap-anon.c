@@ -0,0 +1,79 @@
+/** Maps anonymous memory, changes permissions of parts of it, and unmaps a
+   hole in the middle. Accesses that the permissions deny kill a child. */
+
+#include "user.h"
+
+#define PG_SIZE 4096
+#define PAGES 4
+#define FLAGS (MAP_ANONYMOUS | MAP_PRIVATE)
+
+// Runs this program with `arg`, and returns the exit status.
+static int run(const char* arg) {
+    const char* args[] = {"mmap-anon", arg, NULL};
+    int pid = exec(args[0], args);
+    assert(pid > 0);
+    return wait(pid);
+}
+
+static void child(const char* what) {
+    char* p = mmap_anon(NULL, PG_SIZE, PROT_READ | PROT_WRITE, FLAGS);
+    assert(p != (void*)-1);
+    p[0] = 1;
+
+    if (strcmp(what, "write-ro") == 0) {
+        assert(mprotect(p, PG_SIZE, PROT_READ) == 0);
+        assert(p[0] == 1);
+        p[0] = 2;
+    } else if (strcmp(what, "read-none") == 0) {
+        assert(mprotect(p, PG_SIZE, PROT_NONE) == 0);
+        printf("%d\n", p[0]);
+    } else if (strcmp(what, "unmapped") == 0) {
+        assert(munmap_range(p, PG_SIZE) == 0);
+        printf("%d\n", p[0]);
+    }
+    exit(0);
+}
+
+void main(int argc, char* argv[]) {
+    if (argc > 1) child(argv[1]);
+
+    assert(mmap_anon(NULL, PG_SIZE, PROT_READ, MAP_PRIVATE) == (void*)-1);
+    assert(mmap_anon(NULL, 0, PROT_READ, FLAGS) == (void*)-1);
+    assert(mmap_anon((void*)1, PG_SIZE, PROT_READ, FLAGS) == (void*)-1);
+
+    char* p = mmap_anon(NULL, PAGES * PG_SIZE, PROT_READ | PROT_WRITE, FLAGS);
+    assert(p != (void*)-1);
+    assert((uint64)p % PG_SIZE == 0);
+    assert(p >= (char*)sbrk(0));
+    for (int i = 0; i < PAGES * PG_SIZE; i++) assert(p[i] == 0);
+    memset(p, 0x33, PAGES * PG_SIZE);
+
+    // A read-only page in the middle, and then no permission at all. The
+    // contents survive both.
+    assert(mprotect(p + PG_SIZE, PG_SIZE, PROT_READ) == 0);
+    assert(p[PG_SIZE] == 0x33);
+    assert(mprotect(p + PG_SIZE, PG_SIZE, PROT_NONE) == 0);
+    assert(mprotect(p + PG_SIZE, PG_SIZE, PROT_READ | PROT_WRITE) == 0);
+    assert(p[PG_SIZE] == 0x33);
+    p[PG_SIZE] = 0x44;
+
+    // A hole in the middle splits the mapping.
+    assert(munmap_range(p + 2 * PG_SIZE, PG_SIZE) == 0);
+    assert(mprotect(p, PAGES * PG_SIZE, PROT_READ) == -1);
+    assert(p[3 * PG_SIZE] == 0x33);
+
+    // The kernel fills a page never touched by the user.
+    char* buf = mmap_anon(NULL, PG_SIZE, PROT_READ | PROT_WRITE, FLAGS);
+    int fd = open("sample.txt", O_RDONLY);
+    assert(fd > 2);
+    assert(read(fd, buf, 16) == 16);
+    close(fd);
+
+    assert(munmap_range(p, PAGES * PG_SIZE) == 0);
+    assert(munmap_range(buf, PG_SIZE) == 0);
+    assert(munmap_range(p + 1, PG_SIZE) == -1);
+
+    assert(run("write-ro") == KILLED(SIGSEGV));
+    assert(run("read-none") == KILLED(SIGSEGV));
+    assert(run("unmapped") == KILLED(SIGSEGV));
+}
@@ -0,0 +1,28 @@
+/** A child writes more memory than the user pool and swap hold. Once nothing
+   can be evicted, the kernel kills the process holding the most memory, which is
+   the child, and its memory and swap become available again. */
+
+#include "user.h"
+
+#define PG_SIZE 4096
+
+void main(int argc, char* argv[]) {
+    if (argc > 1) {
+        // The heap can outgrow any user pool and swap.
+        for (;;) {
+            char* p = sbrk(PG_SIZE);
+            assert(p != (char*)-1);
+            *p = 1;
+        }
+    }
+
+    const char* args[] = {"oom-kill", "hog", 0};
+    int child;
+    assert((child = exec(args[0], args)) >= 0);
+    assert(wait(child) == KILLED(SIGKILL), "the child is killed");
+
+    // The memory of the child is free again.
+    char* p = sbrk(64 * PG_SIZE);
+    assert(p != (char*)-1);
+    for (int i = 0; i < 64 * PG_SIZE; i += PG_SIZE) p[i] = 1;
+}
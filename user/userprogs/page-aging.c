@@ -0,0 +1,3 @@
+/* Pages under the aging policy, see page-evict.inc. */
+
+#include "page-evict.inc"
@@ -0,0 +1,3 @@
+/* Pages under the clock policy, see page-evict.inc. */
+
+#include "page-evict.inc"
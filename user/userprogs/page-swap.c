@@ -0,0 +1,37 @@
+/* Writes far more heap pages than the user pool holds, so that they are evicted
+   to swap, and reads them back twice. An area is evicted as well, and keeps its
+   content through permission changes made while it's in swap.
+
+   Run with a small pool, e.g. `userpool=64`. */
+
+#include "user.h"
+
+#define PG_SIZE 4096
+#define PRESSURE 256
+#define WRITTEN 8
+#define FLAGS (MAP_ANONYMOUS | MAP_PRIVATE)
+
+static char pattern(int page, int i) { return (char)(page * 31 + i * 7 + 1); }
+
+void main() {
+    char* w = mmap_anon(NULL, WRITTEN * PG_SIZE, PROT_READ | PROT_WRITE, FLAGS);
+    assert(w != (void*)-1);
+    for (int p = 0; p < WRITTEN; p++)
+        for (int i = 0; i < PG_SIZE; i += 64) w[p * PG_SIZE + i] = pattern(p, i);
+
+    char* heap = sbrk(PRESSURE * PG_SIZE);
+    assert(heap != (char*)-1);
+    for (int p = 0; p < PRESSURE; p++) heap[p * PG_SIZE] = pattern(p, 0);
+
+    // The area has been evicted by now.
+    assert(mprotect(w, WRITTEN / 2 * PG_SIZE, PROT_NONE) == 0);
+    assert(mprotect(w, WRITTEN * PG_SIZE, PROT_READ) == 0);
+
+    for (int round = 0; round < 2; round++)
+        for (int p = 0; p < PRESSURE; p++)
+            assert(heap[p * PG_SIZE] == pattern(p, 0), "page %d in round %d", p, round);
+
+    for (int p = 0; p < WRITTEN; p++)
+        for (int i = 0; i < PG_SIZE; i += 64)
+            assert(w[p * PG_SIZE + i] == pattern(p, i), "written page %d", p);
+}
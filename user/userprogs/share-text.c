@@ -0,0 +1,41 @@
+/** Runs several copies of itself at once, whose read-only pages are shared.
+   Each copy sums up its own code, and must see the same bytes as the parent.
+   Once they all exit, the executable of the children is writable again. */
+
+#include "user.h"
+
+#define CHILDREN 4
+
+static int checksum(void) {
+    const unsigned char* code = (const unsigned char*)checksum;
+    int sum = 0;
+    for (int i = 0; i < 256; i++)
+        sum = (sum * 31 + code[i]) & 0x7f;
+    return sum;
+}
+
+void main(int argc, char* argv[]) {
+    if (argc > 1) {
+        exit(checksum());
+    }
+
+    const char* args[] = {"share-text", "child", 0};
+    int children[CHILDREN];
+    for (int i = 0; i < CHILDREN; i++)
+        assert((children[i] = exec(args[0], args)) >= 0);
+    for (int i = 0; i < CHILDREN; i++)
+        assert(wait(children[i]) == checksum(), "child %d sees the same code", i);
+
+    // No process runs child-rox, so none of its pages may be cached.
+    const char* rox[] = {"child-rox", "2", 0};
+    int fd, child;
+    char buffer[16];
+    assert((child = exec(rox[0], rox)) >= 0);
+    assert(wait(child) == 12);
+
+    assert((fd = open("child-rox", O_RDWR)) > 2);
+    assert(read(fd, buffer, sizeof buffer) == (int)sizeof buffer);
+    seek(fd, 0);
+    assert(write(fd, buffer, sizeof buffer) == (int)sizeof buffer);
+    close(fd);
+}
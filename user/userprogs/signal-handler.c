@@ -0,0 +1,62 @@
+/** Catches signals in a child process. A fault enters the
+   SIGSEGV handler, and a signal sent by kill() interrupts a
+   loop, which resumes with its registers intact after the
+   handler returns. */
+
+#include "user.h"
+
+static volatile int caught;
+
+void on_segv(int sig) {
+    assert(sig == SIGSEGV);
+    exit(81);
+}
+
+void on_usr1(int sig) {
+    assert(sig == SIGUSR1);
+    caught++;
+}
+
+void fault() {
+    assert(signal(SIGSEGV, on_segv) == SIG_DFL);
+    *(volatile int*)NULL = 42;
+    panic("should have faulted");
+}
+
+void interrupted() {
+    assert(signal(SIGKILL, on_usr1) == SIG_ERR);
+    assert(signal(SIGUSR1, on_usr1) == SIG_DFL);
+
+    /* Tell the parent that the handler is ready. */
+    int fd = open("signal-ready", O_CREATE | O_WRONLY);
+    assert(fd > 2);
+    close(fd);
+
+    register int n = 0;
+    while (!caught) n++;
+
+    assert(caught == 1);
+    assert(n > 0);
+    exit(82);
+}
+
+void main(int argc, char* argv[]) {
+    if (argc > 1 && strcmp(argv[1], "fault") == 0) fault();
+    if (argc > 1 && strcmp(argv[1], "interrupted") == 0) interrupted();
+
+    const char* fault_args[] = {"signal-handler", "fault", NULL};
+    assert(wait(exec(fault_args[0], fault_args)) == 81);
+
+    const char* interrupted_args[] = {"signal-handler", "interrupted", NULL};
+    int pid = exec(interrupted_args[0], interrupted_args);
+    assert(pid > 0);
+
+    int fd;
+    while ((fd = open("signal-ready", O_RDONLY)) < 0)
+        ;
+    close(fd);
+    remove("signal-ready");
+
+    assert(kill(pid, SIGUSR1) == 0);
+    assert(wait(pid) == 82);
+}
@@ -0,0 +1,30 @@
+/** Terminates a spinning child, first by a signal with the
+   default action, and then by SIGKILL, which can't be ignored. */
+
+#include "user.h"
+
+void main(int argc, char* argv[]) {
+    if (argc > 1) {
+        if (strcmp(argv[1], "ignore") == 0) {
+            assert(signal(SIGTERM, SIG_IGN) == SIG_DFL);
+            assert(signal(SIGKILL, SIG_IGN) == SIG_ERR);
+        }
+        for (;;)
+            ;
+    }
+
+    const char* dfl_args[] = {"signal-kill", "default", NULL};
+    int pid = exec(dfl_args[0], dfl_args);
+    assert(pid > 0);
+    assert(kill(pid, SIGTERM) == 0);
+    assert(wait(pid) == KILLED(SIGTERM));
+    assert(kill(pid, SIGTERM) == -1);
+
+    const char* ign_args[] = {"signal-kill", "ignore", NULL};
+    pid = exec(ign_args[0], ign_args);
+    assert(pid > 0);
+    assert(kill(pid, 0) == 0);
+    assert(kill(pid, 64) == -1);
+    assert(kill(pid, SIGKILL) == 0);
+    assert(wait(pid) == KILLED(SIGKILL));
+}
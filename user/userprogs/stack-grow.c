@@ -0,0 +1,100 @@
+/** Grows the stack by large frames, deep recursion, loads and pushes just below
+   sp. Accesses far below sp, or beyond the stack limit, kill a child. The limit
+   is inherited by exec(). */
+
+#include "user.h"
+
+#define PG_SIZE 4096
+#define MiB (1024 * 1024)
+
+// Runs this program with `arg`, and returns the exit status.
+static int run(const char* arg) {
+    const char* args[] = {"stack-grow", arg, NULL};
+    int pid = exec(args[0], args);
+    assert(pid > 0);
+    return wait(pid);
+}
+
+// Uses about `n` KiB of stack.
+static int recurse(int n) {
+    volatile char frame[1000];
+    frame[0] = 1;
+    if (n == 0) return 0;
+    return recurse(n - 1) + frame[0] - 1;
+}
+
+static int big_frame(void) {
+    volatile char buf[64 * 1024];
+    volatile char unused[16 * 1024];
+    memset((void*)buf, 0x5a, sizeof(buf));
+    // Loads grow the stack too, and see zeros.
+    return buf[sizeof(buf) / 2] + unused[0];
+}
+
+// Stores 16 bytes below sp, with sp moved to the start of a page that's not
+// mapped yet.
+static void push(void) {
+    asm volatile(
+        "mv t0, sp\n"
+        "li t1, -4096\n"
+        "and sp, sp, t1\n"
+        "li t1, 64 * 4096\n"
+        "sub sp, sp, t1\n"
+        "sd t0, -16(sp)\n"
+        "ld t1, -16(sp)\n"
+        "mv sp, t0\n"
+        :
+        :
+        : "t0", "t1", "memory");
+}
+
+// Loads from two pages below sp.
+static void far(void) {
+    asm volatile(
+        "li t0, -2 * 4096\n"
+        "add t0, sp, t0\n"
+        "ld t0, 0(t0)\n"
+        :
+        :
+        : "t0", "memory");
+}
+
+static void child(const char* what) {
+    struct rlimit rlim;
+    if (strcmp(what, "push") == 0) {
+        push();
+    } else if (strcmp(what, "far") == 0) {
+        far();
+    } else if (strcmp(what, "limit") == 0) {
+        rlim.rlim_cur = 64 * 1024;
+        rlim.rlim_max = 8 * MiB;
+        assert(setrlimit(RLIMIT_STACK, &rlim) == 0);
+        recurse(256);
+    } else if (strcmp(what, "inherit") == 0) {
+        assert(getrlimit(RLIMIT_STACK, &rlim) == 0);
+        assert(rlim.rlim_cur == 64 * 1024);
+    }
+    exit(0);
+}
+
+void main(int argc, char* argv[]) {
+    if (argc > 1) child(argv[1]);
+
+    struct rlimit rlim;
+    assert(getrlimit(RLIMIT_STACK, &rlim) == 0);
+    assert(rlim.rlim_cur == 8 * MiB && rlim.rlim_max == 8 * MiB);
+    assert(getrlimit(0, &rlim) == -1);
+
+    assert(big_frame() == 0x5a);
+    assert(recurse(2 * 1024) == 0);
+
+    assert(run("push") == 0);
+    assert(run("far") == KILLED(SIGSEGV), "a load far below sp should kill");
+    assert(run("limit") == KILLED(SIGSEGV), "the stack should not grow beyond its limit");
+
+    rlim.rlim_cur = 16 * MiB;
+    assert(setrlimit(RLIMIT_STACK, &rlim) == -1);
+    rlim.rlim_cur = 64 * 1024;
+    assert(setrlimit(RLIMIT_STACK, &rlim) == 0);
+    assert(run("inherit") == 0);
+}
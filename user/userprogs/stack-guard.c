@@ -0,0 +1,39 @@
+/** A store right at the stack pointer below the stack usually grows the stack,
+   but not into the guard page at the bottom of the stack slot. The child doing
+   so is killed. Mappings that are both writable and executable are refused. */
+
+#include "user.h"
+
+#define PG_SIZE 4096
+#define FLAGS (MAP_ANONYMOUS | MAP_PRIVATE)
+// The guard page of the first thread's stack, which ends at 0x80500000 and
+// takes 8 MiB of address space, plus the guard page.
+#define GUARD 0x7fcff000UL
+
+static void child(void) {
+    asm volatile(
+        "mv t0, sp\n"
+        "mv sp, %0\n"
+        "sd zero, 0(sp)\n"
+        "mv sp, t0\n"
+        :
+        : "r"(GUARD + 8)
+        : "t0", "memory");
+    exit(0);
+}
+
+void main(int argc, char* argv[]) {
+    if (argc > 1) child();
+
+    const char* args[] = {"stack-guard", "child", NULL};
+    int pid = exec(args[0], args);
+    assert(pid > 0);
+    assert(wait(pid) == KILLED(SIGSEGV), "the guard page should never be mapped");
+
+    assert(mmap_anon(NULL, PG_SIZE, PROT_WRITE | PROT_EXEC, FLAGS) == (void*)-1);
+    char* p = mmap_anon(NULL, PG_SIZE, PROT_READ | PROT_WRITE, FLAGS);
+    assert(p != (void*)-1);
+    assert(mprotect(p, PG_SIZE, PROT_READ | PROT_WRITE | PROT_EXEC) == -1);
+    assert(mprotect(p, PG_SIZE, PROT_READ | PROT_EXEC) == 0);
+    assert(munmap_range(p, PG_SIZE) == 0);
+}
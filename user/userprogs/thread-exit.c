@@ -0,0 +1,18 @@
+/** Exits the process while other threads are still running.
+   All of them must be terminated, and the exit status is
+   the one passed to exit(). */
+
+#include "user.h"
+
+#define WORKERS 4
+
+void spin(void* arg) {
+    for (;;)
+        ;
+}
+
+void main() {
+    for (int i = 0; i < WORKERS; i++) assert(thread_create(spin, NULL) != -1);
+
+    exit(NORMAL_EXIT);
+}
@@ -0,0 +1,41 @@
+/** Sums up an array with parallel workers in one process.
+   Each worker runs on its own stack, and reports its index
+   through thread_exit(). */
+
+#include "user.h"
+
+#define WORKERS 4
+#define N 4096
+
+static int array[N];
+static int sums[WORKERS];
+
+void worker(void* arg) {
+    int id = (int)(uint64)arg;
+    int sum = 0;
+
+    for (int i = id; i < N; i += WORKERS) sum += array[i];
+    sums[id] = sum;
+
+    thread_exit(id);
+}
+
+void main() {
+    int tids[WORKERS];
+    int total = 0;
+
+    for (int i = 0; i < N; i++) array[i] = i;
+
+    for (int i = 0; i < WORKERS; i++) {
+        tids[i] = thread_create(worker, (void*)(uint64)i);
+        assert(tids[i] != -1);
+    }
+
+    for (int i = 0; i < WORKERS; i++) {
+        assert(thread_join(tids[i]) == i);
+        total += sums[i];
+    }
+
+    assert(total == N * (N - 1) / 2);
+    assert(thread_join(tids[0]) == -1);
+}
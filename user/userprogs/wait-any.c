@@ -0,0 +1,55 @@
+/** Waits for children in any order with waitpid(-1), polls a running child
+   with WNOHANG, and leaves an orphan behind, which the kernel reaps. */
+
+#include "user.h"
+
+#define CHILDREN 3
+
+void main(int argc, char* argv[]) {
+    if (argc > 1) {
+        if (strcmp(argv[1], "spin") == 0)
+            for (;;)
+                ;
+        if (strcmp(argv[1], "linger") == 0)
+            for (volatile int i = 0; i < 10000000; i++)
+                ;
+        if (strcmp(argv[1], "orphan") == 0) {
+            // The grandchild outlives this process.
+            const char* args[] = {"wait-any", "linger", NULL};
+            assert(exec(args[0], args) > 0);
+        }
+        exit(atoi(argv[1]));
+    }
+
+    int status, mask = 0;
+    for (int i = 1; i <= CHILDREN; i++) {
+        char arg[4];
+        itoa(arg, i);
+        const char* args[] = {"wait-any", arg, NULL};
+        assert(exec(args[0], args) > 0);
+    }
+    for (int i = 0; i < CHILDREN; i++) {
+        assert(waitpid(-1, &status, 0) > 0);
+        assert(status >= 1 && status <= CHILDREN);
+        mask |= 1 << status;
+    }
+    assert(mask == 0b1110);
+    assert(waitpid(-1, &status, 0) == -1);
+    assert(waitpid(-1, NULL, WNOHANG) == -1);
+
+    const char* spin_args[] = {"wait-any", "spin", NULL};
+    int pid = exec(spin_args[0], spin_args);
+    assert(pid > 0);
+    assert(waitpid(pid, &status, WNOHANG) == 0);
+    assert(waitpid(-1, &status, WNOHANG) == 0);
+    assert(kill(pid, SIGKILL) == 0);
+    assert(waitpid(-1, &status, 0) == pid);
+    assert(status == KILLED(SIGKILL));
+
+    const char* orphan_args[] = {"wait-any", "orphan", NULL};
+    pid = exec(orphan_args[0], orphan_args);
+    assert(pid > 0);
+    assert(waitpid(pid, &status, 0) == pid);
+    assert(status == 0);
+    assert(waitpid(-1, NULL, WNOHANG) == -1);
+}
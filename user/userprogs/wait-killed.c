@@ -9,5 +9,5 @@ void main() {
     const char* args[] = {"child-bad", 0};
 
     assert((pid = exec(args[0], args)) >= 0);
-    assert(wait(pid) == -1, "child-bad should exit with a non-zero code");
+    assert(wait(pid) == KILLED(SIGSEGV), "child-bad should be killed by SIGSEGV");
 }